@@ -0,0 +1,89 @@
+/*
+ * C interface of beesting, implemented in src/ffi.rs. Link against the cdylib or staticlib
+ * that `cargo build` puts in target/<profile>.
+ *
+ * Interpreters and values are owned by the caller and freed with bst_interpreter_free and
+ * bst_value_free. An interpreter and its values must only be used on the thread that created
+ * the interpreter.
+ */
+#ifndef BEESTING_H
+#define BEESTING_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Interpreter BstInterpreter;
+typedef struct BstValue BstValue;
+
+/* Types returned by bst_value_type */
+#define BST_NIL 0
+#define BST_BOOLEAN 1
+#define BST_INTEGER 2
+#define BST_STRING 3
+#define BST_LIST 4
+/* Any other value, e.g. a function, which can only be printed */
+#define BST_OTHER 5
+/* Failed evaluation, bst_value_error returns the message */
+#define BST_ERROR 6
+#define BST_FLOAT 7
+
+/*
+ * Called when a builtin registered with bst_register is called. The arguments are only valid
+ * during the call. Returns a new value, or one made with bst_value_new_error to fail the call;
+ * the interpreter frees it. Returning NULL returns nil.
+ */
+typedef BstValue *(*BstCallback)(void *user_data, const BstValue *const *args, size_t argc);
+
+BstInterpreter *bst_interpreter_new(void);
+void bst_interpreter_free(BstInterpreter *interpreter);
+
+/*
+ * Evaluation recurses on the calling thread's stack, so threads with small stacks should lower
+ * the call depth limit to fail with an error instead of overflowing.
+ */
+void bst_set_max_call_depth(size_t depth);
+
+/* Evaluates all forms of the code and returns the value of the last one, or an error value */
+BstValue *bst_eval(BstInterpreter *interpreter, const char *code);
+
+/* Binds name to a builtin calling callback with user_data. Returns 0, or -1 on invalid input. */
+int bst_register(BstInterpreter *interpreter, const char *name, BstCallback callback,
+                 void *user_data);
+
+void bst_value_free(BstValue *value);
+int bst_value_type(const BstValue *value);
+/* Stores an integer or boolean (as 0 or 1) in out and returns 0, or returns -1 otherwise */
+int bst_value_int(const BstValue *value, int64_t *out);
+/* Stores a float or integer (converted) in out and returns 0, or returns -1 otherwise */
+int bst_value_float(const BstValue *value, double *out);
+/* Text of a string value, valid until the value is freed; NULL for other values */
+const char *bst_value_string(const BstValue *value);
+/* Message of an error value, valid until the value is freed; NULL for other values */
+const char *bst_value_error(const BstValue *value);
+/* Number of elements of a list, 0 for other values */
+size_t bst_value_list_len(const BstValue *value);
+/* New value holding an element of a list, or NULL if there is no such element */
+BstValue *bst_value_list_get(const BstValue *value, size_t index);
+/* The value as the REPL prints it readably, to be freed with bst_string_free */
+char *bst_value_print(const BstValue *value);
+void bst_string_free(char *s);
+
+BstValue *bst_value_new_nil(void);
+BstValue *bst_value_new_int(int64_t n);
+BstValue *bst_value_new_float(double x);
+BstValue *bst_value_new_bool(int b);
+BstValue *bst_value_new_string(const char *s);
+/* New list of copies of the given values, which stay owned by the caller */
+BstValue *bst_value_new_list(const BstValue *const *items, size_t len);
+/* Error value, which makes a callback returning it fail with the message */
+BstValue *bst_value_new_error(const char *message);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,173 @@
+use crate::errors::RuntimeError;
+use crate::parser::{keyword_map, Ast};
+use crate::root_env::Environment;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Call depth used unless `set_max_call_depth` is called
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+/// Stack size sufficient for `DEFAULT_MAX_CALL_DEPTH` nested calls, even in debug builds
+pub const EVAL_STACK_SIZE: usize = 1 << 30;
+
+/// How many of the innermost calls a stack overflow error shows
+const OVERFLOW_CALLS_SHOWN: usize = 5;
+
+struct Frame {
+    form: Rc<Vec<Ast>>,
+    /// Environment holding the arguments, for calls of user functions
+    env: Option<Rc<RefCell<Environment>>>,
+}
+
+thread_local! {
+    /// The function calls currently being evaluated, outermost first
+    static CALL_STACK: RefCell<Vec<Frame>> = const { RefCell::new(vec![]) };
+    static MAX_CALL_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_CALL_DEPTH) };
+    /// Call stack at the point the error currently being propagated was raised
+    static ERROR_BACKTRACE: RefCell<Option<Vec<Rc<Vec<Ast>>>>> = const { RefCell::new(None) };
+}
+
+/// The part of the call stack belonging to one invocation of `eval`. A tail call replaces the
+/// frame of the call it is made from, like it replaces its evaluation; the frames are removed
+/// when the scope is dropped.
+pub struct CallScope {
+    base: usize,
+}
+
+impl CallScope {
+    pub fn new() -> CallScope {
+        CallScope {
+            base: CALL_STACK.with(|stack| stack.borrow().len()),
+        }
+    }
+
+    /// Records a call, failing with a stack overflow once there are too many calls in progress
+    pub fn enter(&self, form: &Rc<Vec<Ast>>) -> Result<(), RuntimeError> {
+        CALL_STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if stack.len() > self.base {
+                stack.truncate(self.base);
+            }
+
+            let max_depth = MAX_CALL_DEPTH.with(Cell::get);
+            if stack.len() >= max_depth {
+                let calls = stack
+                    .iter()
+                    .rev()
+                    .take(OVERFLOW_CALLS_SHOWN)
+                    .map(Frame::call)
+                    .collect();
+                return Err(RuntimeError::StackOverflow(max_depth, calls));
+            }
+
+            stack.push(Frame {
+                form: Rc::clone(form),
+                env: None,
+            });
+            Ok(())
+        })
+    }
+
+    /// Remembers the environment a user function was called with, whose slots are its arguments
+    pub fn bind_arguments(&self, env: &Rc<RefCell<Environment>>) {
+        CALL_STACK.with(|stack| {
+            if let Some(frame) = stack.borrow_mut().last_mut() {
+                frame.env = Some(Rc::clone(env));
+            }
+        });
+    }
+}
+
+impl Frame {
+    /// The call with the values of its arguments if they are known, e.g. `(fib -3)`
+    fn call(&self) -> Ast {
+        match &self.env {
+            Some(env) => {
+                let mut call = vec![Ast::Symbol(frame_name(&self.form))];
+                call.extend(env.borrow().slots.iter().cloned());
+                Ast::List(Rc::new(call))
+            }
+            None => Ast::List(Rc::clone(&self.form)),
+        }
+    }
+}
+
+impl Default for CallScope {
+    fn default() -> Self {
+        CallScope::new()
+    }
+}
+
+impl Drop for CallScope {
+    fn drop(&mut self) {
+        CALL_STACK.with(|stack| stack.borrow_mut().truncate(self.base));
+    }
+}
+
+/// Sets how many function calls may be in progress at once. Tail calls don't count, as they
+/// replace the call they are made from.
+///
+/// Every call uses up some of the native stack, so the thread evaluating deeply recursive code
+/// needs a stack large enough for this many calls; the `beesting` binary uses
+/// `EVAL_STACK_SIZE`.
+pub fn set_max_call_depth(depth: usize) {
+    MAX_CALL_DEPTH.with(|max| max.set(depth));
+}
+
+/// Number of function calls in progress
+pub fn stack_depth() -> usize {
+    CALL_STACK.with(|stack| stack.borrow().len())
+}
+
+/// The forms of the calls in progress, innermost first
+pub fn backtrace() -> Vec<Rc<Vec<Ast>>> {
+    CALL_STACK.with(|stack| {
+        stack
+            .borrow()
+            .iter()
+            .rev()
+            .map(|frame| Rc::clone(&frame.form))
+            .collect()
+    })
+}
+
+/// Remembers the call stack for the error that is being propagated. Called on every level the
+/// error passes through, so only the first, innermost call records anything.
+pub fn record_error() {
+    ERROR_BACKTRACE.with(|recorded| {
+        let mut recorded = recorded.borrow_mut();
+        if recorded.is_none() {
+            *recorded = Some(backtrace());
+        }
+    });
+}
+
+/// The call stack recorded for the last error, innermost first. Clears it, so the next error
+/// records its own.
+pub fn take_error_backtrace() -> Vec<Rc<Vec<Ast>>> {
+    ERROR_BACKTRACE
+        .with(|recorded| recorded.borrow_mut().take())
+        .unwrap_or_default()
+}
+
+/// Frames as hash-maps with the `:name` of the called function and the `:form` of the call
+pub fn frames_to_ast(frames: Vec<Rc<Vec<Ast>>>) -> Ast {
+    let frames = frames
+        .into_iter()
+        .map(|form| {
+            keyword_map(vec![
+                ("name", Ast::String(frame_name(&form))),
+                ("form", Ast::List(form)),
+            ])
+        })
+        .collect();
+    Ast::List(Rc::new(frames))
+}
+
+/// Name of the function called by a form, as written at the call site
+pub fn frame_name(form: &[Ast]) -> String {
+    match form.first() {
+        Some(Ast::Symbol(name)) | Some(Ast::Local(_, _, name)) => name.clone(),
+        _ => "<anonymous>".to_owned(),
+    }
+}
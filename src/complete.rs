@@ -0,0 +1,134 @@
+use crate::eval::SPECIAL_FORMS;
+use crate::parser::Ast;
+use crate::printer::pr_str;
+use crate::root_env::Environment;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    SpecialForm,
+    Builtin,
+    Function,
+    Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub name: String,
+    pub kind: CompletionKind,
+    /// Short description of the binding, currently the parameter list of user functions
+    pub doc: Option<String>,
+}
+
+/// Returns the special forms and the names bound in `env` or its parents that start with
+/// `prefix`, sorted by name. Where a name is bound in several frames the innermost binding wins.
+pub fn complete(prefix: &str, env: &Environment) -> Vec<Completion> {
+    search(env, |c| c.name.starts_with(prefix))
+}
+
+/// Like `complete`, but returns the bindings whose name or doc contains `pattern` anywhere
+pub fn apropos(pattern: &str, env: &Environment) -> Vec<Completion> {
+    search(env, |c| {
+        c.name.contains(pattern) || c.doc.as_ref().is_some_and(|doc| doc.contains(pattern))
+    })
+}
+
+/// The bound name or special form closest to a misspelled `name`, if any is close enough to be
+/// a likely typo. Swapping two neighbouring characters counts as a single edit.
+pub fn suggest(name: &str, env: &Environment) -> Option<String> {
+    let max_distance = name.chars().count() / 3;
+
+    search(env, |_| true)
+        .into_iter()
+        .map(|c| (edit_distance(name, &c.name), c.name))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// Optimal string alignment distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+fn search(env: &Environment, matches: impl Fn(&Completion) -> bool) -> Vec<Completion> {
+    let mut found = BTreeMap::new();
+    collect(env, &matches, &mut found);
+
+    for form in SPECIAL_FORMS {
+        let completion = Completion {
+            name: form.to_owned(),
+            kind: CompletionKind::SpecialForm,
+            doc: None,
+        };
+        if matches(&completion) {
+            found.entry(completion.name.clone()).or_insert(completion);
+        }
+    }
+
+    found.into_values().collect()
+}
+
+fn collect(
+    env: &Environment,
+    matches: &impl Fn(&Completion) -> bool,
+    found: &mut BTreeMap<String, Completion>,
+) {
+    let slots = env.slot_names.iter().zip(env.slots.iter()).rev();
+    for (name, value) in slots.chain(env.values.iter()) {
+        if found.contains_key(name) {
+            continue;
+        }
+
+        let completion = completion(name, value);
+        if matches(&completion) {
+            found.insert(name.clone(), completion);
+        }
+    }
+
+    if let Some(parent) = &env.parent {
+        collect(&parent.borrow(), matches, found);
+    }
+}
+
+fn completion(name: &str, value: &Ast) -> Completion {
+    let (kind, doc) = match value {
+        Ast::Builtin(..) => (CompletionKind::Builtin, None),
+        Ast::Closure(closure) => (CompletionKind::Function, Some(closure.name.clone())),
+        Ast::Function(f) => {
+            let params = f.written_params().into_iter().map(Ast::Symbol).collect();
+            let params = Ast::List(Rc::new(params));
+            (CompletionKind::Function, Some(pr_str(&params, false)))
+        }
+        _ => (CompletionKind::Value, None),
+    };
+
+    Completion {
+        name: name.to_owned(),
+        kind,
+        doc,
+    }
+}
@@ -0,0 +1,269 @@
+//! Conversion between Rust values and beesting values for embedders. Structs become association
+//! lists with string keys, like `(("name" "web") ("port" 8080))`; `record_conversion!` writes
+//! both directions for a struct whose fields can be converted.
+
+use crate::errors::RuntimeError;
+use crate::parser::Ast;
+use crate::root_env::assoc_lookup;
+use std::rc::Rc;
+
+pub trait ToAst {
+    fn to_ast(&self) -> Ast;
+}
+
+pub trait FromAst: Sized {
+    fn from_ast(ast: &Ast) -> Result<Self, RuntimeError>;
+}
+
+fn mismatch(expected: &str, got: &Ast) -> RuntimeError {
+    RuntimeError::Conversion(String::new(), expected.to_owned(), got.clone())
+}
+
+/// Prefixes the location inside a value to the path of a conversion error
+fn at(location: String, err: RuntimeError) -> RuntimeError {
+    match err {
+        RuntimeError::Conversion(path, expected, got) if path.is_empty() => {
+            RuntimeError::Conversion(location, expected, got)
+        }
+        RuntimeError::Conversion(path, expected, got) if path.starts_with('[') => {
+            RuntimeError::Conversion(location + &path, expected, got)
+        }
+        RuntimeError::Conversion(path, expected, got) => {
+            RuntimeError::Conversion(format!("{}.{}", location, path), expected, got)
+        }
+        other => other,
+    }
+}
+
+/// Key of a struct field: underscores become dashes, as usual for beesting names
+pub fn field_key(field: &str) -> String {
+    field.replace('_', "-")
+}
+
+impl ToAst for Ast {
+    fn to_ast(&self) -> Ast {
+        self.clone()
+    }
+}
+
+impl FromAst for Ast {
+    fn from_ast(ast: &Ast) -> Result<Self, RuntimeError> {
+        Ok(ast.clone())
+    }
+}
+
+impl ToAst for i64 {
+    fn to_ast(&self) -> Ast {
+        Ast::Integer(*self)
+    }
+}
+
+impl FromAst for i64 {
+    fn from_ast(ast: &Ast) -> Result<Self, RuntimeError> {
+        match ast {
+            Ast::Integer(n) => Ok(*n),
+            other => Err(mismatch("Integer", other)),
+        }
+    }
+}
+
+/// Integer types narrower than `i64`, which fail to convert when the value is out of range
+macro_rules! narrow_integer {
+    ($($t:ty),*) => {
+        $(
+            impl ToAst for $t {
+                fn to_ast(&self) -> Ast {
+                    Ast::Integer(*self as i64)
+                }
+            }
+
+            impl FromAst for $t {
+                fn from_ast(ast: &Ast) -> Result<Self, RuntimeError> {
+                    let n = i64::from_ast(ast)?;
+                    <$t>::try_from(n).map_err(|_| {
+                        mismatch(&format!("Integer in the range of {}", stringify!($t)), ast)
+                    })
+                }
+            }
+        )*
+    };
+}
+
+narrow_integer!(i32, u16, u32, usize);
+
+impl ToAst for f64 {
+    fn to_ast(&self) -> Ast {
+        Ast::Float(*self)
+    }
+}
+
+/// Integers convert as well
+impl FromAst for f64 {
+    fn from_ast(ast: &Ast) -> Result<Self, RuntimeError> {
+        match ast {
+            Ast::Float(x) => Ok(*x),
+            Ast::Integer(n) => Ok(*n as f64),
+            other => Err(mismatch("Number", other)),
+        }
+    }
+}
+
+impl ToAst for bool {
+    fn to_ast(&self) -> Ast {
+        Ast::Boolean(*self)
+    }
+}
+
+impl FromAst for bool {
+    fn from_ast(ast: &Ast) -> Result<Self, RuntimeError> {
+        match ast {
+            Ast::Boolean(b) => Ok(*b),
+            other => Err(mismatch("Boolean", other)),
+        }
+    }
+}
+
+impl ToAst for String {
+    fn to_ast(&self) -> Ast {
+        Ast::String(self.clone())
+    }
+}
+
+impl ToAst for &str {
+    fn to_ast(&self) -> Ast {
+        Ast::String((*self).to_owned())
+    }
+}
+
+impl FromAst for String {
+    fn from_ast(ast: &Ast) -> Result<Self, RuntimeError> {
+        match ast {
+            Ast::String(s) => Ok(s.clone()),
+            other => Err(mismatch("String", other)),
+        }
+    }
+}
+
+/// `None` is nil
+impl<T: ToAst> ToAst for Option<T> {
+    fn to_ast(&self) -> Ast {
+        self.as_ref().map_or(Ast::Nil, ToAst::to_ast)
+    }
+}
+
+impl<T: FromAst> FromAst for Option<T> {
+    fn from_ast(ast: &Ast) -> Result<Self, RuntimeError> {
+        match ast {
+            Ast::Nil => Ok(None),
+            other => T::from_ast(other).map(Some),
+        }
+    }
+}
+
+impl<T: ToAst> ToAst for Vec<T> {
+    fn to_ast(&self) -> Ast {
+        Ast::List(Rc::new(self.iter().map(ToAst::to_ast).collect()))
+    }
+}
+
+/// Lists, vectors and nil, which is the empty list
+impl<T: FromAst> FromAst for Vec<T> {
+    fn from_ast(ast: &Ast) -> Result<Self, RuntimeError> {
+        match ast {
+            Ast::List(xs) | Ast::Vector(xs) => xs
+                .iter()
+                .enumerate()
+                .map(|(i, x)| T::from_ast(x).map_err(|err| at(format!("[{}]", i), err)))
+                .collect(),
+            Ast::Nil => Ok(vec![]),
+            other => Err(mismatch("List", other)),
+        }
+    }
+}
+
+/// Builds the association list of a struct field by field
+#[derive(Default)]
+pub struct Record {
+    entries: Vec<Ast>,
+}
+
+impl Record {
+    pub fn new() -> Record {
+        Record::default()
+    }
+
+    pub fn field(mut self, key: &str, value: &impl ToAst) -> Record {
+        self.entries.push(Ast::List(Rc::new(vec![
+            Ast::String(key.to_owned()),
+            value.to_ast(),
+        ])));
+        self
+    }
+
+    pub fn build(self) -> Ast {
+        Ast::List(Rc::new(self.entries))
+    }
+}
+
+/// Reads the fields of a struct from an association list. Missing keys read as nil, so they
+/// convert to `None` for optional fields and fail for others.
+pub struct Fields<'a> {
+    entries: &'a [Ast],
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(ast: &'a Ast) -> Result<Fields<'a>, RuntimeError> {
+        match ast {
+            Ast::List(entries) => Ok(Fields { entries }),
+            Ast::Nil => Ok(Fields { entries: &[] }),
+            other => Err(mismatch("association list", other)),
+        }
+    }
+
+    pub fn get<T: FromAst>(&self, key: &str) -> Result<T, RuntimeError> {
+        let value = assoc_lookup(self.entries, &Ast::String(key.to_owned())).unwrap_or(Ast::Nil);
+        T::from_ast(&value).map_err(|err| at(key.to_owned(), err))
+    }
+}
+
+/// Implements `ToAst` and `FromAst` for a struct with named fields that all implement them:
+///
+/// ```text
+/// use beesting::convert::{FromAst, ToAst};
+/// use beesting::record_conversion;
+///
+/// struct Server {
+///     host: String,
+///     port: u16,
+///     max_connections: Option<i64>,
+/// }
+///
+/// record_conversion!(Server { host, port, max_connections });
+///
+/// // (("host" "localhost") ("port" 8080) ("max-connections" nil))
+/// let value = Server { host: "localhost".to_owned(), port: 8080, max_connections: None }.to_ast();
+/// let server = Server::from_ast(&value)?;
+/// ```
+#[macro_export]
+macro_rules! record_conversion {
+    ($type:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::convert::ToAst for $type {
+            fn to_ast(&self) -> $crate::parser::Ast {
+                $crate::convert::Record::new()
+                    $(.field(&$crate::convert::field_key(stringify!($field)), &self.$field))*
+                    .build()
+            }
+        }
+
+        impl $crate::convert::FromAst for $type {
+            fn from_ast(
+                ast: &$crate::parser::Ast,
+            ) -> Result<Self, $crate::errors::RuntimeError> {
+                let fields = $crate::convert::Fields::new(ast)?;
+                Ok($type {
+                    $($field: fields.get(&$crate::convert::field_key(stringify!($field)))?,)*
+                })
+            }
+        }
+    };
+}
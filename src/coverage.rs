@@ -0,0 +1,274 @@
+use crate::eval::unquoted;
+use crate::parser::{read_all, Ast, ParserError};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// List read from a source file that is evaluated when the program runs, i.e. not a parameter
+/// or binding list
+struct Form {
+    file: usize,
+    line: usize,
+    evaluated: bool,
+}
+
+/// Lists by address, with a number for each. Holding on to the lists keeps their addresses from
+/// being reused.
+type ListNumbers = HashMap<*const Vec<Ast>, (Rc<Vec<Ast>>, usize)>;
+
+#[derive(Default)]
+struct Coverage {
+    files: Vec<String>,
+    forms: Vec<Form>,
+    /// Form id of each tagged list
+    ids: ListNumbers,
+    /// Start lines of the lists read from the file being parsed
+    read_lines: Option<ListNumbers>,
+}
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static COVERAGE: RefCell<Coverage> = RefCell::new(Coverage::default());
+}
+
+/// Makes files read from now on record which of their forms are evaluated
+pub fn enable() {
+    ENABLED.with(|enabled| enabled.set(true));
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Parses the source of a file, tagging its forms so their evaluation is recorded
+pub fn read_file(file_name: &str, code: &str) -> Result<Vec<Ast>, ParserError> {
+    COVERAGE.with(|c| c.borrow_mut().read_lines = Some(HashMap::new()));
+    let forms = read_all(code);
+    let read_lines = COVERAGE.with(|c| c.borrow_mut().read_lines.take().unwrap_or_default());
+    let forms = forms?;
+
+    COVERAGE.with(|c| {
+        let mut coverage = c.borrow_mut();
+        let file = coverage.files.len();
+        coverage.files.push(file_name.to_owned());
+        for form in &forms {
+            coverage.tag_evaluated(form, file, &read_lines);
+        }
+    });
+    Ok(forms)
+}
+
+/// Called by the parser for every list it reads
+pub(crate) fn list_read(list: &Rc<Vec<Ast>>, line: usize) {
+    if !is_enabled() {
+        return;
+    }
+    COVERAGE.with(|c| {
+        if let Some(read_lines) = c.borrow_mut().read_lines.as_mut() {
+            read_lines.insert(Rc::as_ptr(list), (Rc::clone(list), line));
+        }
+    });
+}
+
+/// Gives a list the parser built in place of one it read, e.g. for `#(...)`, the line of the
+/// original
+pub(crate) fn list_replaced(original: &Rc<Vec<Ast>>, replacement: &Rc<Vec<Ast>>) {
+    if !is_enabled() {
+        return;
+    }
+    COVERAGE.with(|c| {
+        if let Some(read_lines) = c.borrow_mut().read_lines.as_mut() {
+            if let Some(&(_, line)) = read_lines.get(&Rc::as_ptr(original)) {
+                read_lines.insert(Rc::as_ptr(replacement), (Rc::clone(replacement), line));
+            }
+        }
+    });
+}
+
+/// Carries the tag of a list over to the list it was rewritten into, e.g. by the resolver
+pub(crate) fn rewritten(original: *const Vec<Ast>, rewritten: &Ast) {
+    let Ast::List(list) = rewritten else {
+        return;
+    };
+    if !is_enabled() || Rc::as_ptr(list) == original {
+        return;
+    }
+    COVERAGE.with(|c| {
+        let mut coverage = c.borrow_mut();
+        if let Some(&(_, id)) = coverage.ids.get(&original) {
+            coverage.ids.insert(Rc::as_ptr(list), (Rc::clone(list), id));
+        }
+    });
+}
+
+/// Called by the evaluator for every list it evaluates
+pub(crate) fn evaluated(list: &Rc<Vec<Ast>>) {
+    if !is_enabled() {
+        return;
+    }
+    COVERAGE.with(|c| {
+        let mut coverage = c.borrow_mut();
+        if let Some(&(_, id)) = coverage.ids.get(&Rc::as_ptr(list)) {
+            coverage.forms[id].evaluated = true;
+        }
+    });
+}
+
+impl Coverage {
+    /// Tags `ast` if it is a list, then the parts of it that are evaluated in turn
+    fn tag_evaluated(&mut self, ast: &Ast, file: usize, read_lines: &ListNumbers) {
+        let xs = match ast {
+            Ast::List(xs) => xs,
+            Ast::Vector(xs) => {
+                for x in xs.iter() {
+                    self.tag_evaluated(x, file, read_lines);
+                }
+                return;
+            }
+            Ast::HashMap(map) => {
+                for x in map.values() {
+                    self.tag_evaluated(x, file, read_lines);
+                }
+                return;
+            }
+            _ => return,
+        };
+        if let Some((list, line)) = read_lines.get(&Rc::as_ptr(xs)) {
+            self.ids
+                .insert(Rc::as_ptr(xs), (Rc::clone(list), self.forms.len()));
+            self.forms.push(Form {
+                file,
+                line: *line,
+                evaluated: false,
+            });
+        }
+
+        let head = match xs.first() {
+            Some(Ast::Symbol(s)) => s.as_str(),
+            _ => "",
+        };
+        let evaluated: Vec<&Ast> = match (head, xs.get(1)) {
+            ("fun*" | "def!" | "defmacro!" | "defonce" | "defconst", _) => {
+                xs.iter().skip(2).collect()
+            }
+            ("let*" | "letrec" | "plet", Some(Ast::List(bindings))) => bindings
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .chain(xs.iter().skip(2))
+                .collect(),
+            ("quote", _) => vec![],
+            ("try*", _) => {
+                let mut forms: Vec<&Ast> = xs.iter().skip(1).take(1).collect();
+                for clause in xs.iter().skip(2) {
+                    match clause {
+                        Ast::List(clause) if clause.len() == 3 => forms.push(&clause[2]),
+                        other => forms.push(other),
+                    }
+                }
+                forms
+            }
+            ("quasiquote", Some(template)) => {
+                let mut forms = vec![];
+                unquoted_forms(template, &mut forms);
+                forms
+            }
+            ("doseq", Some(Ast::List(binding))) => {
+                binding.iter().skip(1).chain(xs.iter().skip(2)).collect()
+            }
+            _ => xs.iter().collect(),
+        };
+        for x in evaluated {
+            self.tag_evaluated(x, file, read_lines);
+        }
+    }
+}
+
+/// Collects the forms of a quasiquote template that are evaluated
+fn unquoted_forms<'a>(template: &'a Ast, forms: &mut Vec<&'a Ast>) {
+    if let Some(x) = unquoted(template, "unquote").or_else(|| unquoted(template, "splice-unquote"))
+    {
+        forms.push(x);
+    } else if let Ast::List(xs) | Ast::Vector(xs) = template {
+        for x in xs.iter() {
+            unquoted_forms(x, forms);
+        }
+    }
+}
+
+/// How much of a file has been evaluated
+pub struct FileCoverage {
+    pub file: String,
+    pub forms: usize,
+    pub evaluated_forms: usize,
+    /// Lines on which a form starts
+    pub lines: usize,
+    /// Lines on which forms start of which none has been evaluated
+    pub unevaluated_lines: Vec<usize>,
+}
+
+impl Display for FileCoverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let percent = |part: usize, total: usize| {
+            if total == 0 {
+                100.0
+            } else {
+                part as f64 * 100.0 / total as f64
+            }
+        };
+        let evaluated_lines = self.lines - self.unevaluated_lines.len();
+        write!(
+            f,
+            "{}: {}/{} forms ({:.1}%), {}/{} lines ({:.1}%)",
+            self.file,
+            self.evaluated_forms,
+            self.forms,
+            percent(self.evaluated_forms, self.forms),
+            evaluated_lines,
+            self.lines,
+            percent(evaluated_lines, self.lines)
+        )?;
+        if !self.unevaluated_lines.is_empty() {
+            let lines: Vec<String> = self
+                .unevaluated_lines
+                .iter()
+                .map(usize::to_string)
+                .collect();
+            write!(f, "\n  not evaluated: lines {}", lines.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Coverage of every file read since coverage was enabled, in the order they were read
+pub fn report() -> Vec<FileCoverage> {
+    COVERAGE.with(|c| {
+        let coverage = c.borrow();
+        coverage
+            .files
+            .iter()
+            .enumerate()
+            .map(|(file, name)| {
+                let forms: Vec<&Form> = coverage.forms.iter().filter(|f| f.file == file).collect();
+                // Whether any form starting on a line has been evaluated, by line
+                let mut lines = BTreeMap::new();
+                for form in &forms {
+                    *lines.entry(form.line).or_insert(false) |= form.evaluated;
+                }
+
+                FileCoverage {
+                    file: name.clone(),
+                    forms: forms.len(),
+                    evaluated_forms: forms.iter().filter(|f| f.evaluated).count(),
+                    lines: lines.len(),
+                    unevaluated_lines: lines
+                        .into_iter()
+                        .filter(|(_, evaluated)| !evaluated)
+                        .map(|(line, _)| line)
+                        .collect(),
+                }
+            })
+            .collect()
+    })
+}
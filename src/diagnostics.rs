@@ -0,0 +1,81 @@
+use crate::lexer::Span;
+use crate::parser::Ast;
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+
+/// Non-fatal problems noticed during evaluation
+pub enum Warning {
+    ShadowedBuiltin(String),
+    OddBindingList(String, Ast),
+    /// Function defined at the top level, with the symbols its body uses that aren't defined
+    UndefinedInFunction(Option<String>, Vec<String>),
+}
+
+impl Debug for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::ShadowedBuiltin(name) => {
+                write!(f, "def! of '{}' shadows a builtin function", name)
+            }
+            Warning::OddBindingList(construct, bindings) => write!(
+                f,
+                "Binding list of {} has an odd number of elements, the last one is ignored: {:?}",
+                construct, bindings
+            ),
+            Warning::UndefinedInFunction(name, symbols) => write!(
+                f,
+                "{} refers to undefined symbols: {}",
+                name.as_ref()
+                    .map_or("fun*".to_owned(), |name| format!("Function '{}'", name)),
+                symbols.join(", ")
+            ),
+        }
+    }
+}
+
+thread_local! {
+    static WARNINGS: RefCell<Vec<Warning>> = const { RefCell::new(vec![]) };
+}
+
+pub fn warn(warning: Warning) {
+    WARNINGS.with(|warnings| warnings.borrow_mut().push(warning));
+}
+
+/// Removes and returns all warnings emitted since the last call
+pub fn take_warnings() -> Vec<Warning> {
+    WARNINGS.with(|warnings| warnings.take())
+}
+
+/// Shows the source line a span starts on, with the spanned text underlined by carets:
+///
+/// ```text
+/// 2 | (def! x (+ 1 2)))
+///   |                 ^
+/// ```
+pub fn underline(source: &str, span: Span) -> String {
+    let line = source.lines().nth(span.start.line - 1).unwrap_or("");
+    let start = span.start.column - 1;
+    let width = if span.end.line == span.start.line {
+        span.end.column.saturating_sub(span.start.column).max(1)
+    } else {
+        line.chars().count().saturating_sub(start).max(1)
+    };
+
+    // Tabs are kept so the carets line up with the text however wide the terminal shows them
+    let padding: String = line
+        .chars()
+        .take(start)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let number = span.start.line.to_string();
+    let gutter = " ".repeat(number.len());
+
+    format!(
+        "{} | {}\n{} | {}{}",
+        number,
+        line,
+        gutter,
+        padding,
+        "^".repeat(width)
+    )
+}
@@ -1,11 +1,173 @@
-use crate::parser::ParserError;
+use crate::callstack;
+use crate::diagnostics::underline;
+use crate::lexer::Span;
+use crate::parser::{keyword_map, Ast, ParserError};
+use crate::printer::pr_str;
+use std::fmt::{Debug, Formatter};
 use std::io;
+use std::rc::Rc;
 
-#[derive(Debug)]
 pub enum ReplError {
     ParserError(ParserError),
+    RuntimeError(RuntimeError),
     IoError(io::Error),
-    SymbolUndefined(String),
+    /// Undefined symbol and the most similar defined name, if any
+    SymbolUndefined(String, Option<String>),
+    /// Error with a location in the given source text, which is shown beneath the message
+    InSource(Box<ReplError>, String),
+    /// Value raised with `throw`
+    Thrown(Ast),
+}
+
+pub enum RuntimeError {
+    TypeMismatch(String, u32, String, Ast),
+    ExpectedSymbol(String, Ast),
+    ExpectedList(String, Ast),
+    MalformedForm(String, Ast),
+    NotCallable(Ast),
+    WrongArity(String, usize, usize),
+    Redefinition(String),
+    ConstantRedefinition(String),
+    NotLoaded(String),
+    /// Special form and the builtin group it needs, which is disabled
+    GroupDisabled(String, String),
+    /// Builtin registered through the C interface and the message it failed with
+    Foreign(String, String),
+    /// Path of the value inside the converted value, the expected type and the value found when
+    /// converting to a Rust type
+    Conversion(String, String, Ast),
+    MemoryLimitExceeded(usize),
+    IndexOutOfBounds(String, i64, usize),
+    /// Arithmetic builtin whose integer result doesn't fit in an `i64`
+    IntegerOverflow(String),
+    DivisionByZero(String),
+    /// Call depth limit and the innermost calls with their argument values
+    StackOverflow(usize, Vec<Ast>),
+}
+
+impl ReplError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ReplError::ParserError(err) => err.span(),
+            ReplError::InSource(err, _) => err.span(),
+            _ => None,
+        }
+    }
+
+    /// Attaches the text that was being read, if the error points at a location in it
+    pub fn with_source(self, source: &str) -> ReplError {
+        match self {
+            ReplError::InSource(..) => self,
+            err if err.span().is_some() => ReplError::InSource(Box::new(err), source.to_owned()),
+            err => err,
+        }
+    }
+
+    /// Name of the kind of error, for handlers to branch on
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            ReplError::ParserError(_) => "parse-error",
+            ReplError::IoError(_) => "io-error",
+            ReplError::SymbolUndefined(..) => "undefined-symbol",
+            ReplError::InSource(err, _) => err.error_type(),
+            ReplError::Thrown(_) => "thrown",
+            ReplError::RuntimeError(err) => match err {
+                RuntimeError::TypeMismatch(..) => "type-mismatch",
+                RuntimeError::ExpectedSymbol(..) => "expected-symbol",
+                RuntimeError::ExpectedList(..) => "expected-list",
+                RuntimeError::MalformedForm(..) => "malformed-form",
+                RuntimeError::NotCallable(_) => "not-callable",
+                RuntimeError::WrongArity(..) => "wrong-arity",
+                RuntimeError::Redefinition(_) => "redefinition",
+                RuntimeError::ConstantRedefinition(_) => "constant-redefinition",
+                RuntimeError::NotLoaded(_) => "not-loaded",
+                RuntimeError::GroupDisabled(..) => "group-disabled",
+                RuntimeError::Foreign(..) => "foreign-error",
+                RuntimeError::Conversion(..) => "conversion-error",
+                RuntimeError::MemoryLimitExceeded(_) => "memory-limit-exceeded",
+                RuntimeError::IndexOutOfBounds(..) => "index-out-of-bounds",
+                RuntimeError::IntegerOverflow(_) => "integer-overflow",
+                RuntimeError::DivisionByZero(_) => "division-by-zero",
+                RuntimeError::StackOverflow(..) => "stack-overflow",
+            },
+        }
+    }
+
+    /// Details of the error as a hash-map, e.g. the expected and actual argument count
+    /// of a `wrong-arity` error
+    pub fn data(&self) -> Ast {
+        let string = |s: &str| Ast::String(s.to_owned());
+        let int = |n: usize| Ast::Integer(n as i64);
+
+        let entries = match self {
+            ReplError::ParserError(_) | ReplError::IoError(_) => vec![],
+            ReplError::SymbolUndefined(symbol, suggestion) => vec![
+                ("symbol", string(symbol)),
+                ("suggestion", suggestion.as_deref().map_or(Ast::Nil, string)),
+            ],
+            ReplError::InSource(err, _) => return err.data(),
+            ReplError::Thrown(value) => vec![("value", value.clone())],
+            ReplError::RuntimeError(err) => match err {
+                RuntimeError::TypeMismatch(fn_name, index, expected, got) => vec![
+                    ("function", string(fn_name)),
+                    ("position", Ast::Integer(*index as i64)),
+                    ("expected", string(expected)),
+                    ("got", got.clone()),
+                ],
+                RuntimeError::ExpectedSymbol(construct, got)
+                | RuntimeError::ExpectedList(construct, got)
+                | RuntimeError::MalformedForm(construct, got) => {
+                    vec![("form", string(construct)), ("got", got.clone())]
+                }
+                RuntimeError::NotCallable(got) => vec![("got", got.clone())],
+                RuntimeError::WrongArity(fn_name, expected, got) => vec![
+                    ("function", string(fn_name)),
+                    ("expected", int(*expected)),
+                    ("got", int(*got)),
+                ],
+                RuntimeError::Redefinition(name) | RuntimeError::ConstantRedefinition(name) => {
+                    vec![("symbol", string(name))]
+                }
+                RuntimeError::NotLoaded(file) => vec![("file", string(file))],
+                RuntimeError::GroupDisabled(construct, group) => {
+                    vec![("form", string(construct)), ("group", string(group))]
+                }
+                RuntimeError::Foreign(fn_name, _) => vec![("function", string(fn_name))],
+                RuntimeError::Conversion(path, expected, got) => vec![
+                    ("path", string(path)),
+                    ("expected", string(expected)),
+                    ("got", got.clone()),
+                ],
+                RuntimeError::MemoryLimitExceeded(limit) => vec![("limit", int(*limit))],
+                RuntimeError::IndexOutOfBounds(fn_name, index, len) => vec![
+                    ("function", string(fn_name)),
+                    ("index", Ast::Integer(*index)),
+                    ("length", int(*len)),
+                ],
+                RuntimeError::IntegerOverflow(fn_name) | RuntimeError::DivisionByZero(fn_name) => {
+                    vec![("function", string(fn_name))]
+                }
+                RuntimeError::StackOverflow(limit, calls) => vec![
+                    ("limit", int(*limit)),
+                    ("calls", Ast::List(Rc::new(calls.clone()))),
+                ],
+            },
+        };
+
+        keyword_map(entries)
+    }
+
+    /// The error as a value for error handlers: a hash-map with its `:type`, `:message`,
+    /// `:data` and the `:backtrace` recorded when it was raised
+    pub fn to_value(&self) -> Ast {
+        let backtrace = callstack::frames_to_ast(callstack::take_error_backtrace());
+        keyword_map(vec![
+            ("type", Ast::Keyword(self.error_type().to_owned())),
+            ("message", Ast::String(format!("{:?}", self))),
+            ("data", self.data()),
+            ("backtrace", backtrace),
+        ])
+    }
 }
 
 impl From<ParserError> for ReplError {
@@ -14,8 +176,110 @@ impl From<ParserError> for ReplError {
     }
 }
 
+impl From<RuntimeError> for ReplError {
+    fn from(value: RuntimeError) -> ReplError {
+        ReplError::RuntimeError(value)
+    }
+}
+
 impl From<io::Error> for ReplError {
     fn from(value: io::Error) -> ReplError {
         ReplError::IoError(value)
     }
 }
+
+impl Debug for ReplError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplError::ParserError(err) => write!(f, "{:?}", err),
+            ReplError::RuntimeError(err) => write!(f, "{:?}", err),
+            ReplError::IoError(err) => write!(f, "IO error: {}", err),
+            ReplError::SymbolUndefined(symbol, None) => {
+                write!(f, "Symbol '{}' is undefined", symbol)
+            }
+            ReplError::SymbolUndefined(symbol, Some(suggestion)) => write!(
+                f,
+                "Symbol '{}' is undefined, did you mean '{}'?",
+                symbol, suggestion
+            ),
+            ReplError::InSource(err, source) => match err.span() {
+                Some(span) => write!(f, "{:?}\n{}", err, underline(source, span)),
+                None => write!(f, "{:?}", err),
+            },
+            ReplError::Thrown(value) => write!(f, "Uncaught exception: {}", pr_str(value, true)),
+        }
+    }
+}
+
+impl Debug for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::TypeMismatch(fn_name, index, expected, got) => write!(
+                f,
+                "Type mismatch: Expected {} at argument position {} of {} but got {:?}",
+                expected, index, fn_name, got
+            ),
+            RuntimeError::ExpectedSymbol(construct, got) => {
+                write!(f, "Expected symbol in {} but got {:?}", construct, got)
+            }
+            RuntimeError::ExpectedList(construct, got) => {
+                write!(f, "Expected list in {} but got {:?}", construct, got)
+            }
+            RuntimeError::MalformedForm(construct, form) => {
+                write!(f, "Malformed {} form: {:?}", construct, form)
+            }
+            RuntimeError::NotCallable(got) => write!(f, "{:?} is not callable", got),
+            RuntimeError::WrongArity(fn_name, expected, got) => write!(
+                f,
+                "Wrong number of arguments to {}: expected {} but got {}",
+                fn_name, expected, got
+            ),
+            RuntimeError::Redefinition(name) => {
+                write!(f, "Strict mode: '{}' is already defined", name)
+            }
+            RuntimeError::ConstantRedefinition(name) => {
+                write!(f, "'{}' is a constant and can't be redefined", name)
+            }
+            RuntimeError::NotLoaded(file) => {
+                write!(f, "'{}' can't be reloaded, it was never loaded", file)
+            }
+            RuntimeError::GroupDisabled(construct, group) => write!(
+                f,
+                "{} needs the {} builtins, which are disabled",
+                construct, group
+            ),
+            RuntimeError::Foreign(fn_name, message) => write!(f, "{}: {}", fn_name, message),
+            RuntimeError::Conversion(path, expected, got) if path.is_empty() => {
+                write!(f, "Can't convert {:?}: expected {}", got, expected)
+            }
+            RuntimeError::Conversion(path, expected, got) => write!(
+                f,
+                "Can't convert {:?} at {}: expected {}",
+                got, path, expected
+            ),
+            RuntimeError::IndexOutOfBounds(fn_name, index, len) => write!(
+                f,
+                "Index {} out of bounds in {}: length is {}",
+                index, fn_name, len
+            ),
+            RuntimeError::IntegerOverflow(fn_name) => {
+                write!(f, "Integer overflow in {}", fn_name)
+            }
+            RuntimeError::DivisionByZero(fn_name) => write!(f, "Division by zero in {}", fn_name),
+            RuntimeError::MemoryLimitExceeded(limit) => {
+                write!(f, "Memory limit of {} bytes exceeded", limit)
+            }
+            RuntimeError::StackOverflow(limit, calls) => {
+                write!(
+                    f,
+                    "Stack overflow: more than {} nested calls. Innermost calls:",
+                    limit
+                )?;
+                for call in calls {
+                    write!(f, "\n  {:?}", call)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,10 +1,53 @@
-use crate::errors::ReplError;
-use crate::parser::{Ast, ParserError, UserFunction};
-use crate::root_env::{get_root, lookup, Environment};
+use crate::callstack::{record_error, take_error_backtrace, CallScope};
+use crate::coverage;
+use crate::diagnostics::{warn, Warning};
+use crate::errors::{ReplError, RuntimeError};
+use crate::interpreter::with_options;
+use crate::memory::check_memory_limit;
+use crate::optimize::fold_constants;
+use crate::output::{with_port, write_to, OutputPort, ERR, OUT};
+use crate::parser::{keyword_map, Ast, Closure, EnvFunction, MapKey, UserFunction};
+use crate::resolve::{defining, resolve_function_body};
+use crate::root_env::{
+    assoc_lookup, find, get_root, is_redefined_by_its_file, lookup, lookup_local,
+    record_root_definition, Environment,
+};
+use crate::seq::{for_each, is_iterable};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::iter::zip;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Forms handled by `eval_list` itself rather than by calling a function
+pub const SPECIAL_FORMS: [&str; 28] = [
+    "quote",
+    "quasiquote",
+    "unquote",
+    "splice-unquote",
+    "def!",
+    "defmacro!",
+    "defconst",
+    "defonce",
+    "let*",
+    "letrec",
+    "do",
+    "if",
+    "cond",
+    "and",
+    "or",
+    "fun*",
+    "eval",
+    "bench",
+    "time",
+    "some->",
+    "plet",
+    "with-out-str",
+    "with-out",
+    "with-out-file",
+    "doseq",
+    "with-env",
+    "try*",
+    "catch*",
+];
 
 enum EvalBehaviour {
     ReturnImmediately(Ast),
@@ -12,14 +55,72 @@ enum EvalBehaviour {
     LoopWithAstAndEnv(Ast, Rc<RefCell<Environment>>),
 }
 
+/// Anything that can be applied to arguments
+#[derive(Clone)]
+pub enum Callable {
+    User(Rc<UserFunction>),
+    Builtin(String, EnvFunction),
+    Closure(Rc<Closure>),
+}
+
+impl Callable {
+    pub fn from_ast(ast: Ast) -> Result<Callable, RuntimeError> {
+        match ast {
+            Ast::Function(user_fun) => Ok(Callable::User(user_fun)),
+            Ast::Builtin(name, cb) => Ok(Callable::Builtin(name, cb)),
+            Ast::Closure(closure) => Ok(Callable::Closure(closure)),
+            other => Err(RuntimeError::NotCallable(other)),
+        }
+    }
+
+    /// Calls with already evaluated arguments and returns the result
+    pub fn call(&self, args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+        match self.prepare(args, env)? {
+            EvalBehaviour::ReturnImmediately(result) => Ok(result),
+            EvalBehaviour::LoopWithAst(ast) => eval(ast, env),
+            EvalBehaviour::LoopWithAstAndEnv(ast, n_env) => eval(ast, &n_env),
+        }
+    }
+
+    /// Binds the arguments, leaving the evaluation of user function bodies to the caller so that
+    /// tail calls don't grow the stack
+    fn prepare(
+        &self,
+        args: Vec<Ast>,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalBehaviour, ReplError> {
+        match self {
+            Callable::User(user_fun) => Ok(EvalBehaviour::LoopWithAstAndEnv(
+                user_fun.body.clone(),
+                Rc::new(RefCell::new(bind_fn(
+                    &user_fun.params,
+                    user_fun.variadic,
+                    args,
+                    &user_fun.env,
+                )?)),
+            )),
+            Callable::Builtin(name, cb) => {
+                Ok(EvalBehaviour::ReturnImmediately(cb(name, args, env)?))
+            }
+            Callable::Closure(closure) => {
+                Ok(EvalBehaviour::ReturnImmediately((closure.f)(args, env)?))
+            }
+        }
+    }
+}
+
 pub fn eval(i_ast: Ast, i_env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
     let mut ast = i_ast;
     let mut env = Rc::clone(i_env);
+    let mut scope = None;
 
     loop {
         match ast {
             Ast::List(xs) => {
-                let behaviour = eval_list(xs, &env)?;
+                check_memory_limit()?;
+                coverage::evaluated(&xs);
+                let scope = scope.get_or_insert_with(CallScope::new);
+                let behaviour = eval_list(&xs, &env, scope).inspect_err(|_| record_error())?;
                 match behaviour {
                     EvalBehaviour::ReturnImmediately(n_ast) => return Ok(n_ast),
                     EvalBehaviour::LoopWithAst(n_ast) => ast = n_ast,
@@ -29,138 +130,742 @@ pub fn eval(i_ast: Ast, i_env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplErr
                     }
                 }
             }
+            Ast::Vector(xs) => return Ok(Ast::Vector(Rc::new(eval_all(&xs, &env)?))),
+            Ast::HashMap(map) => {
+                let values = map
+                    .iter()
+                    .map(|(k, v)| Ok((k.clone(), eval(v.clone(), &env)?)));
+                return Ok(Ast::HashMap(Rc::new(
+                    values.collect::<Result<_, ReplError>>()?,
+                )));
+            }
             Ast::Symbol(s) => return eval_symbol(s, &env),
             Ast::Integer(n) => return Ok(Ast::Integer(n)),
+            Ast::Float(x) => return Ok(Ast::Float(x)),
+            Ast::Keyword(k) => return Ok(Ast::Keyword(k)),
             Ast::Boolean(b) => return Ok(Ast::Boolean(b)),
             Ast::String(str) => return Ok(Ast::String(str)),
             Ast::Function(f) => return Ok(Ast::Function(f)),
             Ast::Builtin(n, f) => return Ok(Ast::Builtin(n, f)),
+            Ast::Closure(c) => return Ok(Ast::Closure(c)),
             Ast::Nil => return Ok(Ast::Nil),
             Ast::Atom(ast) => return Ok(Ast::Atom(ast)),
+            Ast::MutableVector(v) => return Ok(Ast::MutableVector(v)),
+            Ast::IntArray(a) => return Ok(Ast::IntArray(a)),
+            Ast::FloatArray(a) => return Ok(Ast::FloatArray(a)),
+            Ast::StringBuilder(sb) => return Ok(Ast::StringBuilder(sb)),
+            Ast::Port(port) => return Ok(Ast::Port(port)),
+            Ast::Seq(seq) => return Ok(Ast::Seq(seq)),
+            Ast::Reader(reader) => return Ok(Ast::Reader(reader)),
+            Ast::Env(env) => return Ok(Ast::Env(env)),
+            Ast::Local(depth, slot, name) => return lookup_local(depth, slot, &name, &env),
         }
     }
 }
 
-fn eval_list(mut xs: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<EvalBehaviour, ReplError> {
+fn eval_list(
+    xs: &Rc<Vec<Ast>>,
+    env: &Rc<RefCell<Environment>>,
+    scope: &CallScope,
+) -> Result<EvalBehaviour, ReplError> {
+    // `()` evaluates to itself, like any other empty collection
     if xs.is_empty() {
-        todo!("error: empty list")
+        return Ok(EvalBehaviour::ReturnImmediately(Ast::List(Rc::clone(xs))));
     }
 
     if let Ast::Symbol(s) = &xs[0] {
         match s.as_str() {
-            "def!" => Ok(EvalBehaviour::ReturnImmediately(eval_form_def(xs, env)?)),
+            "quote" => {
+                check_form(xs, 2, "quote")?;
+                Ok(EvalBehaviour::ReturnImmediately(xs[1].clone()))
+            }
+            "quasiquote" => {
+                check_form(xs, 2, "quasiquote")?;
+                Ok(EvalBehaviour::ReturnImmediately(eval_quasiquote(
+                    &xs[1], env,
+                )?))
+            }
+            // Only meaningful inside a quasiquote template or a `try*` form
+            "unquote" | "splice-unquote" | "catch*" => {
+                Err(RuntimeError::MalformedForm(s.clone(), Ast::List(Rc::clone(xs))).into())
+            }
+            "def!" => Ok(EvalBehaviour::ReturnImmediately(eval_form_def(
+                xs, env, false,
+            )?)),
+            "defconst" => Ok(EvalBehaviour::ReturnImmediately(eval_form_def(
+                xs, env, true,
+            )?)),
+            "defmacro!" => Ok(EvalBehaviour::ReturnImmediately(eval_form_defmacro(
+                xs, env,
+            )?)),
+            "defonce" => Ok(EvalBehaviour::ReturnImmediately(eval_form_defonce(
+                xs, env,
+            )?)),
             "let*" => do_form_let(xs, env),
             "letrec" => do_form_letrec(xs, env),
+            "plet" => do_form_plet(xs, env),
             "do" => do_form_do(xs, env),
             "if" => Ok(EvalBehaviour::LoopWithAst(do_form_if(xs, env)?)),
+            "cond" => Ok(EvalBehaviour::LoopWithAst(do_form_cond(xs, env)?)),
+            "and" => do_form_and_or(xs, env, true),
+            "or" => do_form_and_or(xs, env, false),
             "fun*" => Ok(EvalBehaviour::ReturnImmediately(eval_form_fun(xs, env)?)),
             "eval" => {
-                let result = eval(xs.remove(1), env)?;
+                check_form(xs, 2, "eval")?;
+                let result = eval(xs[1].clone(), env)?;
                 Ok(EvalBehaviour::LoopWithAstAndEnv(result, get_root(env)))
             }
-            _ => eval_func_call(xs, env),
+            "bench" => Ok(EvalBehaviour::ReturnImmediately(eval_form_bench(xs, env)?)),
+            "time" => Ok(EvalBehaviour::ReturnImmediately(eval_form_time(xs, env)?)),
+            "some->" => Ok(EvalBehaviour::ReturnImmediately(eval_form_some_thread(
+                xs, env,
+            )?)),
+            "with-out-str" => Ok(EvalBehaviour::ReturnImmediately(eval_form_with_out_str(
+                xs, env,
+            )?)),
+            "doseq" => Ok(EvalBehaviour::ReturnImmediately(eval_form_doseq(xs, env)?)),
+            "with-env" => do_form_with_env(xs, env),
+            "try*" => do_form_try(xs, env),
+            "with-out" => Ok(EvalBehaviour::ReturnImmediately(eval_form_with_out(
+                xs, env,
+            )?)),
+            "with-out-file" => Ok(EvalBehaviour::ReturnImmediately(eval_form_with_out_file(
+                xs, env,
+            )?)),
+            _ => eval_func_call(xs, env, scope),
         }
     } else {
-        eval_func_call(xs, env)
+        eval_func_call(xs, env, scope)
     }
 }
 
-fn eval_form_def(mut args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
-    // todo arity check
-    let definition = args.pop().unwrap();
-    let name = get_symbol_name(args.pop().unwrap())?;
+/// Whether `ast` is `(<form> x)`, returning `x`
+pub fn unquoted<'a>(ast: &'a Ast, form: &str) -> Option<&'a Ast> {
+    match ast {
+        Ast::List(xs) => match xs.as_slice() {
+            [Ast::Symbol(s), x] if s == form => Some(x),
+            _ => None,
+        },
+        _ => None,
+    }
+}
 
-    let definition_value = eval(definition, env)?;
-    env.borrow_mut()
-        .values
-        .insert(name, definition_value.clone()); // todo do i clone here or below? probably here right
-    Ok(definition_value)
+/// Returns a quasiquote template with `(unquote x)` replaced by the value of `x` and the
+/// elements of the list or sequence `xs` spliced in place of `(splice-unquote xs)`
+fn eval_quasiquote(template: &Ast, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if let Some(x) = unquoted(template, "unquote") {
+        return eval(x.clone(), env);
+    }
+    let (xs, is_vector) = match template {
+        Ast::List(xs) => (xs, false),
+        Ast::Vector(xs) => (xs, true),
+        other => return Ok(other.clone()),
+    };
+
+    let mut result = Vec::with_capacity(xs.len());
+    for x in xs.iter() {
+        let Some(spliced) = unquoted(x, "splice-unquote") else {
+            result.push(eval_quasiquote(x, env)?);
+            continue;
+        };
+
+        let value = eval(spliced.clone(), env)?;
+        if !is_iterable(&value) {
+            return Err(RuntimeError::TypeMismatch(
+                "splice-unquote".to_owned(),
+                1,
+                "List, Vector or Seq".to_owned(),
+                value,
+            )
+            .into());
+        }
+        for_each(&value, |y| {
+            result.push(y);
+            Ok(true)
+        })?;
+    }
+    if is_vector {
+        return Ok(Ast::Vector(Rc::new(result)));
+    }
+    Ok(Ast::List(Rc::new(result)))
 }
 
-fn do_form_let(
-    mut args: Vec<Ast>,
+fn eval_form_def(
+    args: &[Ast],
     env: &Rc<RefCell<Environment>>,
-) -> Result<EvalBehaviour, ReplError> {
-    let expr = args.pop().unwrap();
-    let bindings = args.pop().unwrap();
+    constant: bool,
+) -> Result<Ast, ReplError> {
+    let construct = if constant { "defconst" } else { "def!" };
+    check_form(args, 3, construct)?;
+    let name = get_symbol_name(&args[1], construct)?;
+    let mut definition = args[2].clone();
+
+    if with_options(|options| options.fold_constants) {
+        definition = fold_constants(definition, env);
+    }
+
+    check_redefinition(&name, env)?;
 
-    let n_env = bind_let(bindings, env, false)?;
-    Ok(EvalBehaviour::LoopWithAstAndEnv(expr, n_env))
+    if let Ok(Ast::Builtin(..)) = lookup(name.clone(), env) {
+        warn(Warning::ShadowedBuiltin(name.clone()));
+    }
+
+    let definition_value = defining(&name, || eval(definition, env))?;
+    if constant {
+        env.borrow_mut().constants.insert(name.clone());
+    }
+    define(name, definition_value.clone(), env); // todo do i clone here or below? probably here right
+    Ok(definition_value)
+}
+
+/// Rejects defining `name` in `env` if it is a constant there, or in strict mode if it is already
+/// defined at the root by something other than the file being reloaded
+fn check_redefinition(name: &str, env: &Rc<RefCell<Environment>>) -> Result<(), ReplError> {
+    if env.borrow().constants.contains(name) {
+        return Err(RuntimeError::ConstantRedefinition(name.to_owned()).into());
+    }
+
+    let is_root = env.borrow().parent.is_none();
+    if is_root
+        && with_options(|options| options.strict)
+        && env.borrow().get_local(name).is_some()
+        && !is_redefined_by_its_file(name)
+    {
+        return Err(RuntimeError::Redefinition(name.to_owned()).into());
+    }
+    Ok(())
+}
+
+/// Binds `name` in `env`, which `check_redefinition` has allowed
+fn define(name: String, value: Ast, env: &Rc<RefCell<Environment>>) {
+    let mut env = env.borrow_mut();
+    if env.parent.is_none() {
+        record_root_definition(&name);
+    }
+    env.set_local(name, value);
+}
+
+/// `(defmacro! name (fun* ...))` defines a macro: its calls are replaced by the result of calling
+/// the function with the unevaluated arguments, which is then evaluated
+fn eval_form_defmacro(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_form(args, 3, "defmacro!")?;
+    let name = get_symbol_name(&args[1], "defmacro!")?;
+    check_redefinition(&name, env)?;
+
+    let macro_fn = match defining(&name, || eval(args[2].clone(), env))? {
+        Ast::Function(f) => Ast::Function(Rc::new(UserFunction {
+            is_macro: true,
+            ..(*f).clone()
+        })),
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                "defmacro!".to_owned(),
+                2,
+                "Function".to_owned(),
+                other,
+            )
+            .into())
+        }
+    };
+    define(name, macro_fn.clone(), env);
+    Ok(macro_fn)
+}
+
+/// The macro called by the list `xs`, if it is a call of one
+pub fn macro_function(xs: &[Ast], env: &Rc<RefCell<Environment>>) -> Option<Rc<UserFunction>> {
+    let Some(Ast::Symbol(head)) = xs.first() else {
+        return None;
+    };
+    match find(head, env)? {
+        Ast::Function(f) if f.is_macro => Some(f),
+        _ => None,
+    }
+}
+
+/// Like `def!`, but keeps and returns an existing binding without evaluating the definition
+fn eval_form_defonce(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_form(args, 3, "defonce")?;
+    let name = get_symbol_name(&args[1], "defonce")?;
+
+    if let Some(existing) = env.borrow().get_local(&name) {
+        return Ok(existing.clone());
+    }
+
+    let definition_value = defining(&name, || eval(args[2].clone(), env))?;
+    define(name, definition_value.clone(), env);
+    Ok(definition_value)
+}
+
+fn do_form_let(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<EvalBehaviour, ReplError> {
+    check_form(args, 3, "let*")?;
+
+    let n_env = bind_let(&args[1], env, "let*", false)?;
+    Ok(EvalBehaviour::LoopWithAstAndEnv(args[2].clone(), n_env))
+}
+
+/// Like `let*`, but all values are evaluated before any of the bindings is visible
+fn do_form_plet(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<EvalBehaviour, ReplError> {
+    check_form(args, 3, "plet")?;
+
+    let n_env = bind_let(&args[1], env, "plet", true)?;
+    Ok(EvalBehaviour::LoopWithAstAndEnv(args[2].clone(), n_env))
 }
 
 fn do_form_letrec(
-    mut args: Vec<Ast>,
+    args: &[Ast],
     env: &Rc<RefCell<Environment>>,
 ) -> Result<EvalBehaviour, ReplError> {
-    let expr = args.pop().unwrap();
-    let bindings = args.pop().unwrap();
+    check_form(args, 3, "letrec")?;
 
-    let n_env = bind_let(bindings, env, true)?;
-    Ok(EvalBehaviour::LoopWithAstAndEnv(expr, n_env))
+    let n_env = bind_let(&args[1], env, "letrec", false)?;
+    Ok(EvalBehaviour::LoopWithAstAndEnv(args[2].clone(), n_env))
 }
 
-fn do_form_do(
-    mut args: Vec<Ast>,
-    env: &Rc<RefCell<Environment>>,
-) -> Result<EvalBehaviour, ReplError> {
-    let last_maybe = args.pop();
+fn do_form_do(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<EvalBehaviour, ReplError> {
+    let body = &args[1..];
 
-    for arg in args.into_iter().skip(1) {
-        eval(arg, env)?;
-    }
+    if let Some((last, init)) = body.split_last() {
+        for arg in init {
+            eval(arg.clone(), env)?;
+        }
 
-    if let Some(last) = last_maybe {
-        Ok(EvalBehaviour::LoopWithAst(last))
+        Ok(EvalBehaviour::LoopWithAst(last.clone()))
     } else {
         Ok(EvalBehaviour::ReturnImmediately(Ast::Nil))
     }
 }
 
-fn do_form_if(mut args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
-    let condition = eval(args.remove(1), env)?;
-
-    let is_true = match condition {
-        Ast::Boolean(b) => b,
-        _ => true,
+/// `(and x ...)` evaluates the arguments until one is falsy and `(or x ...)` until one is truthy,
+/// returning its value. The last argument is left to the caller as a tail call. Without
+/// arguments `and` is true and `or` is nil.
+fn do_form_and_or(
+    args: &[Ast],
+    env: &Rc<RefCell<Environment>>,
+    is_and: bool,
+) -> Result<EvalBehaviour, ReplError> {
+    let Some((last, init)) = args[1..].split_last() else {
+        let empty = if is_and { Ast::Boolean(true) } else { Ast::Nil };
+        return Ok(EvalBehaviour::ReturnImmediately(empty));
     };
 
-    Ok(if is_true {
-        args.remove(1)
+    for arg in init {
+        let value = eval(arg.clone(), env)?;
+        if is_truthy(&value) != is_and {
+            return Ok(EvalBehaviour::ReturnImmediately(value));
+        }
+    }
+    Ok(EvalBehaviour::LoopWithAst(last.clone()))
+}
+
+/// Only `false` and `nil` count as false in conditions, every other value is true
+pub fn is_truthy(ast: &Ast) -> bool {
+    !matches!(ast, Ast::Boolean(false) | Ast::Nil)
+}
+
+fn do_form_if(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if !(3..=4).contains(&args.len()) {
+        return Err(RuntimeError::MalformedForm(
+            "if".to_owned(),
+            Ast::List(Rc::new(args.to_vec())),
+        )
+        .into());
+    }
+    let condition = eval(args[1].clone(), env)?;
+
+    Ok(if is_truthy(&condition) {
+        args[2].clone()
     } else {
-        args.remove(2)
+        args.get(3).cloned().unwrap_or(Ast::Nil)
     })
 }
 
-fn eval_form_fun(mut args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
-    let body = args.pop().unwrap();
-    let params = get_symbol_list(args.pop().unwrap())?;
-    let fun = Ast::Function(Box::new(UserFunction {
-        params,
+/// Whether a test of `cond` is the `else` that always matches
+pub fn is_else(test: &Ast) -> bool {
+    matches!(test, Ast::Symbol(s) if s == "else")
+}
+
+/// `(cond test expr ... else expr)` evaluates the tests in order and returns the expression
+/// following the first truthy one, or nil if there is none
+fn do_form_cond(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    let clauses = &args[1..];
+    if !clauses.len().is_multiple_of(2) {
+        return Err(RuntimeError::MalformedForm(
+            "cond".to_owned(),
+            Ast::List(Rc::new(args.to_vec())),
+        )
+        .into());
+    }
+
+    for clause in clauses.chunks(2) {
+        if is_else(&clause[0]) || is_truthy(&eval(clause[0].clone(), env)?) {
+            return Ok(clause[1].clone());
+        }
+    }
+    Ok(Ast::Nil)
+}
+
+fn eval_form_fun(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_form(args, 3, "fun*")?;
+    let params = get_symbol_list(&args[1], "fun* parameters")?;
+    let (params, variadic) = split_rest_param(params, &args[1])?;
+    let body = resolve_function_body(&params, args[2].clone(), env);
+    let fun = Ast::Function(Rc::new(UserFunction {
+        params: Rc::new(params),
         body,
         env: Rc::clone(env),
+        source: Ast::List(Rc::new(args.to_vec())),
+        is_macro: false,
+        variadic,
     }));
     Ok(fun)
 }
 
+/// Turns the parameters `a & rest` into `a rest` and whether there is a rest parameter, which
+/// must be the only one after the `&`
+fn split_rest_param(
+    mut params: Vec<String>,
+    written: &Ast,
+) -> Result<(Vec<String>, bool), ReplError> {
+    let Some(i) = params.iter().position(|p| p == "&") else {
+        return Ok((params, false));
+    };
+    if i + 2 != params.len() || params[i + 1] == "&" {
+        return Err(
+            RuntimeError::MalformedForm("fun* parameters".to_owned(), written.clone()).into(),
+        );
+    }
+
+    params.remove(i);
+    Ok((params, true))
+}
+
+/// `(bench expr n)` evaluates `expr` n times after a warmup of n/10 runs and returns the
+/// minimum, mean and maximum run time in nanoseconds
+fn eval_form_bench(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_form(args, 3, "bench")?;
+    let runs = match eval(args[2].clone(), env)? {
+        Ast::Integer(n) if n > 0 => n as u32,
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                "bench".to_owned(),
+                2,
+                "positive Integer".to_owned(),
+                other,
+            )
+            .into())
+        }
+    };
+
+    for _ in 0..runs.div_ceil(10) {
+        eval(args[1].clone(), env)?;
+    }
+
+    let mut timings = Vec::with_capacity(runs as usize);
+    for _ in 0..runs {
+        let start = Instant::now();
+        eval(args[1].clone(), env)?;
+        timings.push(start.elapsed());
+    }
+
+    let min = timings.iter().min().unwrap().as_nanos();
+    let max = timings.iter().max().unwrap().as_nanos();
+    let mean = (timings.iter().sum::<Duration>() / runs).as_nanos();
+
+    let nanos = |n: u128| Ast::Integer(n as i64);
+    Ok(keyword_map(vec![
+        ("min-ns", nanos(min)),
+        ("mean-ns", nanos(mean)),
+        ("max-ns", nanos(max)),
+    ]))
+}
+
+/// `(time expr)` evaluates `expr`, prints the elapsed time to stderr and returns the result
+fn eval_form_time(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_form(args, 2, "time")?;
+
+    let start = Instant::now();
+    let result = eval(args[1].clone(), env)?;
+    write_to(ERR, &format!("Elapsed time: {:?}\n", start.elapsed()), env)?;
+
+    Ok(result)
+}
+
+/// `(doseq (x coll) body)` evaluates the body with `x` bound to each element of a list or lazy
+/// sequence in turn, and returns nil
+fn eval_form_doseq(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_form(args, 3, "doseq")?;
+    let (name, coll) = match &args[1] {
+        Ast::List(binding) if binding.len() == 2 => {
+            (get_symbol_name(&binding[0], "doseq")?, binding[1].clone())
+        }
+        _ => {
+            return Err(RuntimeError::MalformedForm(
+                "doseq".to_owned(),
+                Ast::List(Rc::new(args.to_vec())),
+            )
+            .into())
+        }
+    };
+
+    let coll = eval(coll, env)?;
+    if !is_iterable(&coll) {
+        return Err(RuntimeError::TypeMismatch(
+            "doseq".to_owned(),
+            1,
+            "List or Seq".to_owned(),
+            coll,
+        )
+        .into());
+    }
+
+    let names = Rc::new(vec![name]);
+    for_each(&coll, |x| {
+        let frame = Environment::frame(Rc::clone(&names), vec![x], env);
+        eval(args[2].clone(), &Rc::new(RefCell::new(frame)))?;
+        Ok(true)
+    })?;
+    Ok(Ast::Nil)
+}
+
+/// `(with-env e body...)` evaluates the body in the environment `e` made by `fork-env`, so it
+/// sees and defines the bindings of that environment instead of the surrounding ones
+fn do_form_with_env(
+    args: &[Ast],
+    env: &Rc<RefCell<Environment>>,
+) -> Result<EvalBehaviour, ReplError> {
+    if args.len() < 2 {
+        return Err(RuntimeError::MalformedForm(
+            "with-env".to_owned(),
+            Ast::List(Rc::new(args.to_vec())),
+        )
+        .into());
+    }
+
+    let target = match eval(args[1].clone(), env)? {
+        Ast::Env(target) => target,
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                "with-env".to_owned(),
+                1,
+                "Environment".to_owned(),
+                other,
+            )
+            .into())
+        }
+    };
+
+    match args[2..].split_last() {
+        Some((last, init)) => {
+            eval_body(init, &target)?;
+            Ok(EvalBehaviour::LoopWithAstAndEnv(last.clone(), target))
+        }
+        None => Ok(EvalBehaviour::ReturnImmediately(Ast::Nil)),
+    }
+}
+
+/// `(try* expr (catch* e handler))` evaluates `expr`; if that fails, `handler` is evaluated with
+/// `e` bound to the value given to `throw`, or to a description of the error made by
+/// `ReplError::to_value`. Without a `catch*` clause errors are passed on.
+fn do_form_try(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<EvalBehaviour, ReplError> {
+    let malformed =
+        || RuntimeError::MalformedForm("try*".to_owned(), Ast::List(Rc::new(args.to_vec())));
+    let (name, handler) = match args {
+        [_, _] => return Ok(EvalBehaviour::LoopWithAst(args[1].clone())),
+        [_, _, Ast::List(clause)] => match clause.as_slice() {
+            [Ast::Symbol(head), name, handler] if head == "catch*" => {
+                (get_symbol_name(name, "catch*")?, handler.clone())
+            }
+            _ => return Err(malformed().into()),
+        },
+        _ => return Err(malformed().into()),
+    };
+
+    let err = match eval(args[1].clone(), env) {
+        Ok(value) => return Ok(EvalBehaviour::ReturnImmediately(value)),
+        Err(err) => err,
+    };
+    let value = match err {
+        ReplError::Thrown(value) => {
+            take_error_backtrace();
+            value
+        }
+        err => err.to_value(),
+    };
+
+    let frame = Environment::frame(Rc::new(vec![name]), vec![value], env);
+    Ok(EvalBehaviour::LoopWithAstAndEnv(
+        handler,
+        Rc::new(RefCell::new(frame)),
+    ))
+}
+
+/// Evaluates forms in order and returns the value of the last one, or nil if there are none
+fn eval_body(forms: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    let mut result = Ast::Nil;
+    for form in forms {
+        result = eval(form.clone(), env)?;
+    }
+    Ok(result)
+}
+
+/// `(with-out port body...)` evaluates the body with `*out*` bound to the port
+fn eval_form_with_out(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.len() < 2 {
+        return Err(RuntimeError::MalformedForm(
+            "with-out".to_owned(),
+            Ast::List(Rc::new(args.to_vec())),
+        )
+        .into());
+    }
+
+    let port = match eval(args[1].clone(), env)? {
+        Ast::Port(port) => port,
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                "with-out".to_owned(),
+                1,
+                "Port".to_owned(),
+                other,
+            )
+            .into())
+        }
+    };
+    with_port(OUT, port, env, || eval_body(&args[2..], env))
+}
+
+/// `(with-out-file path body...)` evaluates the body with `*out*` bound to a new file at `path`,
+/// which is closed afterwards
+fn eval_form_with_out_file(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if !cfg!(feature = "io") || !with_options(|options| options.builtin_groups.io) {
+        return Err(
+            RuntimeError::GroupDisabled("with-out-file".to_owned(), "io".to_owned()).into(),
+        );
+    }
+    if args.len() < 2 {
+        return Err(RuntimeError::MalformedForm(
+            "with-out-file".to_owned(),
+            Ast::List(Rc::new(args.to_vec())),
+        )
+        .into());
+    }
+
+    let path = match eval(args[1].clone(), env)? {
+        Ast::String(path) => path,
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                "with-out-file".to_owned(),
+                1,
+                "String".to_owned(),
+                other,
+            )
+            .into())
+        }
+    };
+    let port = Rc::new(OutputPort::open_file(&path)?);
+    let result = with_port(OUT, Rc::clone(&port), env, || eval_body(&args[2..], env));
+    port.close()?;
+    result
+}
+
+/// `(with-out-str body...)` evaluates the body and returns what it printed instead of printing it
+fn eval_form_with_out_str(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    let port = Rc::new(OutputPort::buffer());
+    with_port(OUT, Rc::clone(&port), env, || eval_body(&args[1..], env))?;
+    Ok(Ast::String(port.contents().unwrap_or_default()))
+}
+
+/// `(some-> x (f a) g)` calls `(f x a)`, then `(g <result>)`, stopping with nil as soon as a
+/// value is nil
+fn eval_form_some_thread(args: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.len() < 2 {
+        return Err(RuntimeError::MalformedForm(
+            "some->".to_owned(),
+            Ast::List(Rc::new(args.to_vec())),
+        )
+        .into());
+    }
+
+    let mut value = eval(args[1].clone(), env)?;
+    for step in &args[2..] {
+        if let Ast::Nil = value {
+            return Ok(Ast::Nil);
+        }
+
+        let (f, rest) = match step {
+            Ast::List(xs) if !xs.is_empty() => (&xs[0], &xs[1..]),
+            other => (other, &[][..]),
+        };
+        let fun = Callable::from_ast(eval(f.clone(), env)?)?;
+        let mut step_args = vec![value];
+        step_args.extend(eval_all(rest, env)?);
+        value = fun.call(step_args, env)?;
+    }
+
+    Ok(value)
+}
+
 fn eval_func_call(
-    mut xs: Vec<Ast>,
+    xs: &Rc<Vec<Ast>>,
     env: &Rc<RefCell<Environment>>,
+    scope: &CallScope,
 ) -> Result<EvalBehaviour, ReplError> {
-    let fun_ast = xs.remove(0);
-    let fun = eval(fun_ast, env)?;
-    let args = eval_all(xs, env)?;
-
-    match fun {
-        Ast::Function(fun_box) => {
-            let user_fun = fun_box;
-            Ok(EvalBehaviour::LoopWithAstAndEnv(
-                user_fun.body,
-                Rc::new(RefCell::new(bind_fn(&user_fun.params, args, &user_fun.env))),
-            ))
+    if let Ast::Keyword(k) = &xs[0] {
+        let args = eval_all(&xs[1..], env)?;
+        return Ok(EvalBehaviour::ReturnImmediately(keyword_access(k, args)?));
+    }
+
+    let fun = match eval(xs[0].clone(), env)? {
+        Ast::Function(f) if f.is_macro => {
+            let expansion = Callable::User(f).call(xs[1..].to_vec(), env)?;
+            return Ok(EvalBehaviour::LoopWithAst(expansion));
         }
-        Ast::Builtin(name, cb) => Ok(EvalBehaviour::ReturnImmediately(cb(&name, args)?)),
-        _ => todo!("error: attempted to call non-function"),
+        other => Callable::from_ast(other)?,
+    };
+    let args = eval_all(&xs[1..], env)?;
+
+    scope.enter(xs)?;
+    let behaviour = fun.prepare(args, env)?;
+    if let EvalBehaviour::LoopWithAstAndEnv(_, frame) = &behaviour {
+        scope.bind_arguments(frame);
+    }
+    Ok(behaviour)
+}
+
+/// `(:key coll default?)` looks up the entry `(:key value)` or `("key" value)` of an association
+/// list, or the key `:key` or `"key"` of a hash-map, and returns its value, or the default (nil if
+/// not given) when there is none
+fn keyword_access(name: &str, args: Vec<Ast>) -> Result<Ast, RuntimeError> {
+    let keyword = format!(":{}", name);
+    if args.is_empty() || args.len() > 2 {
+        return Err(RuntimeError::WrongArity(keyword, 1, args.len()));
     }
+
+    let mut args = args.into_iter();
+    let coll = args.next().unwrap();
+    let default = args.next().unwrap_or(Ast::Nil);
+
+    let entries = match coll {
+        Ast::List(entries) => entries,
+        Ast::HashMap(map) => {
+            let value = map
+                .get(&MapKey::Keyword(name.to_owned()))
+                .or_else(|| map.get(&MapKey::String(name.to_owned())));
+            return Ok(value.cloned().unwrap_or(default));
+        }
+        Ast::Nil => return Ok(default),
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                keyword,
+                1,
+                "association list or HashMap".to_owned(),
+                other,
+            ))
+        }
+    };
+
+    let value = assoc_lookup(&entries, &Ast::Keyword(name.to_owned()))
+        .or_else(|| assoc_lookup(&entries, &Ast::String(name.to_owned())));
+    Ok(value.unwrap_or(default))
 }
 
 fn eval_symbol(s: String, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
@@ -169,74 +874,108 @@ fn eval_symbol(s: String, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplErr
 }
 
 // todo move into enum impl?
-pub fn bind_fn(params: &[String], args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Environment {
-    let mut values = HashMap::new();
-    // todo check params length against args length
-    for (name, ast) in zip(params, args) {
-        values.insert(name.clone(), ast);
+pub fn bind_fn(
+    params: &Rc<Vec<String>>,
+    variadic: bool,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Environment, RuntimeError> {
+    if variadic {
+        let required = params.len() - 1;
+        if args.len() < required {
+            return Err(RuntimeError::WrongArity(
+                "<function>".to_owned(),
+                required,
+                args.len(),
+            ));
+        }
+        let rest = args.split_off(required);
+        args.push(Ast::List(Rc::new(rest)));
+    } else if params.len() != args.len() {
+        return Err(RuntimeError::WrongArity(
+            "<function>".to_owned(),
+            params.len(),
+            args.len(),
+        ));
     }
 
-    Environment {
-        values,
-        parent: Some(Rc::clone(env)),
-    }
+    Ok(Environment::frame(Rc::clone(params), args, env))
 }
 
-fn eval_all(xs: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Vec<Ast>, ReplError> {
-    let mut values = vec![];
+fn eval_all(xs: &[Ast], env: &Rc<RefCell<Environment>>) -> Result<Vec<Ast>, ReplError> {
+    let mut values = Vec::with_capacity(xs.len());
     for x in xs {
-        values.push(eval(x, env)?);
+        values.push(eval(x.clone(), env)?);
     }
     Ok(values)
 }
 
-fn get_symbol_list(ast: Ast) -> Result<Vec<String>, ReplError> {
+fn get_symbol_list(ast: &Ast, construct: &str) -> Result<Vec<String>, ReplError> {
     if let Ast::List(xs) = ast {
         let mut result = vec![];
-        for x in xs {
-            result.push(get_symbol_name(x)?);
+        for x in xs.iter() {
+            result.push(get_symbol_name(x, construct)?);
         }
 
         Ok(result)
     } else {
-        todo!("Error: not a list")
+        Err(RuntimeError::ExpectedList(construct.to_owned(), ast.clone()).into())
     }
 }
 
+/// Creates the frame of a binding form. Values are evaluated in order; unless `parallel` is set,
+/// each one can already see the bindings before it.
 fn bind_let(
-    ast: Ast,
+    ast: &Ast,
     env: &Rc<RefCell<Environment>>,
-    rec: bool,
+    construct: &str,
+    parallel: bool,
 ) -> Result<Rc<RefCell<Environment>>, ReplError> {
-    let n_env = Rc::new(RefCell::new(Environment {
-        values: HashMap::new(),
-        parent: Some(env.clone()),
-    }));
-
     let xs = match ast {
         Ast::List(xs) => xs,
-        _ => todo!("error"),
+        other => {
+            return Err(RuntimeError::ExpectedList(construct.to_owned(), other.clone()).into())
+        }
     };
 
-    let mut symbol = "".to_owned();
-    let mut get_sym = true;
-    for x in xs {
-        if get_sym {
-            symbol = get_symbol_name(x)?;
-            get_sym = false;
-        } else {
-            let v = eval(x, if rec { &n_env } else { env })?;
-            n_env.borrow_mut().values.insert(symbol.clone(), v);
-            get_sym = true;
-        }
+    if xs.len() % 2 != 0 {
+        warn(Warning::OddBindingList(construct.to_owned(), ast.clone()));
+    }
+
+    let mut names = vec![];
+    for symbol in xs.iter().step_by(2).take(xs.len() / 2) {
+        names.push(get_symbol_name(symbol, construct)?);
+    }
+
+    let n_env = Rc::new(RefCell::new(Environment::frame(
+        Rc::new(names),
+        Vec::with_capacity(xs.len() / 2),
+        env,
+    )));
+
+    for expr in xs.iter().skip(1).step_by(2) {
+        let v = eval(expr.clone(), if parallel { env } else { &n_env })?;
+        n_env.borrow_mut().slots.push(v);
     }
 
     Ok(n_env)
 }
 
-fn get_symbol_name(ast: Ast) -> Result<String, ReplError> {
+fn get_symbol_name(ast: &Ast, construct: &str) -> Result<String, ReplError> {
     match ast {
-        Ast::Symbol(s) => Ok(s),
-        _ => Err(ReplError::ParserError(ParserError::ExpectedSymbol)),
+        Ast::Symbol(s) => Ok(s.clone()),
+        other => Err(RuntimeError::ExpectedSymbol(construct.to_owned(), other.clone()).into()),
+    }
+}
+
+/// Checks the number of elements of a special form, including its name
+fn check_form(xs: &[Ast], expected: usize, construct: &str) -> Result<(), RuntimeError> {
+    if xs.len() == expected {
+        Ok(())
+    } else {
+        Err(RuntimeError::MalformedForm(
+            construct.to_owned(),
+            Ast::List(Rc::new(xs.to_vec())),
+        ))
     }
 }
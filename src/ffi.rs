@@ -0,0 +1,362 @@
+//! C interface for embedding the interpreter, declared in `include/beesting.h`.
+//!
+//! Interpreters and values are handed out as pointers that the caller frees with
+//! `bst_interpreter_free` and `bst_value_free`. An interpreter and the values it produced must
+//! only be used on the thread that created the interpreter.
+
+use crate::callstack::set_max_call_depth;
+use crate::errors::{ReplError, RuntimeError};
+use crate::interpreter::Interpreter;
+use crate::parser::{Ast, Closure};
+use crate::printer::pr_str;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+use std::rc::Rc;
+
+pub const BST_NIL: c_int = 0;
+pub const BST_BOOLEAN: c_int = 1;
+pub const BST_INTEGER: c_int = 2;
+pub const BST_STRING: c_int = 3;
+pub const BST_LIST: c_int = 4;
+/// Any other value, e.g. a function, which can only be printed
+pub const BST_OTHER: c_int = 5;
+/// Failed evaluation, `bst_value_error` returns the message
+pub const BST_ERROR: c_int = 6;
+pub const BST_FLOAT: c_int = 7;
+
+/// Result of an evaluation or an argument of a callback
+pub struct BstValue {
+    value: Result<Ast, String>,
+    /// Text returned by `bst_value_string` or `bst_value_error`, kept so the pointer stays valid
+    /// as long as the value
+    text: Option<CString>,
+}
+
+impl BstValue {
+    fn new(value: Result<Ast, String>) -> *mut BstValue {
+        let text = match &value {
+            Ok(Ast::String(s)) => CString::new(s.as_str()).ok(),
+            Ok(_) => None,
+            Err(message) => CString::new(message.replace('\0', " ")).ok(),
+        };
+        Box::into_raw(Box::new(BstValue { value, text }))
+    }
+}
+
+/// Function called when a builtin registered with `bst_register` is called. Returns a new value,
+/// or a value made with `bst_value_new_error` to fail the call; the interpreter frees it.
+pub type BstCallback = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    args: *const *const BstValue,
+    argc: usize,
+) -> *mut BstValue;
+
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+unsafe fn value_ref<'a>(value: *const BstValue) -> Option<&'a BstValue> {
+    value.as_ref()
+}
+
+#[no_mangle]
+pub extern "C" fn bst_interpreter_new() -> *mut Interpreter {
+    Box::into_raw(Box::new(Interpreter::new()))
+}
+
+/// # Safety
+/// `interpreter` must come from `bst_interpreter_new` and not have been freed, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn bst_interpreter_free(interpreter: *mut Interpreter) {
+    if !interpreter.is_null() {
+        drop(Box::from_raw(interpreter));
+    }
+}
+
+/// Evaluation recurses on the calling thread's stack, so threads with small stacks should lower
+/// the call depth limit to fail with an error instead of overflowing.
+#[no_mangle]
+pub extern "C" fn bst_set_max_call_depth(depth: usize) {
+    set_max_call_depth(depth);
+}
+
+/// Evaluates all forms of the code and returns the value of the last one, or an error value.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter and `code` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bst_eval(
+    interpreter: *mut Interpreter,
+    code: *const c_char,
+) -> *mut BstValue {
+    let Some(interpreter) = interpreter.as_ref() else {
+        return BstValue::new(Err("No interpreter".to_owned()));
+    };
+    let Some(code) = to_str(code) else {
+        return BstValue::new(Err("Code isn't valid UTF-8".to_owned()));
+    };
+
+    let result = crate::parser::read_all(code)
+        .map_err(|err| ReplError::from(err).with_source(code))
+        .and_then(|forms| {
+            let mut result = Ast::Nil;
+            for form in forms {
+                result = interpreter.eval(form)?;
+            }
+            Ok(result)
+        });
+    BstValue::new(result.map_err(|err| format!("{:?}", err)))
+}
+
+/// Binds `name` in the root environment to a builtin that calls `callback` with `user_data` and
+/// the arguments. Returns 0, or -1 if the name isn't valid UTF-8.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter and `name` a NUL-terminated string. `callback` is
+/// called with `user_data` for as long as the builtin is reachable.
+#[no_mangle]
+pub unsafe extern "C" fn bst_register(
+    interpreter: *mut Interpreter,
+    name: *const c_char,
+    callback: BstCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    let (Some(interpreter), Some(name)) = (interpreter.as_ref(), to_str(name)) else {
+        return -1;
+    };
+
+    let fn_name = name.to_owned();
+    let f = move |args: Vec<Ast>, _: &_| -> Result<Ast, ReplError> {
+        let args: Vec<*mut BstValue> = args.into_iter().map(|x| BstValue::new(Ok(x))).collect();
+        let arg_ptrs: Vec<*const BstValue> = args.iter().map(|&x| x as *const _).collect();
+        let result = callback(user_data, arg_ptrs.as_ptr(), arg_ptrs.len());
+        for arg in args {
+            drop(Box::from_raw(arg));
+        }
+
+        if result.is_null() {
+            return Ok(Ast::Nil);
+        }
+        let result = Box::from_raw(result);
+        result
+            .value
+            .map_err(|message| RuntimeError::Foreign(fn_name.clone(), message).into())
+    };
+
+    let closure = Ast::Closure(Rc::new(Closure {
+        name: name.to_owned(),
+        f: Box::new(f),
+    }));
+    interpreter
+        .root_env()
+        .borrow_mut()
+        .set_local(name.to_owned(), closure);
+    0
+}
+
+/// # Safety
+/// `value` must come from this interface and not have been freed, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_free(value: *mut BstValue) {
+    if !value.is_null() {
+        drop(Box::from_raw(value));
+    }
+}
+
+/// One of the `BST_*` type constants
+///
+/// # Safety
+/// `value` must be a live value.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_type(value: *const BstValue) -> c_int {
+    match value_ref(value).map(|v| &v.value) {
+        None | Some(Ok(Ast::Nil)) => BST_NIL,
+        Some(Ok(Ast::Boolean(_))) => BST_BOOLEAN,
+        Some(Ok(Ast::Integer(_))) => BST_INTEGER,
+        Some(Ok(Ast::Float(_))) => BST_FLOAT,
+        Some(Ok(Ast::String(_))) => BST_STRING,
+        Some(Ok(Ast::List(_))) => BST_LIST,
+        Some(Ok(_)) => BST_OTHER,
+        Some(Err(_)) => BST_ERROR,
+    }
+}
+
+/// Stores an integer or boolean (as 0 or 1) in `out` and returns 0, or returns -1 for other
+/// values
+///
+/// # Safety
+/// `value` must be a live value and `out` point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_int(value: *const BstValue, out: *mut i64) -> c_int {
+    let n = match value_ref(value).map(|v| &v.value) {
+        Some(Ok(Ast::Integer(n))) => *n,
+        Some(Ok(Ast::Boolean(b))) => *b as i64,
+        _ => return -1,
+    };
+    if out.is_null() {
+        return -1;
+    }
+    *out = n;
+    0
+}
+
+/// Stores a float or integer (converted) in `out` and returns 0, or returns -1 for other values
+///
+/// # Safety
+/// `value` must be a live value and `out` point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_float(value: *const BstValue, out: *mut f64) -> c_int {
+    let x = match value_ref(value).map(|v| &v.value) {
+        Some(Ok(Ast::Float(x))) => *x,
+        Some(Ok(Ast::Integer(n))) => *n as f64,
+        _ => return -1,
+    };
+    if out.is_null() {
+        return -1;
+    }
+    *out = x;
+    0
+}
+
+/// Text of a string value, valid until the value is freed. Null for other values and for strings
+/// containing NUL characters.
+///
+/// # Safety
+/// `value` must be a live value.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_string(value: *const BstValue) -> *const c_char {
+    match value_ref(value) {
+        Some(BstValue {
+            value: Ok(Ast::String(_)),
+            text: Some(text),
+        }) => text.as_ptr(),
+        _ => ptr::null(),
+    }
+}
+
+/// Message of an error value, valid until the value is freed. Null for other values.
+///
+/// # Safety
+/// `value` must be a live value.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_error(value: *const BstValue) -> *const c_char {
+    match value_ref(value) {
+        Some(BstValue {
+            value: Err(_),
+            text: Some(text),
+        }) => text.as_ptr(),
+        _ => ptr::null(),
+    }
+}
+
+/// Number of elements of a list, 0 for other values
+///
+/// # Safety
+/// `value` must be a live value.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_list_len(value: *const BstValue) -> usize {
+    match value_ref(value).map(|v| &v.value) {
+        Some(Ok(Ast::List(xs))) => xs.len(),
+        _ => 0,
+    }
+}
+
+/// New value holding an element of a list, or null if there is no such element
+///
+/// # Safety
+/// `value` must be a live value.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_list_get(value: *const BstValue, index: usize) -> *mut BstValue {
+    match value_ref(value).map(|v| &v.value) {
+        Some(Ok(Ast::List(xs))) if index < xs.len() => BstValue::new(Ok(xs[index].clone())),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// The value as the REPL would print it readably. Free the string with `bst_string_free`.
+///
+/// # Safety
+/// `value` must be a live value.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_print(value: *const BstValue) -> *mut c_char {
+    let text = match value_ref(value).map(|v| &v.value) {
+        Some(Ok(ast)) => pr_str(ast, true),
+        Some(Err(message)) => message.clone(),
+        None => "nil".to_owned(),
+    };
+    CString::new(text.replace('\0', " ")).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// # Safety
+/// `s` must come from `bst_value_print` and not have been freed, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn bst_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn bst_value_new_nil() -> *mut BstValue {
+    BstValue::new(Ok(Ast::Nil))
+}
+
+#[no_mangle]
+pub extern "C" fn bst_value_new_int(n: i64) -> *mut BstValue {
+    BstValue::new(Ok(Ast::Integer(n)))
+}
+
+#[no_mangle]
+pub extern "C" fn bst_value_new_float(x: f64) -> *mut BstValue {
+    BstValue::new(Ok(Ast::Float(x)))
+}
+
+#[no_mangle]
+pub extern "C" fn bst_value_new_bool(b: c_int) -> *mut BstValue {
+    BstValue::new(Ok(Ast::Boolean(b != 0)))
+}
+
+/// New string value, or an error value if `s` isn't valid UTF-8
+///
+/// # Safety
+/// `s` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_new_string(s: *const c_char) -> *mut BstValue {
+    match to_str(s) {
+        Some(s) => BstValue::new(Ok(Ast::String(s.to_owned()))),
+        None => BstValue::new(Err("String isn't valid UTF-8".to_owned())),
+    }
+}
+
+/// New list of the given values, which are copied and stay owned by the caller
+///
+/// # Safety
+/// `items` must point to `len` live values.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_new_list(
+    items: *const *const BstValue,
+    len: usize,
+) -> *mut BstValue {
+    let mut xs = Vec::with_capacity(len);
+    for i in 0..len {
+        match value_ref(*items.add(i)).map(|v| &v.value) {
+            Some(Ok(x)) => xs.push(x.clone()),
+            Some(Err(message)) => return BstValue::new(Err(message.clone())),
+            None => xs.push(Ast::Nil),
+        }
+    }
+    BstValue::new(Ok(Ast::List(Rc::new(xs))))
+}
+
+/// Error value, which makes a callback returning it fail with the message
+///
+/// # Safety
+/// `message` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bst_value_new_error(message: *const c_char) -> *mut BstValue {
+    let message = to_str(message).unwrap_or("Unknown error").to_owned();
+    BstValue::new(Err(message))
+}
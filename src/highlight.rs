@@ -0,0 +1,101 @@
+use crate::lexer::{get_token, Token};
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[36m";
+const KEYWORD: &str = "\x1b[35m";
+/// Parens cycle through these colors by nesting depth
+const PAREN_COLORS: [&str; 6] = [
+    "\x1b[33m", "\x1b[34m", "\x1b[31m", "\x1b[36m", "\x1b[32m", "\x1b[35m",
+];
+
+/// Adds ANSI colors to a line of source: parens are colored by depth, strings, numbers and
+/// keywords are highlighted and comments are dimmed. Works on unfinished input as well.
+pub fn highlight(line: &str) -> String {
+    let mut result = String::new();
+    let mut depth = 0usize;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => {
+                paint(
+                    &mut result,
+                    PAREN_COLORS[depth % PAREN_COLORS.len()],
+                    &c.to_string(),
+                );
+                depth += 1;
+            }
+            ')' | ']' | '}' => {
+                depth = depth.saturating_sub(1);
+                paint(
+                    &mut result,
+                    PAREN_COLORS[depth % PAREN_COLORS.len()],
+                    &c.to_string(),
+                );
+            }
+            '"' => {
+                let delimiter = if chars.clone().take(2).eq("\"\"".chars()) {
+                    chars.nth(1);
+                    "\"\"\""
+                } else {
+                    "\""
+                };
+
+                let mut string = String::from(delimiter);
+                while let Some(c) = chars.next() {
+                    string.push(c);
+                    if c == '\\' && delimiter == "\"" {
+                        string.extend(chars.next());
+                    } else if string.len() >= 2 * delimiter.len() && string.ends_with(delimiter) {
+                        break;
+                    }
+                }
+                paint(&mut result, STRING, &string);
+            }
+            ';' => {
+                let comment: String = std::iter::once(c).chain(chars.by_ref()).collect();
+                let trimmed = comment.trim_end_matches(['\r', '\n']);
+                paint(&mut result, DIM, trimmed);
+                result.push_str(&comment[trimmed.len()..]);
+            }
+            c if c.is_whitespace() => result.push(c),
+            c => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace()
+                        || matches!(c, '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+                    {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+
+                match word_color(&word) {
+                    Some(color) => paint(&mut result, color, &word),
+                    None => result.push_str(&word),
+                }
+            }
+        }
+    }
+
+    result
+}
+
+fn word_color(word: &str) -> Option<&'static str> {
+    if matches!(get_token(word), Token::Integer(_) | Token::Float(_)) {
+        Some(NUMBER)
+    } else if word.starts_with(':') && word.len() > 1 {
+        Some(KEYWORD)
+    } else {
+        None
+    }
+}
+
+fn paint(result: &mut String, color: &str, text: &str) {
+    result.push_str(color);
+    result.push_str(text);
+    result.push_str(RESET);
+}
@@ -0,0 +1,102 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Number of entries kept when the history is saved
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// REPL input history, persisted to a file across sessions. The file has one entry per line,
+/// newlines inside an entry are written as `\n` and backslashes as `\\`.
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+    max_size: usize,
+}
+
+impl History {
+    /// Location used when no other file is configured: `$BEESTING_HISTORY`, otherwise
+    /// `~/.beesting_history`
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("BEESTING_HISTORY") {
+            return Some(PathBuf::from(path));
+        }
+
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".beesting_history"))
+    }
+
+    /// Reads the history from the file, a missing file gives an empty history. Without a path the
+    /// history is only kept in memory.
+    pub fn load(path: Option<PathBuf>, max_size: usize) -> io::Result<History> {
+        let mut history = History {
+            path,
+            entries: Vec::new(),
+            max_size,
+        };
+
+        if let Some(path) = &history.path {
+            match fs::read_to_string(path) {
+                Ok(content) => content
+                    .lines()
+                    .for_each(|line| history.add(&unescape(line))),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// Records an input line. Blank lines are ignored and an earlier identical entry is dropped,
+    /// so each input only appears once, at its latest position.
+    pub fn add(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+
+        self.entries.retain(|entry| entry != line);
+        self.entries.push(line.to_owned());
+        if self.entries.len() > self.max_size {
+            self.entries.drain(..self.entries.len() - self.max_size);
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => {
+                let escaped: Vec<String> = self.entries.iter().map(|e| escape(e)).collect();
+                let mut content = escaped.join("\n");
+                content.push('\n');
+                fs::write(path, content)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            _ => entry.push(c),
+        }
+    }
+    entry
+}
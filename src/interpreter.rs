@@ -0,0 +1,77 @@
+use crate::callstack::take_error_backtrace;
+use crate::errors::ReplError;
+use crate::eval::eval;
+use crate::memory::set_memory_limit;
+use crate::parser::Ast;
+use crate::root_env::{create_root_env_with, reset_random_and_clock, BuiltinGroups, Environment};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Clone, Default)]
+pub struct InterpreterOptions {
+    /// Makes `def!` of an already bound root symbol an error instead of overwriting it
+    pub strict: bool,
+    /// Pre-evaluates constant subexpressions of `def!` definitions
+    pub fold_constants: bool,
+    /// Heap usage in bytes above which evaluation fails, see `memory::CountingAllocator`
+    pub memory_limit: Option<usize>,
+    /// Makes `rand-int` use a fixed seed and `time-ms` count calls, for reproducible output
+    pub deterministic: bool,
+    /// Builtins available besides the core ones, e.g. `BuiltinGroups::core_only()` for code
+    /// that mustn't access files
+    pub builtin_groups: BuiltinGroups,
+}
+
+thread_local! {
+    static OPTIONS: RefCell<InterpreterOptions> = RefCell::new(InterpreterOptions::default());
+}
+
+/// Gives access to the options of the interpreter that is currently evaluating
+pub fn with_options<R>(f: impl FnOnce(&InterpreterOptions) -> R) -> R {
+    OPTIONS.with(|options| f(&options.borrow()))
+}
+
+pub struct Interpreter {
+    root_env: Rc<RefCell<Environment>>,
+    options: InterpreterOptions,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_options(InterpreterOptions::default())
+    }
+
+    pub fn with_options(options: InterpreterOptions) -> Interpreter {
+        reset_random_and_clock();
+        Interpreter {
+            root_env: Rc::new(RefCell::new(create_root_env_with(options.builtin_groups))),
+            options,
+        }
+    }
+
+    pub fn options(&self) -> &InterpreterOptions {
+        &self.options
+    }
+
+    pub fn root_env(&self) -> &Rc<RefCell<Environment>> {
+        &self.root_env
+    }
+
+    pub fn eval(&self, ast: Ast) -> Result<Ast, ReplError> {
+        OPTIONS.with(|options| *options.borrow_mut() = self.options.clone());
+        set_memory_limit(self.options.memory_limit);
+        // Discards the backtrace of an earlier error that was never caught
+        take_error_backtrace();
+        eval(ast, &self.root_env)
+    }
+
+    pub fn eval_str(&self, input: &str) -> Result<Ast, ReplError> {
+        self.eval(input.parse()?)
+    }
+}
@@ -0,0 +1,550 @@
+//! Jupyter kernel: evaluates notebook cells in one persistent interpreter. Speaks version 5.3
+//! of the Jupyter messaging protocol over the ZeroMQ sockets described by a connection file.
+
+use crate::complete::complete;
+use crate::diagnostics::take_warnings;
+use crate::errors::ReplError;
+use crate::hmac::{constant_time_eq, hmac_sha256_hex, sha256};
+use crate::interpreter::Interpreter;
+use crate::json::{parse, Json};
+use crate::lexer::needs_more_input;
+use crate::output::{with_port, OutputPort, ERR, OUT};
+use crate::parser::{read_all, Ast};
+use crate::printer::{pretty_print, PrintLimits, DEFAULT_WIDTH};
+use crate::zmtp::{bind, Connection, Message, SocketType};
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PROTOCOL_VERSION: &str = "5.3";
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// Ports and signing key from the connection file Jupyter passes to the kernel
+struct ConnectionInfo {
+    ip: String,
+    key: String,
+    shell_port: u16,
+    iopub_port: u16,
+    stdin_port: u16,
+    control_port: u16,
+    hb_port: u16,
+}
+
+impl ConnectionInfo {
+    fn read(path: &str) -> io::Result<ConnectionInfo> {
+        let json = parse(&fs::read_to_string(path)?).map_err(io::Error::other)?;
+        let text = |key: &str| {
+            json.get(key)
+                .and_then(Json::as_str)
+                .unwrap_or("")
+                .to_owned()
+        };
+        let port = |key: &str| {
+            json.get(key)
+                .and_then(Json::as_i64)
+                .and_then(|port| u16::try_from(port).ok())
+                .ok_or_else(|| io::Error::other(format!("Connection file lacks {}", key)))
+        };
+
+        if !matches!(text("transport").as_str(), "" | "tcp") {
+            return Err(io::Error::other("Only the tcp transport is supported"));
+        }
+        if !matches!(text("signature_scheme").as_str(), "" | "hmac-sha256") {
+            return Err(io::Error::other(
+                "Only hmac-sha256 signatures are supported",
+            ));
+        }
+
+        Ok(ConnectionInfo {
+            ip: match text("ip").as_str() {
+                "" => "127.0.0.1".to_owned(),
+                ip => ip.to_owned(),
+            },
+            key: text("key"),
+            shell_port: port("shell_port")?,
+            iopub_port: port("iopub_port")?,
+            stdin_port: port("stdin_port")?,
+            control_port: port("control_port")?,
+            hb_port: port("hb_port")?,
+        })
+    }
+}
+
+/// Message received on the shell or control socket
+struct Request {
+    connection: Arc<Connection>,
+    ids: Vec<Vec<u8>>,
+    header: Json,
+    content: Json,
+}
+
+impl Request {
+    fn msg_type(&self) -> &str {
+        self.header
+            .get("msg_type")
+            .and_then(Json::as_str)
+            .unwrap_or("")
+    }
+}
+
+struct Kernel {
+    interpreter: Interpreter,
+    key: Vec<u8>,
+    session: String,
+    execution_count: i64,
+    message_count: u64,
+    /// Subscribers of the iopub socket
+    subscribers: Arc<Mutex<Vec<Arc<Connection>>>>,
+}
+
+/// Serves the given connection file with `interpreter` until a client asks it to shut down
+pub fn run(connection_file: &str, interpreter: Interpreter) -> Result<(), ReplError> {
+    let info = ConnectionInfo::read(connection_file)?;
+    let (requests, incoming) = channel();
+
+    listen_for_requests(&info.ip, info.shell_port, requests.clone())?;
+    listen_for_requests(&info.ip, info.control_port, requests)?;
+
+    let subscribers = Arc::new(Mutex::new(vec![]));
+    let iopub = subscribers.clone();
+    bind(
+        &info.ip,
+        info.iopub_port,
+        SocketType::Pub,
+        move |connection, mut receiver| {
+            iopub.lock().unwrap().push(connection);
+            // Subscriptions are ignored, every subscriber gets every message
+            while receiver.recv().is_ok() {}
+        },
+    )?;
+
+    bind(
+        &info.ip,
+        info.hb_port,
+        SocketType::Rep,
+        |connection, mut receiver| {
+            while let Ok(message) = receiver.recv() {
+                if connection.send(&message).is_err() {
+                    break;
+                }
+            }
+        },
+    )?;
+
+    // Input requests aren't supported, but clients expect to be able to connect
+    bind(
+        &info.ip,
+        info.stdin_port,
+        SocketType::Router,
+        |_, mut receiver| {
+            while receiver.recv().is_ok() {}
+        },
+    )?;
+
+    let mut kernel = Kernel {
+        interpreter,
+        key: info.key.into_bytes(),
+        session: new_id(b"session"),
+        execution_count: 0,
+        message_count: 0,
+        subscribers,
+    };
+
+    while let Ok((connection, message)) = incoming.recv() {
+        let Some(request) = kernel.parse_request(connection, message) else {
+            continue;
+        };
+        // A reply that can't be sent only concerns that client, the kernel keeps serving
+        match kernel.handle(&request) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => eprintln!("Could not answer {}: {:?}", request.msg_type(), err),
+        }
+    }
+    Ok(())
+}
+
+fn listen_for_requests(
+    ip: &str,
+    port: u16,
+    requests: Sender<(Arc<Connection>, Message)>,
+) -> io::Result<()> {
+    let requests = Mutex::new(requests);
+    bind(
+        ip,
+        port,
+        SocketType::Router,
+        move |connection, mut receiver| {
+            let requests = requests.lock().unwrap().clone();
+            while let Ok(message) = receiver.recv() {
+                if requests.send((connection.clone(), message)).is_err() {
+                    break;
+                }
+            }
+        },
+    )
+}
+
+impl Kernel {
+    /// Splits a message into its parts, dropping it if it is malformed or wrongly signed
+    fn parse_request(&self, connection: Arc<Connection>, message: Message) -> Option<Request> {
+        let delimiter = message.iter().position(|frame| frame == DELIMITER)?;
+        let ids = message[..delimiter].to_vec();
+        let [signature, header, parent, metadata, content] =
+            message.get(delimiter + 1..delimiter + 6)?
+        else {
+            return None;
+        };
+
+        if !self.key.is_empty() {
+            let expected = hmac_sha256_hex(&self.key, &[header, parent, metadata, content]);
+            if !constant_time_eq(expected.as_bytes(), signature) {
+                return None;
+            }
+        }
+
+        Some(Request {
+            connection,
+            ids,
+            header: parse(std::str::from_utf8(header).ok()?).ok()?,
+            content: parse(std::str::from_utf8(content).ok()?).ok()?,
+        })
+    }
+
+    /// Answers a request, returns false once the kernel should shut down
+    fn handle(&mut self, request: &Request) -> Result<bool, ReplError> {
+        self.publish("status", &request.header, status("busy"));
+        // Clients wait for the kernel to go idle again, even if answering failed
+        let result = self.answer(request);
+        self.publish("status", &request.header, status("idle"));
+        result
+    }
+
+    fn answer(&mut self, request: &Request) -> Result<bool, ReplError> {
+        let mut keep_running = true;
+
+        match request.msg_type() {
+            "kernel_info_request" => self.reply(request, "kernel_info_reply", kernel_info())?,
+            "execute_request" => self.execute(request)?,
+            "is_complete_request" => {
+                let code = request
+                    .content
+                    .get("code")
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                let state = if needs_more_input(code) {
+                    "incomplete"
+                } else {
+                    "complete"
+                };
+                let mut content = status(state);
+                if let Json::Object(entries) = &mut content {
+                    entries.push(("indent".to_owned(), Json::string("")));
+                }
+                self.reply(request, "is_complete_reply", content)?
+            }
+            "complete_request" => {
+                let content = self.complete(&request.content);
+                self.reply(request, "complete_reply", content)?
+            }
+            "comm_info_request" => self.reply(
+                request,
+                "comm_info_reply",
+                Json::object(vec![
+                    ("status", Json::string("ok")),
+                    ("comms", Json::Object(vec![])),
+                ]),
+            )?,
+            // Evaluation can't be interrupted, but clients wait for an answer
+            "interrupt_request" => self.reply(request, "interrupt_reply", status("ok"))?,
+            "shutdown_request" => {
+                let restart = request
+                    .content
+                    .get("restart")
+                    .cloned()
+                    .unwrap_or(Json::Bool(false));
+                self.reply(
+                    request,
+                    "shutdown_reply",
+                    Json::object(vec![("status", Json::string("ok")), ("restart", restart)]),
+                )?;
+                keep_running = false;
+            }
+            _ => {}
+        }
+
+        Ok(keep_running)
+    }
+
+    fn execute(&mut self, request: &Request) -> Result<(), ReplError> {
+        let code = request
+            .content
+            .get("code")
+            .and_then(Json::as_str)
+            .unwrap_or("");
+        let silent = request
+            .content
+            .get("silent")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        if !silent {
+            self.execution_count += 1;
+            self.publish(
+                "execute_input",
+                &request.header,
+                Json::object(vec![
+                    ("code", Json::string(code)),
+                    ("execution_count", Json::Number(self.execution_count as f64)),
+                ]),
+            );
+        }
+
+        let out = Rc::new(OutputPort::buffer());
+        let err = Rc::new(OutputPort::buffer());
+        let root_env = self.interpreter.root_env();
+        let result = with_port(OUT, out.clone(), root_env, || {
+            with_port(ERR, err.clone(), root_env, || self.eval_cell(code))
+        });
+
+        let mut stderr = err.contents().unwrap_or_default();
+        for warning in take_warnings() {
+            stderr.push_str(&format!("Warning: {:?}\n", warning));
+        }
+        if !silent {
+            for (name, text) in [
+                ("stdout", out.contents().unwrap_or_default()),
+                ("stderr", stderr),
+            ] {
+                if !text.is_empty() {
+                    self.publish(
+                        "stream",
+                        &request.header,
+                        Json::object(vec![
+                            ("name", Json::string(name)),
+                            ("text", Json::String(text)),
+                        ]),
+                    );
+                }
+            }
+        }
+
+        let execution_count = Json::Number(self.execution_count as f64);
+        let content = match result {
+            Ok(value) => {
+                if !silent && !matches!(value, Ast::Nil) {
+                    let limits = PrintLimits::from_env(self.interpreter.root_env());
+                    let text = pretty_print(&value, false, limits, DEFAULT_WIDTH);
+                    self.publish(
+                        "execute_result",
+                        &request.header,
+                        Json::object(vec![
+                            ("execution_count", execution_count.clone()),
+                            (
+                                "data",
+                                Json::object(vec![("text/plain", Json::String(text))]),
+                            ),
+                            ("metadata", Json::Object(vec![])),
+                        ]),
+                    );
+                }
+                Json::object(vec![
+                    ("status", Json::string("ok")),
+                    ("execution_count", execution_count),
+                    ("user_expressions", Json::Object(vec![])),
+                    ("payload", Json::Array(vec![])),
+                ])
+            }
+            Err(err) => {
+                let error = vec![
+                    ("ename", Json::string(err.error_type())),
+                    ("evalue", Json::String(format!("{:?}", err))),
+                    (
+                        "traceback",
+                        Json::Array(vec![Json::String(format!("Error: {:?}", err))]),
+                    ),
+                ];
+                if !silent {
+                    self.publish("error", &request.header, Json::object(error.clone()));
+                }
+                let mut content = vec![
+                    ("status", Json::string("error")),
+                    ("execution_count", execution_count),
+                ];
+                content.extend(error);
+                Json::object(content)
+            }
+        };
+        self.reply(request, "execute_reply", content)
+    }
+
+    /// Evaluates all forms of a cell, stopping at the first error
+    fn eval_cell(&self, code: &str) -> Result<Ast, ReplError> {
+        let forms = read_all(code).map_err(|err| ReplError::from(err).with_source(code))?;
+        let mut result = Ast::Nil;
+        for form in forms {
+            result = self.interpreter.eval(form)?;
+        }
+        Ok(result)
+    }
+
+    /// Completes the symbol in front of the cursor, whose position counts characters
+    fn complete(&self, content: &Json) -> Json {
+        let code: Vec<char> = content
+            .get("code")
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .chars()
+            .collect();
+        let cursor = content
+            .get("cursor_pos")
+            .and_then(Json::as_i64)
+            .map_or(code.len(), |pos| (pos.max(0) as usize).min(code.len()));
+        let start = code[..cursor]
+            .iter()
+            .rposition(|c| c.is_whitespace() || matches!(c, '(' | ')' | '\'' | '"'))
+            .map_or(0, |i| i + 1);
+        let prefix: String = code[start..cursor].iter().collect();
+
+        let matches = complete(&prefix, &self.interpreter.root_env().borrow())
+            .into_iter()
+            .map(|completion| Json::String(completion.name))
+            .collect();
+        Json::object(vec![
+            ("status", Json::string("ok")),
+            ("matches", Json::Array(matches)),
+            ("cursor_start", Json::Number(start as f64)),
+            ("cursor_end", Json::Number(cursor as f64)),
+            ("metadata", Json::Object(vec![])),
+        ])
+    }
+
+    fn reply(&mut self, request: &Request, msg_type: &str, content: Json) -> Result<(), ReplError> {
+        let message = self.message(request.ids.clone(), msg_type, &request.header, content);
+        Ok(request.connection.send(&message)?)
+    }
+
+    /// Sends a message to all iopub subscribers, forgetting those that have disconnected
+    fn publish(&mut self, msg_type: &str, parent: &Json, content: Json) {
+        let topic = vec![msg_type.as_bytes().to_vec()];
+        let message = self.message(topic, msg_type, parent, content);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(&message).is_ok());
+    }
+
+    /// Builds the frames of a signed message
+    fn message(
+        &mut self,
+        ids: Vec<Vec<u8>>,
+        msg_type: &str,
+        parent: &Json,
+        content: Json,
+    ) -> Message {
+        self.message_count += 1;
+        let header = Json::object(vec![
+            (
+                "msg_id",
+                Json::String(new_id(&self.message_count.to_be_bytes())),
+            ),
+            ("session", Json::String(self.session.clone())),
+            ("username", Json::string("kernel")),
+            ("date", Json::String(iso_date(SystemTime::now()))),
+            ("msg_type", Json::string(msg_type)),
+            ("version", Json::string(PROTOCOL_VERSION)),
+        ]);
+
+        let parts = [header, parent.clone(), Json::Object(vec![]), content]
+            .map(|part| part.to_string().into_bytes());
+        let signature = if self.key.is_empty() {
+            String::new()
+        } else {
+            hmac_sha256_hex(&self.key, &parts.each_ref().map(Vec::as_slice))
+        };
+
+        let mut message = ids;
+        message.push(DELIMITER.to_vec());
+        message.push(signature.into_bytes());
+        message.extend(parts);
+        message
+    }
+}
+
+fn status(state: &str) -> Json {
+    let key = if matches!(state, "busy" | "idle") {
+        "execution_state"
+    } else {
+        "status"
+    };
+    Json::object(vec![(key, Json::string(state))])
+}
+
+fn kernel_info() -> Json {
+    Json::object(vec![
+        ("status", Json::string("ok")),
+        ("protocol_version", Json::string(PROTOCOL_VERSION)),
+        ("implementation", Json::string("beesting")),
+        (
+            "implementation_version",
+            Json::string(env!("CARGO_PKG_VERSION")),
+        ),
+        (
+            "language_info",
+            Json::object(vec![
+                ("name", Json::string("beesting")),
+                ("version", Json::string(env!("CARGO_PKG_VERSION"))),
+                ("mimetype", Json::string("text/x-beesting")),
+                ("file_extension", Json::string(".bst")),
+            ]),
+        ),
+        ("banner", Json::string("beesting 🐝")),
+        ("help_links", Json::Array(vec![])),
+    ])
+}
+
+/// Unique id for a session or message, from the current time and `seed`
+fn new_id(seed: &[u8]) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    let mut input = nanos.to_be_bytes().to_vec();
+    input.extend(seed);
+    input.extend(std::process::id().to_be_bytes());
+    sha256(&input)[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// `2024-05-01T12:30:00.000000Z`
+fn iso_date(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_micros()
+    )
+}
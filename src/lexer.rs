@@ -0,0 +1,424 @@
+use crate::parser::{
+    find_reader_macro_prefix, is_partial_reader_macro_prefix, longest_reader_macro_prefix,
+};
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::mem;
+
+#[derive(Debug, Clone)]
+pub enum Token {
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    Symbol(String),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    ReaderMacro(String),
+    /// String literal missing its closing quote, with the text up to the end of the input
+    UnterminatedString(String),
+    /// `#_`, which makes the parser skip the next form
+    Discard,
+}
+
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        mem::discriminant(self) == mem::discriminant(other)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    fn advance(&mut self, c: char) {
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+/// Source range of a token, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.start.line, self.start.column)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+#[derive(PartialEq)]
+enum LexerState {
+    Normal,
+    InString,
+    /// After a backslash in a string
+    InStringEscape,
+    /// Inside a `"""` string, whose content is dedented
+    InHeredoc,
+    InComment,
+}
+
+/// Incremental tokenizer. Input is handed over with `feed` and tokens are pulled by iterating.
+///
+/// Iteration returns `None` when the fed input is used up. More input can be fed afterwards and
+/// iteration resumed; only after `finish` is a pending symbol or number at the very end emitted,
+/// since until then it could still continue in the next chunk. Likewise a `#`, `"` or reader
+/// macro prefix whose meaning depends on characters that haven't been fed yet is left unread.
+pub struct Lexer {
+    input: VecDeque<char>,
+    state: LexerState,
+    buffer: String,
+    buffer_start: Position,
+    position: Position,
+    ready: VecDeque<SpannedToken>,
+    finished: bool,
+}
+
+impl Default for Lexer {
+    fn default() -> Self {
+        Lexer::new()
+    }
+}
+
+impl Lexer {
+    pub fn new() -> Lexer {
+        Lexer {
+            input: VecDeque::new(),
+            state: LexerState::Normal,
+            buffer: String::new(),
+            buffer_start: Position::default(),
+            position: Position::default(),
+            ready: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    pub fn feed(&mut self, text: &str) {
+        self.input.extend(text.chars());
+    }
+
+    /// Marks the end of the input
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Whether the input fed so far ends inside a string literal
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self.state,
+            LexerState::InString | LexerState::InStringEscape | LexerState::InHeredoc
+        )
+    }
+
+    /// Consumes the next two characters if both are `"`, completing a `"""` delimiter
+    fn take_heredoc_delimiter(&mut self) -> bool {
+        if self.input.len() < 2 || self.input[0] != '"' || self.input[1] != '"' {
+            return false;
+        }
+
+        for _ in 0..2 {
+            let next = self.input.pop_front().unwrap();
+            self.position.advance(next);
+        }
+        true
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    fn push(&mut self, token: Token, start: Position, end: Position) {
+        self.ready.push_back(SpannedToken {
+            token,
+            span: Span { start, end },
+        });
+    }
+
+    fn push_buffer(&mut self, end: Position) {
+        if !self.buffer.is_empty() {
+            let token = get_token(&self.buffer);
+            self.buffer.clear();
+            self.push(token, self.buffer_start, end);
+        }
+    }
+
+    /// Whether reading `c` depends on characters following it that haven't been fed yet: the `_`
+    /// of `#_`, the rest of a `"""` delimiter or the rest of a reader macro prefix
+    fn needs_more_lookahead(&self, c: char) -> bool {
+        let heredoc_delimiter_possible =
+            self.input.len() < 2 && self.input.iter().all(|&next| next == '"');
+        match self.state {
+            LexerState::Normal if c == '"' => heredoc_delimiter_possible,
+            LexerState::Normal if self.buffer.is_empty() => {
+                (c == '#' && self.input.is_empty())
+                    || (self.input.len() + 1 < longest_reader_macro_prefix()
+                        && is_partial_reader_macro_prefix(&self.lookahead(c, self.input.len() + 1)))
+            }
+            LexerState::InHeredoc => c == '"' && heredoc_delimiter_possible,
+            _ => false,
+        }
+    }
+
+    fn lookahead(&self, first: char, len: usize) -> String {
+        let mut text = String::from(first);
+        text.extend(self.input.iter().take(len - 1));
+        text
+    }
+
+    fn step(&mut self, c: char) {
+        let start = self.position;
+        self.position.advance(c);
+
+        match self.state {
+            LexerState::InString => {
+                if c == '"' {
+                    let str = mem::take(&mut self.buffer);
+                    self.push(Token::String(str), self.buffer_start, self.position);
+                    self.state = LexerState::Normal;
+                } else if c == '\\' {
+                    self.state = LexerState::InStringEscape;
+                } else {
+                    self.buffer.push(c);
+                }
+            }
+            LexerState::InStringEscape => {
+                match c {
+                    'n' => self.buffer.push('\n'),
+                    't' => self.buffer.push('\t'),
+                    '"' | '\\' => self.buffer.push(c),
+                    // Unknown escapes are kept as they are
+                    _ => {
+                        self.buffer.push('\\');
+                        self.buffer.push(c);
+                    }
+                }
+                self.state = LexerState::InString;
+            }
+            LexerState::InHeredoc => {
+                if c == '"' && self.take_heredoc_delimiter() {
+                    let str = dedent(&mem::take(&mut self.buffer));
+                    self.push(Token::String(str), self.buffer_start, self.position);
+                    self.state = LexerState::Normal;
+                } else {
+                    self.buffer.push(c);
+                }
+            }
+            LexerState::InComment => {
+                if c == '\n' {
+                    self.state = LexerState::Normal;
+                }
+            }
+            LexerState::Normal => {
+                if self.buffer.is_empty() && c == '#' && self.input.front() == Some(&'_') {
+                    let next = self.input.pop_front().unwrap();
+                    self.position.advance(next);
+                    self.push(Token::Discard, start, self.position);
+                    return;
+                }
+
+                let prefix_len = longest_reader_macro_prefix();
+                if self.buffer.is_empty() && prefix_len > 0 {
+                    let lookahead = self.lookahead(c, prefix_len);
+                    if let Some(prefix) = find_reader_macro_prefix(&lookahead) {
+                        for _ in prefix.chars().skip(1) {
+                            let next = self.input.pop_front().unwrap();
+                            self.position.advance(next);
+                        }
+                        self.push(Token::ReaderMacro(prefix), start, self.position);
+                        return;
+                    }
+                }
+
+                match c {
+                    '"' => {
+                        self.push_buffer(start);
+                        self.buffer_start = start;
+                        self.state = if self.take_heredoc_delimiter() {
+                            LexerState::InHeredoc
+                        } else {
+                            LexerState::InString
+                        };
+                    }
+                    ';' => {
+                        self.push_buffer(start);
+                        self.state = LexerState::InComment;
+                    }
+                    '(' => {
+                        self.push_buffer(start);
+                        self.push(Token::LeftParen, start, self.position);
+                    }
+                    ')' => {
+                        self.push_buffer(start);
+                        self.push(Token::RightParen, start, self.position);
+                    }
+                    '[' => {
+                        self.push_buffer(start);
+                        self.push(Token::LeftBracket, start, self.position);
+                    }
+                    ']' => {
+                        self.push_buffer(start);
+                        self.push(Token::RightBracket, start, self.position);
+                    }
+                    '{' => {
+                        self.push_buffer(start);
+                        self.push(Token::LeftBrace, start, self.position);
+                    }
+                    '}' => {
+                        self.push_buffer(start);
+                        self.push(Token::RightBrace, start, self.position);
+                    }
+                    _ if c.is_whitespace() => self.push_buffer(start),
+                    _ => {
+                        if self.buffer.is_empty() {
+                            self.buffer_start = start;
+                        }
+                        self.buffer.push(c);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Lexer {
+    type Item = SpannedToken;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(token) = self.ready.pop_front() {
+                return Some(token);
+            }
+
+            match self.input.pop_front() {
+                Some(c) if !self.finished && self.needs_more_lookahead(c) => {
+                    self.input.push_front(c);
+                    return None;
+                }
+                Some(c) => self.step(c),
+                None if self.finished => {
+                    if self.is_incomplete() {
+                        let str = mem::take(&mut self.buffer);
+                        let token = Token::UnterminatedString(str);
+                        self.push(token, self.buffer_start, self.position);
+                        self.state = LexerState::Normal;
+                    } else {
+                        self.push_buffer(self.position);
+                    }
+
+                    return self.ready.pop_front();
+                }
+                None => return None,
+            }
+        }
+    }
+}
+
+/// Drops the line break after an opening `"""`, the whitespace before the closing one and the
+/// indentation all lines have in common
+fn dedent(text: &str) -> String {
+    let text = text.strip_prefix('\n').unwrap_or(text);
+    let text = match text.rfind('\n') {
+        Some(i) if text[i + 1..].trim().is_empty() => &text[..i],
+        _ => text,
+    };
+
+    let indent = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    text.lines()
+        .map(|line| line.get(indent..).unwrap_or(line.trim_start()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether more lines are needed to complete the input, because a string, list, vector or map
+/// is still open
+pub fn needs_more_input(text: &str) -> bool {
+    let mut depth = 0i64;
+    for SpannedToken { token, .. } in tokenize(text) {
+        match token {
+            Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth += 1,
+            Token::RightParen | Token::RightBracket | Token::RightBrace => depth -= 1,
+            Token::UnterminatedString(_) => return true,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+pub(crate) fn get_token(token: &str) -> Token {
+    if let Ok(n) = token.parse::<i64>() {
+        return Token::Integer(n);
+    }
+    match token.parse::<f64>() {
+        Ok(x) if looks_like_float(token) => Token::Float(x),
+        _ => Token::Symbol(token.to_owned()),
+    }
+}
+
+/// Whether the token is written like `1.5`, `-0.25` or `1e9`, unlike e.g. `inf` and `NaN`, which
+/// are symbols although Rust parses them as floats
+fn looks_like_float(token: &str) -> bool {
+    let digits = token.trim_start_matches(['-', '+']).trim_start_matches('.');
+    digits.starts_with(|c: char| c.is_ascii_digit())
+        && token
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '-' | '+'))
+}
+
+/// Tokenizes a complete input
+pub fn tokenize(text: &str) -> Vec<SpannedToken> {
+    let mut lexer = Lexer::new();
+    lexer.feed(text);
+    lexer.finish();
+    lexer.collect()
+}
+
+/// Lists the tokens of the text with their spans, one per line
+pub fn describe_tokens(text: &str) -> String {
+    tokenize(text)
+        .into_iter()
+        .map(|SpannedToken { token, span }| {
+            format!(
+                "{}:{}-{}:{} {:?}",
+                span.start.line, span.start.column, span.end.line, span.end.column, token
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,29 @@
+pub mod callstack;
+pub mod complete;
+pub mod convert;
+pub mod coverage;
+pub mod diagnostics;
+pub mod errors;
+pub mod eval;
+pub mod ffi;
+pub mod highlight;
+pub mod history;
+pub mod hmac;
+pub mod interpreter;
+pub mod json;
+pub mod kernel;
+pub mod lexer;
+pub mod memory;
+pub mod optimize;
+pub mod output;
+pub mod parser;
+pub mod printer;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod resolve;
+pub mod root_env;
+pub mod seq;
+pub mod serialize;
+pub mod stats;
+pub mod transcript;
+pub mod zmtp;
@@ -1,46 +1,573 @@
-mod errors;
-mod eval;
-mod parser;
-mod root_env;
-
-use crate::errors::ReplError;
-use crate::eval::eval;
-use crate::parser::Ast;
-use crate::root_env::{create_root_env, Environment};
-use std::cell::RefCell;
-use std::io;
-use std::io::Write;
+use beesting::callstack::EVAL_STACK_SIZE;
+use beesting::complete::complete;
+use beesting::coverage;
+use beesting::diagnostics::take_warnings;
+use beesting::errors::ReplError;
+use beesting::highlight::highlight;
+use beesting::history::{History, DEFAULT_HISTORY_SIZE};
+use beesting::interpreter::{Interpreter, InterpreterOptions};
+use beesting::kernel;
+use beesting::lexer::{describe_tokens, needs_more_input};
+use beesting::memory::CountingAllocator;
+use beesting::output::{with_port, OutputPort, OUT};
+use beesting::parser::{read_all, Ast};
+use beesting::printer::{describe_ast, pretty_print, PrintLimits, DEFAULT_WIDTH};
+use beesting::root_env::{forget_loaded_files, load, loaded_files, type_name};
+use beesting::serialize::{compile_file, read_program};
+use beesting::transcript::{parse_transcript, Transcript, CONTINUATION_PROMPT, PROMPT};
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+use std::{env, fs, io, thread};
 
-fn read() -> Result<Ast, ReplError> {
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[derive(Default)]
+enum Mode {
+    #[default]
+    Repl,
+    File(String),
+    Expression(String),
+    Watch(String),
+    /// Runs the file, then starts the REPL in the environment it left behind
+    Interactive(String),
+    Compile(String),
+    /// Jupyter kernel serving the given connection file
+    Kernel(String),
+    /// Runs the inputs of a transcript and compares the results with the recorded ones
+    Replay(String),
+}
+
+#[derive(Default)]
+struct CliOptions {
+    interpreter: InterpreterOptions,
+    mode: Mode,
+    dump_tokens: bool,
+    dump_ast: bool,
+    no_color: bool,
+    /// Records which forms of the files that are run get evaluated and reports it at the end
+    coverage: bool,
+    history_file: Option<PathBuf>,
+}
+
+/// Reads a line and, while a string or list is left open, the lines continuing it. Returns
+/// `None` once stdin is closed.
+fn read() -> Result<Option<String>, ReplError> {
     let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    Ok(input.parse()?)
+    if io::stdin().read_line(&mut input)? == 0 {
+        return Ok(None);
+    }
+
+    while needs_more_input(&input) {
+        print!("{}", CONTINUATION_PROMPT);
+        io::stdout().flush()?;
+        if io::stdin().read_line(&mut input)? == 0 {
+            break;
+        }
+    }
+    Ok(Some(input))
+}
+
+fn rep(
+    interpreter: &Interpreter,
+    cli_options: &CliOptions,
+    history: &History,
+    transcript: &mut Option<Transcript>,
+    input: &str,
+) -> Result<Ast, ReplError> {
+    if let Some(command) = input.trim().strip_prefix(':') {
+        return repl_command(interpreter, history, transcript, command);
+    }
+
+    if cli_options.dump_tokens || cli_options.dump_ast {
+        dump(input, cli_options)?;
+        return Ok(Ast::Nil);
+    }
+
+    let form = input
+        .parse()
+        .map_err(|err| ReplError::from(err).with_source(input))?;
+    interpreter.eval(form)
+}
+
+/// Handles REPL commands, which are lines starting with a colon
+fn repl_command(
+    interpreter: &Interpreter,
+    history: &History,
+    transcript: &mut Option<Transcript>,
+    command: &str,
+) -> Result<Ast, ReplError> {
+    let (name, argument) = command
+        .split_once(char::is_whitespace)
+        .map(|(name, argument)| (name, argument.trim()))
+        .unwrap_or((command, ""));
+
+    match name {
+        "reload" if argument.is_empty() => {
+            for file in loaded_files() {
+                load(&file.to_string_lossy(), interpreter.root_env())?;
+            }
+            Ok(Ast::Nil)
+        }
+        "reload" => interpreter.eval(Ast::List(Rc::new(vec![
+            Ast::Symbol("reload".to_owned()),
+            Ast::String(argument.to_owned()),
+        ]))),
+        "complete" => {
+            for completion in complete(argument, &interpreter.root_env().borrow()) {
+                match completion.doc {
+                    Some(doc) => println!("{} {:?} {}", completion.name, completion.kind, doc),
+                    None => println!("{} {:?}", completion.name, completion.kind),
+                }
+            }
+            Ok(Ast::Nil)
+        }
+        "type" => {
+            let value = interpreter.eval(argument.parse()?)?;
+            Ok(Ast::Keyword(type_name(&value).to_owned()))
+        }
+        "history" => {
+            for (i, entry) in history.entries().iter().enumerate() {
+                println!("{:>5}  {}", i + 1, entry);
+            }
+            Ok(Ast::Nil)
+        }
+        "record" if argument.is_empty() => {
+            eprintln!("Usage: :record <file>");
+            Ok(Ast::Nil)
+        }
+        "record" => {
+            *transcript = Some(Transcript::create(argument.as_ref())?);
+            Ok(Ast::Nil)
+        }
+        "stop" => {
+            *transcript = None;
+            Ok(Ast::Nil)
+        }
+        _ => {
+            eprintln!("Unknown command :{}", name);
+            Ok(Ast::Nil)
+        }
+    }
+}
+
+/// Prints what the reader makes of the input instead of evaluating it
+fn dump(input: &str, cli_options: &CliOptions) -> Result<(), ReplError> {
+    if cli_options.dump_tokens {
+        println!("{}", describe_tokens(input));
+    }
+
+    if cli_options.dump_ast {
+        let forms = read_all(input).map_err(|err| ReplError::from(err).with_source(input))?;
+        for form in forms {
+            println!("{}", describe_ast(&form));
+        }
+    }
+
+    Ok(())
+}
+
+fn print_warnings() {
+    for warning in take_warnings() {
+        eprintln!("Warning: {:?}", warning);
+    }
 }
 
-fn rep(root_env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
-    let input = read()?;
-    eval(input, &Rc::clone(root_env))
+/// Parses a size in bytes with an optional K, M or G suffix
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, factor) = match size.char_indices().last()? {
+        (i, 'K' | 'k') => (&size[..i], 1 << 10),
+        (i, 'M' | 'm') => (&size[..i], 1 << 20),
+        (i, 'G' | 'g') => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(factor)
+}
+
+fn parse_options() -> Result<CliOptions, String> {
+    let mut options = CliOptions::default();
+    let mut args = env::args().skip(1).peekable();
+
+    if args.peek().is_some_and(|arg| arg == "compile") {
+        args.next();
+        match args.next() {
+            Some(file) => options.mode = Mode::Compile(file),
+            None => return Err("compile expects a file".to_owned()),
+        }
+    } else if args.peek().is_some_and(|arg| arg == "replay") {
+        args.next();
+        match args.next() {
+            Some(file) => options.mode = Mode::Replay(file),
+            None => return Err("replay expects a transcript file".to_owned()),
+        }
+    } else if args.peek().is_some_and(|arg| arg == "kernel") {
+        args.next();
+        match args.next() {
+            Some(file) => options.mode = Mode::Kernel(file),
+            None => return Err("kernel expects a connection file".to_owned()),
+        }
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict" => options.interpreter.strict = true,
+            "--fold-constants" => options.interpreter.fold_constants = true,
+            "--memory-limit" => match args.next().as_deref().map(parse_size) {
+                Some(Some(limit)) => options.interpreter.memory_limit = Some(limit),
+                _ => return Err("--memory-limit expects a size like 64M".to_owned()),
+            },
+            "--deterministic" => options.interpreter.deterministic = true,
+            "--dump-tokens" => options.dump_tokens = true,
+            "--dump-ast" => options.dump_ast = true,
+            "--no-color" => options.no_color = true,
+            "--coverage" => options.coverage = true,
+            "--history" => match args.next() {
+                Some(file) => options.history_file = Some(PathBuf::from(file)),
+                None => return Err("--history expects a file".to_owned()),
+            },
+            "--watch" => match args.next() {
+                Some(file) => options.mode = Mode::Watch(file),
+                None => return Err("--watch expects a file".to_owned()),
+            },
+            "-i" => match args.next() {
+                Some(file) => options.mode = Mode::Interactive(file),
+                None => return Err("-i expects a file".to_owned()),
+            },
+            "-e" => match args.next() {
+                Some(expression) => options.mode = Mode::Expression(expression),
+                None => return Err("-e expects an expression".to_owned()),
+            },
+            other if other.starts_with('-') => return Err(format!("Unknown argument '{}'", other)),
+            file => options.mode = Mode::File(file.to_owned()),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Replaces the lines the user just typed with a highlighted version of them. Lines wider than
+/// the terminal took up several rows, which are all moved back over.
+fn echo_highlighted(input: &str) {
+    let input = input.trim_end_matches(['\r', '\n']);
+    let columns = terminal_width()
+        .or_else(|| env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+        .max(1);
+    let rows: usize = input
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let prompt = if i == 0 { PROMPT } else { CONTINUATION_PROMPT };
+            (display_width(prompt) + display_width(line))
+                .div_ceil(columns)
+                .max(1)
+        })
+        .sum();
+    print!("\x1b[{}A\r\x1b[J", rows.max(1));
+    for (i, line) in highlight(input).lines().enumerate() {
+        let prompt = if i == 0 { PROMPT } else { CONTINUATION_PROMPT };
+        println!("{}{}", prompt, line);
+    }
+}
+
+/// Columns a line takes up in the terminal, counting emoji as two
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| {
+            if ('\u{1F300}'..='\u{1FAFF}').contains(&c) {
+                2
+            } else {
+                1
+            }
+        })
+        .sum()
+}
+
+/// Number of columns of the terminal on standard output, if its size can be queried
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn terminal_width() -> Option<usize> {
+    use std::ffi::{c_int, c_ulong};
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct WinSize {
+        rows: u16,
+        columns: u16,
+        x_pixels: u16,
+        y_pixels: u16,
+    }
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    #[cfg(target_os = "linux")]
+    const TIOCGWINSZ: c_ulong = 0x5413;
+    #[cfg(target_os = "macos")]
+    const TIOCGWINSZ: c_ulong = 0x4008_7468;
+
+    let mut size = WinSize::default();
+    // SAFETY: TIOCGWINSZ only writes a `struct winsize` through the pointer
+    let result = unsafe { ioctl(1, TIOCGWINSZ, &mut size as *mut WinSize) };
+    (result == 0 && size.columns > 0).then_some(size.columns as usize)
 }
 
-fn main() {
-    let root_env = Rc::new(RefCell::new(create_root_env()));
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn terminal_width() -> Option<usize> {
+    None
+}
 
-    eval(
-        "(def! load-file (fun* (f) (eval (read-str (str '(do ' (slurp f) ' nil)')))))"
-            .parse()
-            .unwrap(),
-        &Rc::clone(&root_env),
-    )
-    .expect("Could not create function load-file");
+fn repl(interpreter: &Interpreter, cli_options: &CliOptions) {
+    let color = !cli_options.no_color && io::stdin().is_terminal() && io::stdout().is_terminal();
+    let history_file = cli_options
+        .history_file
+        .clone()
+        .or_else(History::default_path);
+    let mut history = History::load(history_file, DEFAULT_HISTORY_SIZE).unwrap_or_else(|err| {
+        eprintln!("Could not read history: {:?}", err);
+        History::load(None, DEFAULT_HISTORY_SIZE).unwrap()
+    });
+    let mut transcript = None;
 
     loop {
-        print!("🐝> ");
+        print!("{}", PROMPT);
         io::stdout().flush().expect("Can't flush. Call Luigi");
-        let output_result = rep(&root_env);
+        let input = match read() {
+            Ok(Some(input)) => input,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("Error occurred: {:?}", err);
+                break;
+            }
+        };
+        if color {
+            echo_highlighted(&input);
+        }
+
+        // REPL commands aren't recorded, replaying them wouldn't reproduce the session
+        let recording = transcript.is_some() && !input.trim().starts_with(':');
+        let (output_result, out) = if recording {
+            let (result, out) = capture_output(interpreter, || {
+                rep(interpreter, cli_options, &history, &mut transcript, &input)
+            });
+            print!("{}", out);
+            (result, out)
+        } else {
+            let result = rep(interpreter, cli_options, &history, &mut transcript, &input);
+            (result, String::new())
+        };
+        history.add(&input);
+        print_warnings();
+        let printed = printed_result(interpreter, &output_result);
         match output_result {
+            Ok(_) => println!("{}", printed),
+            Err(_) => eprintln!("{}", printed),
+        }
+
+        if let Some(file) = transcript.as_mut().filter(|_| recording) {
+            if let Err(err) = file.record(&input, &format!("{}{}", out, printed)) {
+                eprintln!("Could not write transcript, recording stopped: {:?}", err);
+                transcript = None;
+            }
+        }
+    }
+
+    if let Err(err) = history.save() {
+        eprintln!("Could not save history: {:?}", err);
+    }
+}
+
+/// What the REPL prints for the result of an input
+fn printed_result(interpreter: &Interpreter, result: &Result<Ast, ReplError>) -> String {
+    match result {
+        Ok(output) => {
+            let limits = PrintLimits::from_env(interpreter.root_env());
+            pretty_print(output, false, limits, DEFAULT_WIDTH)
+        }
+        Err(err) => format!("Error occurred: {:?}", err),
+    }
+}
+
+/// Runs `f` with `*out*` bound to a buffer, returning its result and what it printed
+fn capture_output(
+    interpreter: &Interpreter,
+    f: impl FnOnce() -> Result<Ast, ReplError>,
+) -> (Result<Ast, ReplError>, String) {
+    let port = Rc::new(OutputPort::buffer());
+    let result = with_port(OUT, port.clone(), interpreter.root_env(), f);
+    (result, port.contents().unwrap_or_default())
+}
+
+/// Feeds the inputs of a transcript to the REPL and shows where what it prints, output and
+/// results, differs from the recorded session. Fails if any of them do.
+fn replay(
+    interpreter: &Interpreter,
+    cli_options: &CliOptions,
+    file: &str,
+) -> Result<(), ReplError> {
+    let entries = parse_transcript(&fs::read_to_string(file)?);
+    let history = History::load(None, DEFAULT_HISTORY_SIZE)?;
+    let mut differences = 0;
+
+    for (i, entry) in entries.iter().enumerate() {
+        let (result, out) = capture_output(interpreter, || {
+            rep(interpreter, cli_options, &history, &mut None, &entry.input)
+        });
+        print_warnings();
+        let printed = format!("{}{}", out, printed_result(interpreter, &result));
+        if printed.trim_end() != entry.output.trim_end() {
+            differences += 1;
+            println!("Entry {}: {}", i + 1, entry.input);
+            for line in entry.output.lines() {
+                println!("- {}", line);
+            }
+            for line in printed.lines() {
+                println!("+ {}", line);
+            }
+        }
+    }
+
+    println!(
+        "{} of {} entries match",
+        entries.len() - differences,
+        entries.len()
+    );
+    if differences > 0 {
+        return Err(io::Error::other(format!("{} entries differ", differences)).into());
+    }
+    Ok(())
+}
+
+/// Evaluates all forms of a script, stopping at the first error
+fn run_script(
+    interpreter: &Interpreter,
+    cli_options: &CliOptions,
+    code: &str,
+) -> Result<Ast, ReplError> {
+    if cli_options.dump_tokens || cli_options.dump_ast {
+        dump(code, cli_options)?;
+        return Ok(Ast::Nil);
+    }
+
+    let forms = read_all(code).map_err(|err| ReplError::from(err).with_source(code))?;
+    run_forms(interpreter, forms)
+}
+
+fn run_forms(interpreter: &Interpreter, forms: Vec<Ast>) -> Result<Ast, ReplError> {
+    let mut result = Ast::Nil;
+    for form in forms {
+        let evaluated = interpreter.eval(form);
+        print_warnings();
+        result = evaluated?;
+    }
+
+    Ok(result)
+}
+
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Runs the file in a fresh interpreter whenever it or a file it loaded changes
+fn watch(cli_options: &CliOptions, file: &str) -> ! {
+    loop {
+        forget_loaded_files();
+        let interpreter = Interpreter::with_options(cli_options.interpreter.clone());
+        let result = fs::read_to_string(file)
+            .map_err(ReplError::from)
+            .and_then(|code| run_script(&interpreter, cli_options, &code));
+        match result {
             Ok(output) => println!("{:?}", output),
-            Err(err) => eprintln!("Error occurred: {:?}", err),
+            Err(err) => eprintln!("Error: {:?}", err),
+        }
+
+        let mut watched = vec![PathBuf::from(file)];
+        watched.extend(loaded_files());
+        eprintln!("--- Watching {} file(s) for changes", watched.len());
+
+        let initial = modification_times(&watched);
+        while modification_times(&watched) == initial {
+            thread::sleep(Duration::from_millis(250));
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // Deep recursion in lisp code is deep recursion in the evaluator, so it gets a big stack
+    thread::Builder::new()
+        .stack_size(EVAL_STACK_SIZE)
+        .spawn(run)
+        .expect("Can't spawn the interpreter thread")
+        .join()
+        .unwrap_or(ExitCode::FAILURE)
+}
+
+fn run() -> ExitCode {
+    let cli_options = match parse_options() {
+        Ok(cli_options) => cli_options,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::from(2);
+        }
+    };
+    let interpreter = Interpreter::with_options(cli_options.interpreter.clone());
+    if cli_options.coverage {
+        coverage::enable();
+    }
+
+    let result = match &cli_options.mode {
+        Mode::Repl => {
+            repl(&interpreter, &cli_options);
+            Ok(())
+        }
+        Mode::Watch(file) => watch(&cli_options, file),
+        Mode::File(file) if cli_options.dump_tokens || cli_options.dump_ast => {
+            fs::read_to_string(file)
+                .map_err(ReplError::from)
+                .and_then(|code| run_script(&interpreter, &cli_options, &code))
+                .map(|_| ())
+        }
+        Mode::File(file) => read_program(file)
+            .and_then(|forms| run_forms(&interpreter, forms))
+            .map(|_| ()),
+        Mode::Interactive(file) => {
+            // Errors in the script are reported, but the REPL still starts to look into them
+            let loaded = read_program(file).and_then(|forms| run_forms(&interpreter, forms));
+            if let Err(err) = loaded {
+                eprintln!("Error: {:?}", err);
+            }
+            repl(&interpreter, &cli_options);
+            Ok(())
+        }
+        Mode::Compile(file) => compile_file(file).map(|path| println!("{}", path.display())),
+        Mode::Kernel(file) => kernel::run(file, interpreter),
+        Mode::Replay(file) => replay(&interpreter, &cli_options, file),
+        Mode::Expression(expression) => {
+            run_script(&interpreter, &cli_options, expression).map(|result| {
+                if !cli_options.dump_tokens && !cli_options.dump_ast {
+                    println!("{:?}", result)
+                }
+            })
+        }
+    };
+
+    if cli_options.coverage {
+        for file in coverage::report() {
+            eprintln!("{}", file);
+        }
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::FAILURE
         }
     }
 }
@@ -53,4 +580,4 @@ fn main() {
 
 // (def! add (fun* (acc limit) (if (< acc limit) (add (+ acc 1) limit) acc)))
 
-// (def! load-file (fun* (f) (eval (read-str (str '(do ' (slurp f) ' nil)')))))
+// (def! load-file (fun* (f) (eval (read-str (str "(do " (slurp f) " nil)")))))
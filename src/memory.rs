@@ -0,0 +1,56 @@
+use crate::errors::RuntimeError;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Global allocator that keeps track of the bytes currently allocated. Memory limits only take
+/// effect in programs that install it with `#[global_allocator]`.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// Bytes allocated through `CountingAllocator` that haven't been freed yet
+pub fn allocated_bytes() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+thread_local! {
+    static LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+pub fn set_memory_limit(limit: Option<usize>) {
+    LIMIT.with(|l| l.set(limit));
+}
+
+/// Fails once more heap is in use than the limit allows. Called by the evaluator before each
+/// step, so a runaway evaluation is stopped shortly after crossing the limit.
+pub fn check_memory_limit() -> Result<(), RuntimeError> {
+    match LIMIT.with(Cell::get) {
+        Some(limit) if allocated_bytes() > limit => Err(RuntimeError::MemoryLimitExceeded(limit)),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,145 @@
+use crate::coverage;
+use crate::eval::macro_function;
+use crate::parser::Ast;
+use crate::root_env::{lookup, Environment};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Builtins without side effects, which can be evaluated ahead of time when all arguments are
+/// literals
+const PURE_BUILTINS: [&str; 10] = ["+", "-", "*", "=", "not=", "<", ">", "<=", ">=", "str"];
+
+/// Pre-evaluates calls of pure builtins whose arguments are all literals, e.g. `(+ 1 2)` becomes
+/// `3`. Symbols that are locally bound by `fun*` or a let form are never folded, since they may not
+/// refer to the builtin at runtime.
+pub fn fold_constants(ast: Ast, env: &Rc<RefCell<Environment>>) -> Ast {
+    fold(ast, env, &HashSet::new())
+}
+
+fn fold(ast: Ast, env: &Rc<RefCell<Environment>>, bound: &HashSet<String>) -> Ast {
+    let xs = match ast {
+        Ast::List(xs) => xs,
+        Ast::Vector(xs) => {
+            return Ast::Vector(Rc::new(fold_all(Rc::unwrap_or_clone(xs), env, bound)))
+        }
+        Ast::HashMap(map) => {
+            let entries = Rc::unwrap_or_clone(map).into_iter();
+            return Ast::HashMap(Rc::new(
+                entries.map(|(k, v)| (k, fold(v, env, bound))).collect(),
+            ));
+        }
+        other => return other,
+    };
+    let original = Rc::as_ptr(&xs);
+    let folded = fold_list(Rc::unwrap_or_clone(xs), env, bound);
+    coverage::rewritten(original, &folded);
+    folded
+}
+
+fn fold_list(xs: Vec<Ast>, env: &Rc<RefCell<Environment>>, bound: &HashSet<String>) -> Ast {
+    let head = match xs.first() {
+        Some(Ast::Symbol(s)) => s.clone(),
+        _ => return Ast::List(Rc::new(fold_all(xs, env, bound))),
+    };
+
+    match head.as_str() {
+        "fun*" | "let*" | "letrec" | "plet" | "doseq" => fold_binding_form(xs, env, bound),
+        "catch*" => match <[Ast; 3]>::try_from(xs) {
+            Ok([head, Ast::Symbol(name), handler]) => {
+                let mut inner_bound = bound.clone();
+                inner_bound.insert(name.clone());
+                let handler = fold(handler, env, &inner_bound);
+                Ast::List(Rc::new(vec![head, Ast::Symbol(name), handler]))
+            }
+            Ok(xs) => Ast::List(Rc::new(xs.to_vec())),
+            Err(xs) => Ast::List(Rc::new(xs)),
+        },
+        "def!" | "defmacro!" | "defonce" | "defconst" | "if" | "cond" | "and" | "or" | "do"
+        | "eval" | "try*" => Ast::List(Rc::new(fold_all(xs, env, bound))),
+        // The steps get the threaded value as first argument, so they aren't complete calls.
+        // The body of `with-env` may see other definitions of the builtins.
+        "some->" | "with-env" | "quote" | "quasiquote" => Ast::List(Rc::new(xs)),
+        // Macros get their arguments as written
+        _ if !bound.contains(&head) && macro_function(&xs, env).is_some() => Ast::List(Rc::new(xs)),
+        _ => {
+            let xs = fold_all(xs, env, bound);
+            try_fold_call(&head, &xs, env, bound).unwrap_or_else(|| Ast::List(Rc::new(xs)))
+        }
+    }
+}
+
+fn fold_all(xs: Vec<Ast>, env: &Rc<RefCell<Environment>>, bound: &HashSet<String>) -> Vec<Ast> {
+    xs.into_iter().map(|x| fold(x, env, bound)).collect()
+}
+
+fn fold_binding_form(
+    mut xs: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+    bound: &HashSet<String>,
+) -> Ast {
+    if xs.len() != 3 {
+        return Ast::List(Rc::new(xs));
+    }
+
+    let is_fun = matches!(&xs[0], Ast::Symbol(s) if s == "fun*");
+    let mut inner_bound = bound.clone();
+    if let Ast::List(bindings) = &xs[1] {
+        for (i, binding) in bindings.iter().enumerate() {
+            if let Ast::Symbol(s) = binding {
+                if is_fun || i % 2 == 0 {
+                    inner_bound.insert(s.clone());
+                }
+            }
+        }
+    }
+
+    let body = xs.pop().unwrap();
+    let bindings = xs.pop().unwrap();
+    let bindings = match (is_fun, bindings) {
+        (false, Ast::List(bindings)) => Ast::List(Rc::new(fold_all(
+            Rc::unwrap_or_clone(bindings),
+            env,
+            &inner_bound,
+        ))),
+        (_, other) => other,
+    };
+
+    xs.push(bindings);
+    xs.push(fold(body, env, &inner_bound));
+    Ast::List(Rc::new(xs))
+}
+
+fn try_fold_call(
+    head: &str,
+    xs: &[Ast],
+    env: &Rc<RefCell<Environment>>,
+    bound: &HashSet<String>,
+) -> Option<Ast> {
+    if !PURE_BUILTINS.contains(&head) || bound.contains(head) {
+        return None;
+    }
+
+    let args = &xs[1..];
+    if !args.iter().all(is_literal) {
+        return None;
+    }
+
+    // A call that fails, e.g. on overflow or division by zero, is left for run time to report
+    match lookup(head.to_owned(), env) {
+        Ok(Ast::Builtin(name, f)) => f(&name, args.to_vec(), env).ok().filter(is_literal),
+        _ => None,
+    }
+}
+
+fn is_literal(ast: &Ast) -> bool {
+    matches!(
+        ast,
+        Ast::Integer(_)
+            | Ast::Float(_)
+            | Ast::Keyword(_)
+            | Ast::Boolean(_)
+            | Ast::String(_)
+            | Ast::Nil
+    )
+}
@@ -0,0 +1,128 @@
+use crate::errors::{ReplError, RuntimeError};
+use crate::parser::Ast;
+use crate::root_env::{get_root, lookup, Environment};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::rc::Rc;
+
+/// Symbol holding the port that printing builtins write to
+pub const OUT: &str = "*out*";
+/// Symbol holding the port that `eprn` and `eprintln` write to
+pub const ERR: &str = "*err*";
+
+/// Destination for printed text
+pub enum OutputPort {
+    Stdout,
+    Stderr,
+    /// File opened for writing, `None` once the port is closed
+    File(String, RefCell<Option<BufWriter<File>>>),
+    /// Collects everything written into a string
+    Buffer(RefCell<String>),
+}
+
+impl OutputPort {
+    pub fn open_file(path: &str) -> io::Result<OutputPort> {
+        let file = File::create(path)?;
+        Ok(OutputPort::File(
+            path.to_owned(),
+            RefCell::new(Some(BufWriter::new(file))),
+        ))
+    }
+
+    pub fn buffer() -> OutputPort {
+        OutputPort::Buffer(RefCell::new(String::new()))
+    }
+
+    pub fn write(&self, text: &str) -> io::Result<()> {
+        match self {
+            OutputPort::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()
+            }
+            OutputPort::Stderr => io::stderr().write_all(text.as_bytes()),
+            OutputPort::File(path, file) => match file.borrow_mut().as_mut() {
+                Some(file) => file.write_all(text.as_bytes()),
+                None => Err(io::Error::other(format!("{} is closed", path))),
+            },
+            OutputPort::Buffer(buffer) => {
+                buffer.borrow_mut().push_str(text);
+                Ok(())
+            }
+        }
+    }
+
+    /// Flushes and closes a file port. Other ports stay usable.
+    pub fn close(&self) -> io::Result<()> {
+        match self {
+            OutputPort::File(_, file) => match file.borrow_mut().take() {
+                Some(mut file) => file.flush(),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            OutputPort::Stdout => "stdout".to_owned(),
+            OutputPort::Stderr => "stderr".to_owned(),
+            OutputPort::File(path, _) => format!("file {}", path),
+            OutputPort::Buffer(_) => "string".to_owned(),
+        }
+    }
+
+    /// What has been written to a string port so far
+    pub fn contents(&self) -> Option<String> {
+        match self {
+            OutputPort::Buffer(buffer) => Some(buffer.borrow().clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Writes to the port bound to `symbol` (`*out*` or `*err*`) as seen from `env`
+pub fn write_to(symbol: &str, text: &str, env: &Rc<RefCell<Environment>>) -> Result<(), ReplError> {
+    match lookup(symbol.to_owned(), env)? {
+        Ast::Port(port) => Ok(port.write(text)?),
+        other => {
+            Err(RuntimeError::TypeMismatch(symbol.to_owned(), 1, "Port".to_owned(), other).into())
+        }
+    }
+}
+
+pub fn write_out(text: &str, env: &Rc<RefCell<Environment>>) -> Result<(), ReplError> {
+    write_to(OUT, text, env)
+}
+
+pub fn write_line(text: &str, env: &Rc<RefCell<Environment>>) -> Result<(), ReplError> {
+    write_out(&format!("{}\n", text), env)
+}
+
+/// Runs `f` with the root binding of `symbol` set to `port`, restoring the previous binding
+/// afterwards even if `f` fails. Code called from `f` therefore prints to `port`, unless it
+/// binds the symbol itself.
+pub fn with_port<R>(
+    symbol: &str,
+    port: Rc<OutputPort>,
+    env: &Rc<RefCell<Environment>>,
+    f: impl FnOnce() -> Result<R, ReplError>,
+) -> Result<R, ReplError> {
+    let root = get_root(env);
+    let previous = root.borrow().get_local(symbol).cloned();
+    root.borrow_mut()
+        .set_local(symbol.to_owned(), Ast::Port(port));
+
+    let result = f();
+
+    let mut root = root.borrow_mut();
+    match previous {
+        Some(previous) => root.set_local(symbol.to_owned(), previous),
+        None => {
+            root.values.remove(symbol);
+        }
+    }
+    result
+}
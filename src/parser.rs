@@ -1,147 +1,420 @@
+use crate::coverage;
 use crate::errors::ReplError;
+use crate::lexer::{tokenize, Span, SpannedToken, Token};
+use crate::output::OutputPort;
+use crate::printer::pr_str;
 use crate::root_env::Environment;
-use std::cell::RefCell;
+use crate::seq::LazySeq;
+use crate::stats;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Debug, Formatter};
 use std::iter::Peekable;
-use std::mem;
+use std::ops::Deref;
 use std::rc::Rc;
 use std::str::FromStr;
 use std::vec::IntoIter;
 
-#[derive(Debug)]
-pub enum Token {
-    LeftParen,
-    RightParen,
-    Symbol(String),
-    Integer(i64),
-    String(String),
+/// Transforms the form following a reader macro prefix into the form that is actually read.
+pub type ReaderMacro = fn(Ast) -> Result<Ast, ParserError>;
+
+thread_local! {
+    static READER_MACROS: RefCell<HashMap<String, ReaderMacro>> = RefCell::new(HashMap::from([
+        ("#".to_owned(), dispatch as ReaderMacro),
+        ("'".to_owned(), quote as ReaderMacro),
+        ("`".to_owned(), quasiquote as ReaderMacro),
+        ("~".to_owned(), unquote as ReaderMacro),
+        ("~@".to_owned(), splice_unquote as ReaderMacro),
+    ]));
 }
 
-impl PartialEq for Token {
-    fn eq(&self, other: &Self) -> bool {
-        mem::discriminant(self) == mem::discriminant(other)
-    }
+/// Registers a reader macro, so that `<prefix><form>` is read as `transform(<form>)`.
+/// Registering an existing prefix replaces its transformation.
+pub fn register_reader_macro(prefix: &str, transform: ReaderMacro) {
+    READER_MACROS.with(|macros| {
+        macros.borrow_mut().insert(prefix.to_owned(), transform);
+    });
 }
 
-type PositionalToken = (usize, Token);
+pub fn unregister_reader_macro(prefix: &str) {
+    READER_MACROS.with(|macros| {
+        macros.borrow_mut().remove(prefix);
+    });
+}
 
-#[derive(Default)]
-struct TokenizerState {
-    tokens: Vec<PositionalToken>,
-    buffer: String,
-    quoting: bool,
+pub(crate) fn longest_reader_macro_prefix() -> usize {
+    READER_MACROS
+        .with(|macros| {
+            macros
+                .borrow()
+                .keys()
+                .map(|prefix| prefix.chars().count())
+                .max()
+        })
+        .unwrap_or(0)
 }
 
-impl TokenizerState {
-    fn try_push(&mut self, c: char, index: usize) -> bool {
-        if self.quoting {
-            self.buffer.push(c);
-            return false;
-        }
+pub(crate) fn find_reader_macro_prefix(text: &str) -> Option<String> {
+    READER_MACROS.with(|macros| {
+        macros
+            .borrow()
+            .keys()
+            .filter(|prefix| text.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len())
+            .cloned()
+    })
+}
 
-        self.push_buffer(index, false);
+/// Whether `text` is the start of a reader macro prefix longer than it
+pub(crate) fn is_partial_reader_macro_prefix(text: &str) -> bool {
+    READER_MACROS.with(|macros| {
+        macros
+            .borrow()
+            .keys()
+            .any(|prefix| prefix.len() > text.len() && prefix.starts_with(text))
+    })
+}
 
-        true
-    }
+fn get_reader_macro(prefix: &str) -> Option<ReaderMacro> {
+    READER_MACROS.with(|macros| macros.borrow().get(prefix).copied())
+}
 
-    fn try_push_with(&mut self, c: char, index: usize, with: Token) {
-        if self.try_push(c, index) {
-            self.tokens.push((index, with))
-        }
+fn wrap(head: &str, form: Ast) -> Ast {
+    Ast::List(Rc::new(vec![Ast::Symbol(head.to_owned()), form]))
+}
+
+/// The builtin `'` reader macro, reading `'form` as `(quote form)`
+fn quote(form: Ast) -> Result<Ast, ParserError> {
+    Ok(wrap("quote", form))
+}
+
+/// Reads `` `form `` as `(quasiquote form)`
+fn quasiquote(form: Ast) -> Result<Ast, ParserError> {
+    Ok(wrap("quasiquote", form))
+}
+
+/// Reads `~form` as `(unquote form)`
+fn unquote(form: Ast) -> Result<Ast, ParserError> {
+    Ok(wrap("unquote", form))
+}
+
+/// Reads `~@form` as `(splice-unquote form)`
+fn splice_unquote(form: Ast) -> Result<Ast, ParserError> {
+    Ok(wrap("splice-unquote", form))
+}
+
+/// The builtin `#` reader macro, whose meaning depends on the form that follows
+fn dispatch(form: Ast) -> Result<Ast, ParserError> {
+    match form {
+        Ast::String(s) => interpolate(&s),
+        Ast::List(body) => anonymous_function(&body),
+        other => Err(ParserError::InvalidDispatch(other)),
     }
+}
+
+/// Reads `#(+ % %2)` as `(fun* (%1 %2) (+ %1 %2))`: `%` is the first argument, and the function
+/// takes as many arguments as the highest placeholder used
+fn anonymous_function(body: &Rc<Vec<Ast>>) -> Result<Ast, ParserError> {
+    let mut arity = 0;
+    let body = replace_placeholders(body, &mut arity)?;
+    let params = (1..=arity)
+        .map(|i| Ast::Symbol(format!("%{}", i)))
+        .collect();
+
+    Ok(Ast::List(Rc::new(vec![
+        Ast::Symbol("fun*".to_owned()),
+        Ast::List(Rc::new(params)),
+        Ast::List(body),
+    ])))
+}
 
-    fn push_buffer(&mut self, index: usize, treat_as_str: bool) {
-        if !self.buffer.is_empty() {
-            self.tokens.push((
-                index - self.buffer.len(),
-                get_token(&self.buffer, treat_as_str),
-            ));
-            self.buffer.clear();
+/// Index of an argument placeholder, `%` being `%1`
+fn placeholder_index(symbol: &str) -> Option<usize> {
+    match symbol.strip_prefix('%')? {
+        "" => Some(1),
+        digits if digits.bytes().all(|b| b.is_ascii_digit()) => {
+            digits.parse().ok().filter(|&n| n > 0)
         }
+        _ => None,
     }
 }
 
-fn tokenize(text: &str) -> Vec<PositionalToken> {
-    let mut state = TokenizerState::default();
+/// Renames `%` to `%1` in the list and its sublists and raises `arity` to the highest placeholder
+/// found. Lists without placeholders are kept as they are.
+fn replace_placeholders(
+    list: &Rc<Vec<Ast>>,
+    arity: &mut usize,
+) -> Result<Rc<Vec<Ast>>, ParserError> {
+    if let [Ast::Symbol(head), Ast::List(params), ..] = list.as_slice() {
+        let has_placeholders = params
+            .iter()
+            .any(|p| matches!(p, Ast::Symbol(s) if placeholder_index(s).is_some()));
+        if head == "fun*" && has_placeholders {
+            return Err(ParserError::NestedAnonymousFunction);
+        }
+    }
 
-    for (i, c) in text.char_indices() {
-        match c {
-            '\'' => {
-                state.push_buffer(i, state.quoting);
-                state.quoting = !state.quoting;
+    let mut changed = false;
+    let mut items = Vec::with_capacity(list.len());
+    for item in list.iter() {
+        let replaced = match item {
+            Ast::Symbol(s) => match placeholder_index(s) {
+                Some(index) => {
+                    *arity = (*arity).max(index);
+                    changed |= s == "%";
+                    Ast::Symbol(format!("%{}", index))
+                }
+                None => item.clone(),
+            },
+            Ast::List(xs) => {
+                let replaced = replace_placeholders(xs, arity)?;
+                changed |= !Rc::ptr_eq(xs, &replaced);
+                Ast::List(replaced)
+            }
+            Ast::Vector(xs) => {
+                let replaced = replace_placeholders(xs, arity)?;
+                changed |= !Rc::ptr_eq(xs, &replaced);
+                Ast::Vector(replaced)
             }
-            '(' => state.try_push_with(c, i, Token::LeftParen),
-            ')' => state.try_push_with(c, i, Token::RightParen),
-            _ => {
-                if c.is_whitespace() {
-                    state.try_push(c, i);
+            Ast::HashMap(map) => {
+                let values = Rc::new(map.values().cloned().collect());
+                let replaced = replace_placeholders(&values, arity)?;
+                if Rc::ptr_eq(&values, &replaced) {
+                    item.clone()
                 } else {
-                    state.buffer.push(c)
+                    changed = true;
+                    let entries = map.keys().cloned().zip(replaced.iter().cloned());
+                    Ast::HashMap(Rc::new(entries.collect()))
                 }
             }
+            other => other.clone(),
+        };
+        items.push(replaced);
+    }
+
+    if !changed {
+        return Ok(Rc::clone(list));
+    }
+    let replaced = Rc::new(items);
+    coverage::list_replaced(list, &replaced);
+    Ok(replaced)
+}
+
+/// Reads `#"a ${expr} b"` as `(str "a " expr " b")`
+fn interpolate(text: &str) -> Result<Ast, ParserError> {
+    let mut parts = vec![Ast::Symbol("str".to_owned())];
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        let inner = &rest[start + 2..];
+        let end = matching_brace(inner)
+            .ok_or_else(|| ParserError::InvalidInterpolation(text.to_owned()))?;
+
+        if start > 0 {
+            parts.push(Ast::String(rest[..start].to_owned()));
         }
+        let forms = read_all(&inner[..end])?;
+        match <[Ast; 1]>::try_from(forms) {
+            Ok([form]) => parts.push(form),
+            Err(_) => return Err(ParserError::InvalidInterpolation(text.to_owned())),
+        }
+        rest = &inner[end + 1..];
+    }
+
+    if parts.len() == 1 {
+        return Ok(Ast::String(text.to_owned()));
+    }
+    if !rest.is_empty() {
+        parts.push(Ast::String(rest.to_owned()));
     }
+    Ok(Ast::List(Rc::new(parts)))
+}
+
+/// Byte offset of the `}` closing an interpolation, allowing nested braces
+fn matching_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Builtin function, called with its name, the evaluated arguments and the calling environment
+pub type EnvFunction = fn(&str, Vec<Ast>, &Rc<RefCell<Environment>>) -> Result<Ast, ReplError>;
 
-    state.push_buffer(text.len(), false);
+pub type ClosureFn = dyn Fn(Vec<Ast>, &Rc<RefCell<Environment>>) -> Result<Ast, ReplError>;
 
-    state.tokens
+/// Function created at runtime by a builtin, e.g. by `curry`, which can capture values
+pub struct Closure {
+    pub name: String,
+    pub f: Box<ClosureFn>,
 }
 
-fn get_token(token: &str, is_str: bool) -> Token {
-    if is_str {
-        return Token::String(token.to_owned());
+/// Content of an atom, counted as a live atom in the runtime statistics until it is dropped
+pub struct AtomCell(RefCell<Ast>);
+
+impl AtomCell {
+    pub fn new(value: Ast) -> AtomCell {
+        stats::atom_created();
+        AtomCell(RefCell::new(value))
     }
+}
 
-    match token.parse::<i64>() {
-        Ok(n) => Token::Integer(n),
-        Err(_) => Token::Symbol(token.to_owned()),
+impl Deref for AtomCell {
+    type Target = RefCell<Ast>;
+
+    fn deref(&self) -> &RefCell<Ast> {
+        &self.0
     }
 }
 
-pub type EnvFunction = fn(&str, Vec<Ast>) -> Result<Ast, ReplError>;
+impl Drop for AtomCell {
+    fn drop(&mut self) {
+        stats::atom_dropped();
+    }
+}
 
 #[derive(Clone)]
 pub enum Ast {
     Symbol(String),
     Integer(i64),
+    Float(f64),
+    /// `:name`, stored without the colon
+    Keyword(String),
     Boolean(bool),
     String(String),
-    List(Vec<Ast>),
-    Function(Box<UserFunction>),
+    List(Rc<Vec<Ast>>),
+    /// `[a b]`, which evaluates to a vector of its evaluated elements
+    Vector(Rc<Vec<Ast>>),
+    /// `{:a 1}`, whose values are evaluated like the elements of a vector. Entries are ordered by
+    /// key, so a map always prints the same.
+    HashMap(Rc<BTreeMap<MapKey, Ast>>),
+    Function(Rc<UserFunction>),
     Builtin(String, EnvFunction),
+    Closure(Rc<Closure>),
     Nil,
-    Atom(Rc<RefCell<Ast>>),
+    Atom(Rc<AtomCell>),
+    /// Growable vector that is modified in place, unlike lists
+    MutableVector(Rc<RefCell<Vec<Ast>>>),
+    /// Mutable array of unboxed integers, for numeric code over many elements
+    IntArray(Rc<RefCell<Vec<i64>>>),
+    /// Mutable array of unboxed floats
+    FloatArray(Rc<RefCell<Vec<f64>>>),
+    /// String that is appended to in place, for building strings piece by piece
+    StringBuilder(Rc<RefCell<String>>),
+    Port(Rc<OutputPort>),
+    Seq(Rc<LazySeq>),
+    Reader(Rc<FormReader>),
+    /// Environment as a value, made by `fork-env` to evaluate code in with `with-env`/`eval-in`
+    Env(Rc<RefCell<Environment>>),
+    /// Reference to a parameter or let binding, see `resolve_function_body`
+    Local(usize, usize, String),
+}
+
+/// Key of a hash-map. Only values whose equality doesn't change and is cheap to check can be
+/// keys.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MapKey {
+    Integer(i64),
+    String(String),
+    Keyword(String),
+}
+
+impl MapKey {
+    pub fn from_ast(ast: &Ast) -> Option<MapKey> {
+        match ast {
+            Ast::Integer(n) => Some(MapKey::Integer(*n)),
+            Ast::String(s) => Some(MapKey::String(s.clone())),
+            Ast::Keyword(k) => Some(MapKey::Keyword(k.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_ast(&self) -> Ast {
+        match self {
+            MapKey::Integer(n) => Ast::Integer(*n),
+            MapKey::String(s) => Ast::String(s.clone()),
+            MapKey::Keyword(k) => Ast::Keyword(k.clone()),
+        }
+    }
+}
+
+/// Hash-map from keywords with the given names to values, how builtins return records
+pub fn keyword_map(entries: Vec<(&str, Ast)>) -> Ast {
+    Ast::HashMap(Rc::new(
+        entries
+            .into_iter()
+            .map(|(key, value)| (MapKey::Keyword(key.to_owned()), value))
+            .collect(),
+    ))
 }
 
 #[derive(Clone)]
 pub struct UserFunction {
-    pub params: Vec<String>,
+    pub params: Rc<Vec<String>>,
     pub body: Ast,
     pub env: Rc<RefCell<Environment>>,
+    /// The `fun*` form as it was written, since `body` is rewritten by the resolver
+    pub source: Ast,
+    /// Set by `defmacro!`: calls get the unevaluated arguments and the result is evaluated in
+    /// place of the call
+    pub is_macro: bool,
+    /// Written `(a & rest)`: the last parameter is bound to a list of the remaining arguments
+    pub variadic: bool,
+}
+
+impl UserFunction {
+    /// Parameters as written, with `&` before the rest parameter
+    pub fn written_params(&self) -> Vec<String> {
+        let mut params = self.params.to_vec();
+        if self.variadic {
+            params.insert(params.len() - 1, "&".to_owned());
+        }
+        params
+    }
 }
 
 impl Debug for Ast {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Ast::Integer(n) => write!(f, "{}", n),
-            Ast::String(str) => write!(f, "{}", str),
-            Ast::Function(_) => write!(f, "<function>"),
-            Ast::Builtin(name, _) => write!(f, "<builtin:{}>", name),
-            Ast::List(xs) => write!(f, "{:?}", xs),
-            Ast::Symbol(s) => write!(f, "{}", s),
-            Ast::Boolean(s) => write!(f, "{}", s),
-            Ast::Nil => write!(f, "nil"),
-            Ast::Atom(ast) => write!(f, "<atom:{:?}>", ast.borrow()),
-        }
+        write!(f, "{}", pr_str(self, false))
     }
 }
 
 pub enum ParserError {
-    ExpectedGot(usize, Token, Token),
+    ExpectedGot(Span, Token, Token),
     ExpectedGotEof(Token),
     ExpectedAnyGotEof,
-    TypeMismatch(String, u32, String, Ast),
-    ExpectedSymbol,
+    Unexpected(Span, Token),
+    UnknownReaderMacro(String),
+    TooDeeplyNested(Span, usize),
+    InvalidDispatch(Ast),
+    InvalidInterpolation(String),
+    NestedAnonymousFunction,
+    /// Span from the opening quote to the end of the input
+    UnterminatedString(Span),
+    /// Span of the opening brace of a map literal with a key missing its value
+    OddMapLiteral(Span),
+    /// Span of the opening brace of a map literal and the key that can't be one
+    InvalidMapKey(Span, Ast),
+}
+
+impl ParserError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParserError::ExpectedGot(span, ..)
+            | ParserError::Unexpected(span, _)
+            | ParserError::TooDeeplyNested(span, _)
+            | ParserError::UnterminatedString(span)
+            | ParserError::OddMapLiteral(span)
+            | ParserError::InvalidMapKey(span, _) => Some(*span),
+            _ => None,
+        }
+    }
 }
 
 impl Debug for ParserError {
@@ -149,101 +422,288 @@ impl Debug for ParserError {
         match self {
             ParserError::ExpectedGot(pos, expected, actual) => write!(
                 f,
-                "Error on position {}: Expected '{:?}', but got '{:?}'",
+                "At position {}: Expected '{:?}', but got '{:?}'",
                 pos, expected, actual
             ),
             ParserError::ExpectedGotEof(expected) => {
-                write!(f, "Error: Expected '{:?}', but got EOF", expected)
+                write!(f, "Expected '{:?}', but got EOF", expected)
+            }
+            ParserError::ExpectedAnyGotEof => write!(f, "Expected any input but got EOF"),
+            ParserError::Unexpected(pos, token) => {
+                write!(f, "At position {}: Unexpected '{:?}'", pos, token)
             }
-            ParserError::ExpectedAnyGotEof => write!(f, "Error: Expected any input but got EOF"),
 
-            ParserError::TypeMismatch(fn_name, index, expected, got) => write!(
+            ParserError::UnknownReaderMacro(prefix) => {
+                write!(f, "No reader macro registered for '{}'", prefix)
+            }
+            ParserError::InvalidDispatch(form) => {
+                write!(f, "'#' can't be followed by {:?}", form)
+            }
+            ParserError::InvalidInterpolation(text) => write!(
                 f,
-                "Type mismatch: Expected {} at argument position {} of {} but got {:?}",
-                expected, index, fn_name, got
+                "Interpolated string '{}' needs exactly one form inside each ${{...}}",
+                text
+            ),
+            ParserError::UnterminatedString(pos) => {
+                write!(
+                    f,
+                    "At position {}: String is missing its closing quote",
+                    pos
+                )
+            }
+            ParserError::OddMapLiteral(pos) => {
+                write!(
+                    f,
+                    "At position {}: Map literal has a key without a value",
+                    pos
+                )
+            }
+            ParserError::InvalidMapKey(pos, key) => write!(
+                f,
+                "At position {}: Map keys must be strings, keywords or integers, not {:?}",
+                pos, key
+            ),
+            ParserError::NestedAnonymousFunction => {
+                write!(f, "'#(...)' functions can't be nested")
+            }
+            ParserError::TooDeeplyNested(pos, max) => write!(
+                f,
+                "At position {}: Forms can't be nested more than {} levels deep",
+                pos, max
             ),
-            ParserError::ExpectedSymbol => write!(f, "Expected symbol"),
         }
     }
 }
 
-fn expect(
-    it: &mut Peekable<IntoIter<PositionalToken>>,
-    expected: Token,
-) -> Result<(), ParserError> {
+/// Nesting depth used unless `set_max_nesting_depth` is called
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 1000;
+
+thread_local! {
+    static MAX_NESTING_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_NESTING_DEPTH) };
+}
+
+/// Sets how deeply lists and reader macros may be nested before parsing fails, which keeps
+/// pathological input from overflowing the stack
+pub fn set_max_nesting_depth(depth: usize) {
+    MAX_NESTING_DEPTH.with(|max| max.set(depth));
+}
+
+pub fn max_nesting_depth() -> usize {
+    MAX_NESTING_DEPTH.with(Cell::get)
+}
+
+fn expect(it: &mut Peekable<IntoIter<SpannedToken>>, expected: Token) -> Result<(), ParserError> {
     match it.next() {
         None => Err(ParserError::ExpectedGotEof(expected)),
-        Some((i, t)) => {
-            if t == expected {
+        Some(SpannedToken { token, span }) => {
+            if token == expected {
                 Ok(())
             } else {
-                Err(ParserError::ExpectedGot(i, expected, t))
+                Err(ParserError::ExpectedGot(span, expected, token))
             }
         }
     }
 }
 
-fn peek(it: &mut Peekable<IntoIter<PositionalToken>>) -> Result<&Token, ParserError> {
-    Ok(&it.peek().ok_or(ParserError::ExpectedAnyGotEof)?.1)
+fn peek(it: &mut Peekable<IntoIter<SpannedToken>>) -> Result<&Token, ParserError> {
+    Ok(&it.peek().ok_or(ParserError::ExpectedAnyGotEof)?.token)
 }
 
-fn next(it: &mut Peekable<IntoIter<PositionalToken>>) -> Result<Token, ParserError> {
+fn next(it: &mut Peekable<IntoIter<SpannedToken>>) -> Result<Token, ParserError> {
     match it.next() {
         None => Err(ParserError::ExpectedAnyGotEof),
-        Some((_i, t)) => Ok(t),
+        Some(SpannedToken { token, .. }) => Ok(token),
     }
 }
 
-fn parse_list(it: &mut Peekable<IntoIter<PositionalToken>>) -> Result<Ast, ParserError> {
-    expect(it, Token::LeftParen)?;
+/// Parses the forms between an `open` token and the matching `close` token
+fn parse_delimited(
+    it: &mut Peekable<IntoIter<SpannedToken>>,
+    depth: usize,
+    open: Token,
+    close: Token,
+) -> Result<Vec<Ast>, ParserError> {
+    expect(it, open)?;
 
     let mut items = vec![];
-    while *peek(it)? != Token::RightParen {
-        items.push(parse_any(it)?);
+    skip_discarded(it, depth + 1)?;
+    while *peek(it)? != close {
+        items.push(parse_any(it, depth + 1)?);
+        skip_discarded(it, depth + 1)?;
     }
 
-    expect(it, Token::RightParen)?;
+    expect(it, close)?;
+    Ok(items)
+}
+
+fn parse_list(it: &mut Peekable<IntoIter<SpannedToken>>, depth: usize) -> Result<Ast, ParserError> {
+    let line = it.peek().map_or(0, |t| t.span.start.line);
+    let items = parse_delimited(it, depth, Token::LeftParen, Token::RightParen)?;
 
-    Ok(Ast::List(items))
+    let list = Rc::new(items);
+    coverage::list_read(&list, line);
+    Ok(Ast::List(list))
 }
 
-fn parse_atom(it: &mut Peekable<IntoIter<PositionalToken>>) -> Result<Ast, ParserError> {
-    let atom = next(it)?;
+fn parse_vector(
+    it: &mut Peekable<IntoIter<SpannedToken>>,
+    depth: usize,
+) -> Result<Ast, ParserError> {
+    let items = parse_delimited(it, depth, Token::LeftBracket, Token::RightBracket)?;
+    Ok(Ast::Vector(Rc::new(items)))
+}
+
+/// Parses `{key value ...}`. Keys are read as they are written, so they must be literals.
+fn parse_map(it: &mut Peekable<IntoIter<SpannedToken>>, depth: usize) -> Result<Ast, ParserError> {
+    let span = it.peek().map_or_else(Span::default, |t| t.span);
+    let items = parse_delimited(it, depth, Token::LeftBrace, Token::RightBrace)?;
+    if !items.len().is_multiple_of(2) {
+        return Err(ParserError::OddMapLiteral(span));
+    }
 
-    Ok(match atom {
-        Token::LeftParen => panic!("wtf"),
-        Token::RightParen => panic!("wtf"),
+    let mut map = BTreeMap::new();
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        let key = MapKey::from_ast(&key).ok_or(ParserError::InvalidMapKey(span, key))?;
+        map.insert(key, value);
+    }
+    Ok(Ast::HashMap(Rc::new(map)))
+}
+
+fn parse_atom(it: &mut Peekable<IntoIter<SpannedToken>>) -> Result<Ast, ParserError> {
+    let SpannedToken { token, span } = it.next().ok_or(ParserError::ExpectedAnyGotEof)?;
+
+    Ok(match token {
+        Token::LeftParen | Token::LeftBracket | Token::LeftBrace => panic!("wtf"),
+        Token::RightParen | Token::RightBracket | Token::RightBrace => {
+            return Err(ParserError::Unexpected(span, token))
+        }
         Token::Symbol(s) => translate_symbol(&s),
         Token::Integer(n) => Ast::Integer(n),
+        Token::Float(x) => Ast::Float(x),
         Token::String(str) => Ast::String(str),
+        Token::UnterminatedString(_) => return Err(ParserError::UnterminatedString(span)),
+        Token::ReaderMacro(_) | Token::Discard => panic!("wtf"),
     })
 }
 
+fn parse_reader_macro(
+    it: &mut Peekable<IntoIter<SpannedToken>>,
+    depth: usize,
+) -> Result<Ast, ParserError> {
+    let prefix = match next(it)? {
+        Token::ReaderMacro(prefix) => prefix,
+        _ => panic!("wtf"),
+    };
+
+    let transform = get_reader_macro(&prefix).ok_or(ParserError::UnknownReaderMacro(prefix))?;
+    transform(parse_any(it, depth + 1)?)
+}
+
+/// Reads and drops the forms following `#_` tokens
+fn skip_discarded(
+    it: &mut Peekable<IntoIter<SpannedToken>>,
+    depth: usize,
+) -> Result<(), ParserError> {
+    while it.peek().is_some_and(|t| t.token == Token::Discard) {
+        it.next();
+        parse_any(it, depth)?;
+    }
+    Ok(())
+}
+
 fn translate_symbol(symbol: &str) -> Ast {
     match symbol {
         "true" => Ast::Boolean(true),
         "false" => Ast::Boolean(false),
         "nil" => Ast::Nil,
-        other => Ast::Symbol(other.to_owned()),
+        keyword if keyword.len() > 1 && keyword.starts_with(':') => {
+            Ast::Keyword(keyword[1..].to_owned())
+        }
+        other => {
+            stats::symbol_read(other);
+            Ast::Symbol(other.to_owned())
+        }
     }
 }
 
-fn parse_any(it: &mut Peekable<IntoIter<PositionalToken>>) -> Result<Ast, ParserError> {
+fn parse_any(it: &mut Peekable<IntoIter<SpannedToken>>, depth: usize) -> Result<Ast, ParserError> {
+    let max_depth = max_nesting_depth();
+    if depth >= max_depth {
+        let span = it.peek().map_or_else(Span::default, |t| t.span);
+        return Err(ParserError::TooDeeplyNested(span, max_depth));
+    }
+
+    skip_discarded(it, depth)?;
     let next = peek(it)?;
 
     if *next == Token::LeftParen {
-        parse_list(it)
+        parse_list(it, depth)
+    } else if *next == Token::LeftBracket {
+        parse_vector(it, depth)
+    } else if *next == Token::LeftBrace {
+        parse_map(it, depth)
+    } else if *next == Token::ReaderMacro(String::new()) {
+        parse_reader_macro(it, depth)
     } else {
         parse_atom(it)
     }
 }
 
+/// Parses every top-level form of the text
+pub fn read_all(text: &str) -> Result<Vec<Ast>, ParserError> {
+    let tokens = tokenize(text);
+    let mut it = tokens.into_iter().peekable();
+
+    let mut forms = vec![];
+    skip_discarded(&mut it, 0)?;
+    while it.peek().is_some() {
+        forms.push(parse_any(&mut it, 0)?);
+        skip_discarded(&mut it, 0)?;
+    }
+
+    Ok(forms)
+}
+
+/// Reads the forms of a text one at a time
+pub struct FormReader {
+    /// Where the text came from, e.g. `file a.bst`
+    pub origin: String,
+    text: String,
+    tokens: RefCell<Peekable<IntoIter<SpannedToken>>>,
+}
+
+impl FormReader {
+    pub fn new(origin: String, text: String) -> FormReader {
+        let tokens = tokenize(&text).into_iter().peekable();
+        FormReader {
+            origin,
+            text,
+            tokens: RefCell::new(tokens),
+        }
+    }
+
+    /// Parses the next form, or returns `None` once all forms have been read
+    pub fn read_form(&self) -> Result<Option<Ast>, ReplError> {
+        let mut tokens = self.tokens.borrow_mut();
+        skip_discarded(&mut tokens, 0)
+            .map_err(|err| ReplError::from(err).with_source(&self.text))?;
+        if tokens.peek().is_none() {
+            return Ok(None);
+        }
+
+        parse_any(&mut tokens, 0)
+            .map(Some)
+            .map_err(|err| ReplError::from(err).with_source(&self.text))
+    }
+}
+
 impl FromStr for Ast {
     type Err = ParserError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let tokens = tokenize(s.trim());
+        let tokens = tokenize(s);
         let mut it = tokens.into_iter().peekable();
-        parse_any(&mut it)
+        parse_any(&mut it, 0)
     }
 }
@@ -0,0 +1,359 @@
+use crate::parser::{Ast, AtomCell, MapKey};
+use crate::root_env::{lookup, Environment};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+/// Limits for printing large values, taken from `*print-length*` and `*print-depth*`.
+/// Elements beyond the length and lists nested deeper than the depth are printed as `...`.
+#[derive(Clone, Copy, Default)]
+pub struct PrintLimits {
+    pub length: Option<usize>,
+    pub depth: Option<usize>,
+}
+
+impl PrintLimits {
+    /// Reads the limits from the environment, where nil or a missing binding means no limit
+    pub fn from_env(env: &Rc<RefCell<Environment>>) -> PrintLimits {
+        let limit = |name: &str| match lookup(name.to_owned(), env) {
+            Ok(Ast::Integer(n)) if n >= 0 => Some(n as usize),
+            _ => None,
+        };
+
+        PrintLimits {
+            length: limit("*print-length*"),
+            depth: limit("*print-depth*"),
+        }
+    }
+}
+
+/// Renders a value. In readable mode strings are quoted, so that the output can be read back in.
+pub fn pr_str(ast: &Ast, readable: bool) -> String {
+    pr_str_limited(ast, readable, PrintLimits::default())
+}
+
+pub fn pr_str_limited(ast: &Ast, readable: bool, limits: PrintLimits) -> String {
+    print(ast, readable, limits, 0)
+}
+
+/// String literal that reads back as `s`
+fn escape_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn print(ast: &Ast, readable: bool, limits: PrintLimits, depth: usize) -> String {
+    match ast {
+        Ast::Integer(n) => n.to_string(),
+        // Debug keeps the decimal point of whole numbers and uses exponents for large ones
+        Ast::Float(x) => format!("{:?}", x),
+        Ast::String(str) => {
+            if readable {
+                escape_string(str)
+            } else {
+                str.clone()
+            }
+        }
+        Ast::Function(f) if f.is_macro => "<macro>".to_owned(),
+        Ast::Function(_) => "<function>".to_owned(),
+        Ast::Builtin(name, _) => format!("<builtin:{}>", name),
+        Ast::Closure(closure) => format!("<function:{}>", closure.name),
+        Ast::List(xs) => print_items(xs, ("(", ")"), readable, limits, depth),
+        Ast::Vector(xs) => print_items(xs, ("[", "]"), readable, limits, depth),
+        Ast::HashMap(map) => print_map(map, readable, limits, depth),
+        Ast::Symbol(s) => s.clone(),
+        Ast::Keyword(k) => format!(":{}", k),
+        Ast::Boolean(b) => b.to_string(),
+        Ast::Nil => "nil".to_owned(),
+        Ast::Atom(atom) => print_atom(atom, readable, limits, depth),
+        Ast::MutableVector(v) => print_mutable_vector(v, readable, limits, depth),
+        Ast::IntArray(a) => print_array("int-array", &a.borrow(), limits),
+        Ast::FloatArray(a) => print_array("float-array", &a.borrow(), limits),
+        Ast::StringBuilder(sb) => {
+            let content = Ast::String(sb.borrow().clone());
+            format!(
+                "(string-builder {})",
+                print(&content, readable, limits, depth)
+            )
+        }
+        Ast::Port(port) => format!("<port:{}>", port.describe()),
+        Ast::Seq(seq) => format!("<seq:{}>", seq.origin),
+        Ast::Reader(reader) => format!("<reader:{}>", reader.origin),
+        Ast::Env(_) => "<environment>".to_owned(),
+        Ast::Local(_, _, name) => name.clone(),
+    }
+}
+
+/// Prints the elements of a list or vector between the delimiters
+fn print_items(
+    xs: &[Ast],
+    (open, close): (&str, &str),
+    readable: bool,
+    limits: PrintLimits,
+    depth: usize,
+) -> String {
+    if limits.depth.is_some_and(|max| depth >= max) {
+        return "...".to_owned();
+    }
+
+    let shown = limits.length.unwrap_or(xs.len()).min(xs.len());
+    let mut items: Vec<String> = xs[..shown]
+        .iter()
+        .map(|x| print(x, readable, limits, depth + 1))
+        .collect();
+    if shown < xs.len() {
+        items.push("...".to_owned());
+    }
+    format!("{}{}{}", open, items.join(" "), close)
+}
+
+/// Prints a hash-map as `{key value ...}`, the length limit counting entries
+fn print_map(
+    map: &BTreeMap<MapKey, Ast>,
+    readable: bool,
+    limits: PrintLimits,
+    depth: usize,
+) -> String {
+    if limits.depth.is_some_and(|max| depth >= max) {
+        return "...".to_owned();
+    }
+
+    let shown = limits.length.unwrap_or(map.len()).min(map.len());
+    let mut items: Vec<String> = map
+        .iter()
+        .take(shown)
+        .map(|(key, value)| {
+            let key = print(&key.to_ast(), readable, limits, depth + 1);
+            format!("{} {}", key, print(value, readable, limits, depth + 1))
+        })
+        .collect();
+    if shown < map.len() {
+        items.push("...".to_owned());
+    }
+    format!("{{{}}}", items.join(" "))
+}
+
+thread_local! {
+    /// Atoms and mutable vectors whose content is being printed, to detect ones that contain
+    /// themselves
+    static PRINTING: RefCell<Vec<*const ()>> = const { RefCell::new(vec![]) };
+}
+
+/// Prints the content of a mutable value with `print_content`, or `#<cycle>` if the value is
+/// reached again while printing its own content
+fn print_guarded<T>(value: &Rc<T>, print_content: impl FnOnce() -> String) -> String {
+    let ptr = Rc::as_ptr(value) as *const ();
+    if PRINTING.with(|printing| printing.borrow().contains(&ptr)) {
+        return "#<cycle>".to_owned();
+    }
+
+    PRINTING.with(|printing| printing.borrow_mut().push(ptr));
+    let printed = print_content();
+    PRINTING.with(|printing| printing.borrow_mut().pop());
+    printed
+}
+
+/// Prints an atom as `(atom <value>)`. An atom reached again while printing its own content is
+/// printed as `#<cycle>`, an atom whose content is being modified as `(atom ...)`.
+fn print_atom(atom: &Rc<AtomCell>, readable: bool, limits: PrintLimits, depth: usize) -> String {
+    print_guarded(atom, || {
+        let content = match atom.try_borrow() {
+            Ok(content) => print(&content, readable, limits, depth + 1),
+            Err(_) => "...".to_owned(),
+        };
+        format!("(atom {})", content)
+    })
+}
+
+/// Prints a mutable vector as `(mutable-vector <elements>)`
+fn print_mutable_vector(
+    v: &Rc<RefCell<Vec<Ast>>>,
+    readable: bool,
+    limits: PrintLimits,
+    depth: usize,
+) -> String {
+    print_guarded(v, || {
+        let xs = match v.try_borrow() {
+            Ok(xs) => xs,
+            Err(_) => return "(mutable-vector ...)".to_owned(),
+        };
+        if limits.depth.is_some_and(|max| depth >= max) {
+            return "...".to_owned();
+        }
+
+        let shown = limits.length.unwrap_or(xs.len()).min(xs.len());
+        let mut items = vec!["mutable-vector".to_owned()];
+        items.extend(
+            xs[..shown]
+                .iter()
+                .map(|x| print(x, readable, limits, depth + 1)),
+        );
+        if shown < xs.len() {
+            items.push("...".to_owned());
+        }
+        format!("({})", items.join(" "))
+    })
+}
+
+/// Prints an int or float array as `(<constructor> <elements>)`
+fn print_array<T: std::fmt::Debug>(constructor: &str, xs: &[T], limits: PrintLimits) -> String {
+    let shown = limits.length.unwrap_or(xs.len()).min(xs.len());
+    let mut items = vec![constructor.to_owned()];
+    items.extend(xs[..shown].iter().map(|x| format!("{:?}", x)));
+    if shown < xs.len() {
+        items.push("...".to_owned());
+    }
+    format!("({})", items.join(" "))
+}
+
+pub fn pr_seq(xs: &[Ast], readable: bool, separator: &str, limits: PrintLimits) -> String {
+    xs.iter()
+        .map(|x| pr_str_limited(x, readable, limits))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Width used for pretty-printing unless a different one is requested
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Renders a value like `pr_str_limited`, but breaks lists, vectors and maps that don't fit into
+/// the width across lines, one element per line and indented to align with the first element.
+/// A map entry stays on one line, its value indented past the key if it is broken up itself.
+pub fn pretty_print(ast: &Ast, readable: bool, limits: PrintLimits, width: usize) -> String {
+    pretty(ast, readable, limits, width, 0, 0)
+}
+
+fn pretty(
+    ast: &Ast,
+    readable: bool,
+    limits: PrintLimits,
+    width: usize,
+    indent: usize,
+    depth: usize,
+) -> String {
+    let flat = print(ast, readable, limits, depth);
+    if indent + flat.chars().count() <= width || limits.depth.is_some_and(|max| depth >= max) {
+        return flat;
+    }
+
+    let layout = Layout {
+        readable,
+        limits,
+        width,
+        indent: indent + 1,
+        depth: depth + 1,
+    };
+    match ast {
+        Ast::List(xs) if !xs.is_empty() => format!("({})", layout.items(xs)),
+        Ast::Vector(xs) if !xs.is_empty() => format!("[{}]", layout.items(xs)),
+        Ast::HashMap(map) if !map.is_empty() => format!("{{{}}}", layout.entries(map)),
+        _ => flat,
+    }
+}
+
+/// Settings for laying out the elements of a collection that is broken across lines
+struct Layout {
+    readable: bool,
+    limits: PrintLimits,
+    width: usize,
+    /// Column of the first element, which the others are aligned with
+    indent: usize,
+    depth: usize,
+}
+
+impl Layout {
+    fn pretty(&self, ast: &Ast, indent: usize) -> String {
+        pretty(
+            ast,
+            self.readable,
+            self.limits,
+            self.width,
+            indent,
+            self.depth,
+        )
+    }
+
+    fn lines(&self, mut items: Vec<String>, total: usize) -> String {
+        if items.len() < total {
+            items.push("...".to_owned());
+        }
+        items.join(&format!("\n{}", " ".repeat(self.indent)))
+    }
+
+    fn items(&self, xs: &[Ast]) -> String {
+        let shown = self.limits.length.unwrap_or(xs.len()).min(xs.len());
+        let items = xs[..shown]
+            .iter()
+            .map(|x| self.pretty(x, self.indent))
+            .collect();
+        self.lines(items, xs.len())
+    }
+
+    fn entries(&self, map: &BTreeMap<MapKey, Ast>) -> String {
+        let shown = self.limits.length.unwrap_or(map.len()).min(map.len());
+        let items = map
+            .iter()
+            .take(shown)
+            .map(|(key, value)| {
+                let key = print(&key.to_ast(), self.readable, self.limits, self.depth);
+                let value = self.pretty(value, self.indent + key.chars().count() + 1);
+                format!("{} {}", key, value)
+            })
+            .collect();
+        self.lines(items, map.len())
+    }
+}
+
+/// Renders the structure of a parsed form as an indented tree, one node per line
+pub fn describe_ast(ast: &Ast) -> String {
+    let mut lines = vec![];
+    describe_node(ast, 0, &mut lines);
+    lines.join("\n")
+}
+
+fn describe_node(ast: &Ast, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    match ast {
+        Ast::List(xs) => {
+            lines.push(format!("{}List ({} elements)", indent, xs.len()));
+            for x in xs.iter() {
+                describe_node(x, depth + 1, lines);
+            }
+        }
+        Ast::Symbol(s) => lines.push(format!("{}Symbol {}", indent, s)),
+        Ast::Vector(xs) => {
+            lines.push(format!("{}Vector ({} elements)", indent, xs.len()));
+            for x in xs.iter() {
+                describe_node(x, depth + 1, lines);
+            }
+        }
+        Ast::HashMap(map) => {
+            lines.push(format!("{}HashMap ({} entries)", indent, map.len()));
+            for (key, value) in map.iter() {
+                describe_node(&key.to_ast(), depth + 1, lines);
+                describe_node(value, depth + 1, lines);
+            }
+        }
+        Ast::Keyword(k) => lines.push(format!("{}Keyword {}", indent, k)),
+        Ast::Integer(n) => lines.push(format!("{}Integer {}", indent, n)),
+        Ast::Boolean(b) => lines.push(format!("{}Boolean {}", indent, b)),
+        Ast::String(_) => lines.push(format!("{}String {}", indent, pr_str(ast, true))),
+        Ast::Nil => lines.push(format!("{}Nil", indent)),
+        Ast::Local(depth, slot, name) => lines.push(format!(
+            "{}Local {} (depth {}, slot {})",
+            indent, name, depth, slot
+        )),
+        other => lines.push(format!("{}{}", indent, pr_str(other, true))),
+    }
+}
@@ -0,0 +1,203 @@
+//! Python bindings, built with the `python` feature. The library then doubles as the extension
+//! module `beesting`, exposing an `Interpreter` class:
+//!
+//! ```text
+//! from beesting import Interpreter
+//!
+//! bee = Interpreter()
+//! bee.register("py-upper", lambda s: s.upper())
+//! bee.set("limit", 10)
+//! bee.eval_str('(py-upper "hi")')  # 'HI'
+//! bee.get("limit")                  # 10
+//! ```
+//!
+//! nil is `None`; booleans, integers, floats and strings map to their Python counterparts,
+//! keywords to their name, lists and vectors to lists and hash-maps to dicts. Other values, such
+//! as functions, are wrapped in a `Value` that can be passed back to the interpreter.
+
+// The code generated by `#[pymethods]` converts `PyErr` into itself
+#![allow(clippy::useless_conversion)]
+
+use crate::errors::{ReplError, RuntimeError};
+use crate::interpreter::Interpreter;
+use crate::parser::{read_all, Ast, Closure, MapKey};
+use crate::printer::pr_str;
+use pyo3::exceptions::{PyKeyError, PyRuntimeError, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyTuple};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+/// Errors of the interpreter surface as `RuntimeError`
+fn to_py_err(err: ReplError) -> PyErr {
+    PyRuntimeError::new_err(format!("{:?}", err))
+}
+
+/// Value without a Python counterpart, e.g. a function
+#[pyclass(unsendable, module = "beesting")]
+pub struct Value {
+    ast: Ast,
+}
+
+#[pymethods]
+impl Value {
+    fn __repr__(&self) -> String {
+        format!("Value({})", pr_str(&self.ast, true))
+    }
+}
+
+fn to_py(py: Python<'_>, ast: &Ast) -> PyResult<PyObject> {
+    Ok(match ast {
+        Ast::Nil => py.None(),
+        Ast::Boolean(b) => b.into_py(py),
+        Ast::Integer(n) => n.into_py(py),
+        Ast::Float(x) => x.into_py(py),
+        Ast::String(s) | Ast::Keyword(s) => s.into_py(py),
+        Ast::List(xs) | Ast::Vector(xs) => {
+            let items = xs
+                .iter()
+                .map(|x| to_py(py, x))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new_bound(py, items).into_py(py)
+        }
+        Ast::HashMap(map) => {
+            let dict = PyDict::new_bound(py);
+            for (key, value) in map.iter() {
+                dict.set_item(to_py(py, &key.to_ast())?, to_py(py, value)?)?;
+            }
+            dict.into_py(py)
+        }
+        other => Py::new(py, Value { ast: other.clone() })?.into_py(py),
+    })
+}
+
+fn from_py(obj: &Bound<'_, PyAny>) -> PyResult<Ast> {
+    if obj.is_none() {
+        return Ok(Ast::Nil);
+    }
+    // Checked before integers, since bools are integers in Python
+    if let Ok(b) = obj.downcast_exact::<pyo3::types::PyBool>() {
+        return Ok(Ast::Boolean(b.is_true()));
+    }
+    if let Ok(value) = obj.downcast::<Value>() {
+        return Ok(value.borrow().ast.clone());
+    }
+    if let Ok(n) = obj.extract::<i64>() {
+        return Ok(Ast::Integer(n));
+    }
+    if let Ok(x) = obj.extract::<f64>() {
+        return Ok(Ast::Float(x));
+    }
+    if let Ok(s) = obj.extract::<String>() {
+        return Ok(Ast::String(s));
+    }
+    if let Ok(list) = obj.downcast::<PyList>() {
+        let xs = list.iter().map(|x| from_py(&x)).collect::<PyResult<_>>()?;
+        return Ok(Ast::List(Rc::new(xs)));
+    }
+    if let Ok(tuple) = obj.downcast::<PyTuple>() {
+        let xs = tuple.iter().map(|x| from_py(&x)).collect::<PyResult<_>>()?;
+        return Ok(Ast::List(Rc::new(xs)));
+    }
+    if let Ok(dict) = obj.downcast::<PyDict>() {
+        let mut map = BTreeMap::new();
+        for (key, value) in dict.iter() {
+            let key = MapKey::from_ast(&from_py(&key)?).ok_or_else(|| {
+                PyTypeError::new_err("Map keys must be strings, keywords or integers")
+            })?;
+            map.insert(key, from_py(&value)?);
+        }
+        return Ok(Ast::HashMap(Rc::new(map)));
+    }
+
+    let type_name = obj.get_type().name()?.to_string();
+    Err(PyTypeError::new_err(format!(
+        "Can't convert {} to a beesting value",
+        type_name
+    )))
+}
+
+/// An interpreter with its own root environment. It can only be used on the thread that
+/// created it.
+#[pyclass(unsendable, name = "Interpreter", module = "beesting")]
+pub struct PyInterpreter {
+    interpreter: Interpreter,
+}
+
+#[pymethods]
+impl PyInterpreter {
+    #[new]
+    fn new() -> PyInterpreter {
+        PyInterpreter {
+            interpreter: Interpreter::new(),
+        }
+    }
+
+    /// Evaluates all forms of the code and returns the value of the last one
+    fn eval_str(&self, py: Python<'_>, code: &str) -> PyResult<PyObject> {
+        let forms =
+            read_all(code).map_err(|err| to_py_err(ReplError::from(err).with_source(code)))?;
+        let mut result = Ast::Nil;
+        for form in forms {
+            result = self.interpreter.eval(form).map_err(to_py_err)?;
+        }
+        to_py(py, &result)
+    }
+
+    /// Value of a global, raising `KeyError` if it isn't defined
+    fn get(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+        let value = self
+            .interpreter
+            .root_env()
+            .borrow()
+            .get_local(name)
+            .cloned();
+        match value {
+            Some(value) => to_py(py, &value),
+            None => Err(PyKeyError::new_err(name.to_owned())),
+        }
+    }
+
+    /// Binds a global to a Python value
+    fn set(&self, name: &str, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let value = from_py(value)?;
+        self.interpreter
+            .root_env()
+            .borrow_mut()
+            .set_local(name.to_owned(), value);
+        Ok(())
+    }
+
+    /// Binds a global to a builtin that calls `function` with the converted arguments. An
+    /// exception raised by it fails the call with the exception's message.
+    fn register(&self, name: &str, function: PyObject) {
+        let fn_name = name.to_owned();
+        let f = move |args: Vec<Ast>, _: &_| -> Result<Ast, ReplError> {
+            Python::with_gil(|py| {
+                let args = args
+                    .iter()
+                    .map(|x| to_py(py, x))
+                    .collect::<PyResult<Vec<_>>>()?;
+                let result = function.call1(py, PyTuple::new_bound(py, args))?;
+                from_py(result.bind(py))
+            })
+            .map_err(|err: PyErr| RuntimeError::Foreign(fn_name.clone(), err.to_string()).into())
+        };
+
+        let closure = Ast::Closure(Rc::new(Closure {
+            name: name.to_owned(),
+            f: Box::new(f),
+        }));
+        self.interpreter
+            .root_env()
+            .borrow_mut()
+            .set_local(name.to_owned(), closure);
+    }
+}
+
+#[pymodule]
+fn beesting(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyInterpreter>()?;
+    m.add_class::<Value>()?;
+    Ok(())
+}
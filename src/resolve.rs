@@ -0,0 +1,296 @@
+use crate::coverage;
+use crate::diagnostics::{warn, Warning};
+use crate::eval::macro_function;
+use crate::eval::{is_else, unquoted, SPECIAL_FORMS};
+use crate::parser::Ast;
+use crate::root_env::Environment;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+thread_local! {
+    /// Names whose `def!` is being evaluated, innermost last
+    static DEFINING: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+/// Runs `f` with `name` counting as defined for the free symbol check, so functions can refer
+/// to themselves before their `def!` has bound them
+pub fn defining<R>(name: &str, f: impl FnOnce() -> R) -> R {
+    DEFINING.with(|names| names.borrow_mut().push(name.to_owned()));
+    let result = f();
+    DEFINING.with(|names| names.borrow_mut().pop());
+    result
+}
+
+fn is_being_defined(name: &str) -> bool {
+    DEFINING.with(|names| names.borrow().iter().any(|n| n == name))
+}
+
+/// Rewrites references to parameters and let bindings in a function body into
+/// `Ast::Local(depth, slot, name)`, so they can be looked up by position instead of by name.
+///
+/// Scopes introduced inside the body are tracked statically; above the function's own frame the
+/// closure environment is inspected, as its frames are the ones that will be the parents at
+/// call time. Globals stay symbols and are looked up in the root environment's hash map.
+///
+/// Functions defined at the top level are checked for symbols that aren't defined anywhere,
+/// which are reported as a warning. Nested functions are checked along with the enclosing one.
+pub fn resolve_function_body(params: &[String], body: Ast, env: &Rc<RefCell<Environment>>) -> Ast {
+    let mut resolver = Resolver {
+        scopes: vec![params.to_vec()],
+        env: Rc::clone(env),
+        free: vec![],
+        defined: vec![],
+    };
+    let body = resolver.resolve(body);
+
+    if env.borrow().parent.is_none() && !resolver.free.is_empty() {
+        let name = DEFINING.with(|names| names.borrow().last().cloned());
+        warn(Warning::UndefinedInFunction(name, resolver.free));
+    }
+    body
+}
+
+struct Resolver {
+    /// Slot names of the frames that will exist at runtime, innermost last
+    scopes: Vec<Vec<String>>,
+    env: Rc<RefCell<Environment>>,
+    /// Global symbols used by the body that are currently undefined
+    free: Vec<String>,
+    /// Names defined by the `def!`s and other definitions met so far in the body
+    defined: Vec<String>,
+}
+
+impl Resolver {
+    fn resolve(&mut self, ast: Ast) -> Ast {
+        match ast {
+            Ast::Symbol(s) => self.resolve_symbol(s),
+            Ast::List(xs) => {
+                let original = Rc::as_ptr(&xs);
+                let resolved = self.resolve_list(Rc::unwrap_or_clone(xs));
+                coverage::rewritten(original, &resolved);
+                resolved
+            }
+            Ast::Vector(xs) => Ast::Vector(Rc::new(self.resolve_all(Rc::unwrap_or_clone(xs)))),
+            Ast::HashMap(map) => Ast::HashMap(Rc::new(
+                Rc::unwrap_or_clone(map)
+                    .into_iter()
+                    .map(|(k, v)| (k, self.resolve(v)))
+                    .collect(),
+            )),
+            other => other,
+        }
+    }
+
+    fn resolve_symbol(&mut self, name: String) -> Ast {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.iter().rposition(|n| *n == name) {
+                return Ast::Local(depth, slot, name);
+            }
+        }
+
+        let mut depth = self.scopes.len();
+        let mut current = Rc::clone(&self.env);
+        loop {
+            let next = {
+                let frame = current.borrow();
+                if frame.values.contains_key(&name) {
+                    return Ast::Symbol(name);
+                }
+                if frame.parent.is_none() {
+                    self.check_defined(&name);
+                    return Ast::Symbol(name);
+                }
+
+                if let Some(slot) = frame.slot_names.iter().rposition(|n| *n == name) {
+                    return Ast::Local(depth, slot, name);
+                }
+
+                Rc::clone(frame.parent.as_ref().unwrap())
+            };
+
+            current = next;
+            depth += 1;
+        }
+    }
+
+    fn check_defined(&mut self, name: &str) {
+        let defined = SPECIAL_FORMS.contains(&name)
+            || is_being_defined(name)
+            || self.defined.iter().any(|n| n == name)
+            || self.free.iter().any(|n| n == name);
+        if !defined {
+            self.free.push(name.to_owned());
+        }
+    }
+
+    fn resolve_list(&mut self, xs: Vec<Ast>) -> Ast {
+        if self.is_macro_call(&xs) {
+            // The macro gets the arguments as written and its expansion is looked up by name
+            return Ast::List(Rc::new(xs));
+        }
+
+        let head = match xs.first() {
+            Some(Ast::Symbol(s)) if SPECIAL_FORMS.contains(&s.as_str()) => s.clone(),
+            _ => return Ast::List(Rc::new(self.resolve_all(xs))),
+        };
+
+        match head.as_str() {
+            "fun*" | "let*" | "letrec" | "plet" | "doseq" if xs.len() == 3 => {
+                self.resolve_binding_form(&head, xs)
+            }
+            "def!" | "defmacro!" | "defonce" | "defconst" => {
+                if let Some(Ast::Symbol(name)) = xs.get(1) {
+                    self.defined.push(name.clone());
+                }
+                let mut xs = xs.into_iter();
+                let mut result: Vec<Ast> = xs.by_ref().take(2).collect();
+                result.extend(xs.map(|x| self.resolve(x)));
+                Ast::List(Rc::new(result))
+            }
+            "fun*" | "let*" | "letrec" | "plet" | "doseq" | "quote" => Ast::List(Rc::new(xs)),
+            "try*" => {
+                let mut xs = xs.into_iter();
+                let mut result = vec![xs.next().unwrap()];
+                result.extend(xs.next().map(|x| self.resolve(x)));
+                result.extend(xs.map(|clause| self.resolve_catch(clause)));
+                Ast::List(Rc::new(result))
+            }
+            "quasiquote" => {
+                let mut xs = xs.into_iter();
+                let mut result = vec![xs.next().unwrap()];
+                result.extend(xs.map(|x| self.resolve_template(x)));
+                Ast::List(Rc::new(result))
+            }
+            // `else` is a marker rather than a symbol to look up
+            "cond" => {
+                let result = xs.into_iter().enumerate().map(|(i, x)| match i {
+                    0 => x,
+                    _ if i % 2 == 1 && is_else(&x) => x,
+                    _ => self.resolve(x),
+                });
+                Ast::List(Rc::new(result.collect()))
+            }
+            // The body is evaluated in another environment, where the locals aren't visible
+            "with-env" => {
+                let mut xs = xs.into_iter();
+                let mut result = vec![xs.next().unwrap()];
+                result.extend(xs.next().map(|x| self.resolve(x)));
+                result.extend(xs);
+                Ast::List(Rc::new(result))
+            }
+            _ => {
+                let mut xs = xs.into_iter();
+                let mut result = vec![xs.next().unwrap()];
+                result.extend(xs.map(|x| self.resolve(x)));
+                Ast::List(Rc::new(result))
+            }
+        }
+    }
+
+    /// Resolves the handler of a `(catch* e handler)` clause, in which `e` is bound
+    fn resolve_catch(&mut self, clause: Ast) -> Ast {
+        let Ast::List(xs) = &clause else {
+            return clause;
+        };
+        let [head @ Ast::Symbol(catch), name @ Ast::Symbol(bound), handler] = xs.as_slice() else {
+            return clause;
+        };
+        if catch != "catch*" {
+            return clause;
+        }
+
+        self.scopes.push(vec![bound.clone()]);
+        let handler = self.resolve(handler.clone());
+        self.scopes.pop();
+        Ast::List(Rc::new(vec![head.clone(), name.clone(), handler]))
+    }
+
+    /// Whether the list calls a macro that is defined outside of the function
+    fn is_macro_call(&self, xs: &[Ast]) -> bool {
+        let Some(Ast::Symbol(head)) = xs.first() else {
+            return false;
+        };
+        let shadowed = self.scopes.iter().any(|scope| scope.contains(head));
+        !shadowed && macro_function(xs, &self.env).is_some()
+    }
+
+    /// Resolves the unquoted forms of a quasiquote template, which are the only ones evaluated
+    fn resolve_template(&mut self, template: Ast) -> Ast {
+        for form in ["unquote", "splice-unquote"] {
+            if let Some(x) = unquoted(&template, form) {
+                let resolved = self.resolve(x.clone());
+                return Ast::List(Rc::new(vec![Ast::Symbol(form.to_owned()), resolved]));
+            }
+        }
+
+        match template {
+            Ast::List(xs) => Ast::List(Rc::new(
+                Rc::unwrap_or_clone(xs)
+                    .into_iter()
+                    .map(|x| self.resolve_template(x))
+                    .collect(),
+            )),
+            Ast::Vector(xs) => Ast::Vector(Rc::new(
+                Rc::unwrap_or_clone(xs)
+                    .into_iter()
+                    .map(|x| self.resolve_template(x))
+                    .collect(),
+            )),
+            other => other,
+        }
+    }
+
+    fn resolve_binding_form(&mut self, head: &str, mut xs: Vec<Ast>) -> Ast {
+        let body = xs.pop().unwrap();
+        let bindings = xs.pop().unwrap();
+
+        let names = match &bindings {
+            Ast::List(bindings) => bindings
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| head == "fun*" || (i % 2 == 0 && i + 1 < bindings.len()))
+                // The rest marker takes no slot, see `split_rest_param`
+                .filter(|(_, b)| head != "fun*" || !matches!(b, Ast::Symbol(s) if s == "&"))
+                .map(|(_, b)| match b {
+                    Ast::Symbol(s) => s.clone(),
+                    _ => String::new(),
+                })
+                .collect(),
+            _ => {
+                xs.push(bindings);
+                xs.push(body);
+                return Ast::List(Rc::new(xs));
+            }
+        };
+
+        let bindings = match (head, bindings) {
+            ("plet" | "doseq", Ast::List(bindings)) => self.resolve_binding_values(bindings),
+            (_, bindings) => bindings,
+        };
+
+        self.scopes.push(names);
+        let bindings = match (head, bindings) {
+            ("let*" | "letrec", Ast::List(bindings)) => self.resolve_binding_values(bindings),
+            (_, bindings) => bindings,
+        };
+        let body = self.resolve(body);
+        self.scopes.pop();
+
+        xs.push(bindings);
+        xs.push(body);
+        Ast::List(Rc::new(xs))
+    }
+
+    fn resolve_binding_values(&mut self, bindings: Rc<Vec<Ast>>) -> Ast {
+        let bindings = Rc::unwrap_or_clone(bindings)
+            .into_iter()
+            .enumerate()
+            .map(|(i, b)| if i % 2 == 0 { b } else { self.resolve(b) })
+            .collect();
+        Ast::List(Rc::new(bindings))
+    }
+
+    fn resolve_all(&mut self, xs: Vec<Ast>) -> Vec<Ast> {
+        xs.into_iter().map(|x| self.resolve(x)).collect()
+    }
+}
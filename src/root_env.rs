@@ -1,17 +1,38 @@
-use crate::errors::ReplError;
-use crate::eval::{bind_fn, eval};
-use crate::parser::{Ast, ParserError, UserFunction};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use crate::callstack;
+use crate::complete;
+use crate::errors::{ReplError, RuntimeError};
+use crate::eval::{eval, is_truthy, macro_function, Callable};
+#[cfg(any(feature = "math", feature = "os"))]
+use crate::interpreter::with_options;
+use crate::lexer::describe_tokens;
+use crate::output::{write_line, write_out, write_to, OutputPort, ERR, OUT};
+use crate::parser;
+use crate::parser::{keyword_map, Ast, AtomCell, Closure, FormReader, MapKey};
+use crate::printer::{describe_ast, pr_seq, pretty_print, PrintLimits, DEFAULT_WIDTH};
+use crate::seq;
+#[cfg(feature = "io")]
+use crate::seq::LazySeq;
+use crate::serialize;
+use crate::stats;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+#[cfg(feature = "io")]
+use std::io;
+#[cfg(feature = "io")]
+use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::{fs, mem};
+#[cfg(any(feature = "math", feature = "os"))]
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /* Helper functions */
 
-fn get_int(ast: Ast, pos: u32, fn_name: &str) -> Result<i64, ParserError> {
+fn get_int(ast: Ast, pos: u32, fn_name: &str) -> Result<i64, RuntimeError> {
     match ast {
         Ast::Integer(n) => Ok(n),
-        _ => Err(ParserError::TypeMismatch(
+        _ => Err(RuntimeError::TypeMismatch(
             fn_name.to_owned(),
             pos,
             "Integer".to_owned(),
@@ -20,10 +41,45 @@ fn get_int(ast: Ast, pos: u32, fn_name: &str) -> Result<i64, ParserError> {
     }
 }
 
-fn get_str(ast: Ast, pos: u32, fn_name: &str) -> Result<String, ParserError> {
+/// Integers are converted, so numbers of both types can be mixed
+fn get_float(ast: Ast, pos: u32, fn_name: &str) -> Result<f64, RuntimeError> {
+    match ast {
+        Ast::Integer(n) => Ok(n as f64),
+        Ast::Float(x) => Ok(x),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "Number".to_owned(),
+            ast,
+        )),
+    }
+}
+
+/// Operands of a binary arithmetic builtin: two integers stay integers, as soon as one of them is
+/// a float both are floats
+enum Operands {
+    Integers(i64, i64),
+    Floats(f64, f64),
+}
+
+fn get_operands(mut args: Vec<Ast>, fn_name: &str) -> Result<Operands, RuntimeError> {
+    check_arity(&args, 2, fn_name)?;
+    let b = args.pop().unwrap();
+    let a = args.pop().unwrap();
+
+    match (a, b) {
+        (Ast::Integer(a), Ast::Integer(b)) => Ok(Operands::Integers(a, b)),
+        (a, b) => Ok(Operands::Floats(
+            get_float(a, 1, fn_name)?,
+            get_float(b, 2, fn_name)?,
+        )),
+    }
+}
+
+fn get_str(ast: Ast, pos: u32, fn_name: &str) -> Result<String, RuntimeError> {
     match ast {
         Ast::String(str) => Ok(str),
-        _ => Err(ParserError::TypeMismatch(
+        _ => Err(RuntimeError::TypeMismatch(
             fn_name.to_owned(),
             pos,
             "String".to_owned(),
@@ -32,10 +88,28 @@ fn get_str(ast: Ast, pos: u32, fn_name: &str) -> Result<String, ParserError> {
     }
 }
 
-fn get_atom(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<RefCell<Ast>>, ParserError> {
+#[cfg(feature = "string")]
+/// Characters are represented by strings of one character
+fn get_char(ast: Ast, pos: u32, fn_name: &str) -> Result<char, RuntimeError> {
+    if let Ast::String(s) = &ast {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(c);
+        }
+    }
+
+    Err(RuntimeError::TypeMismatch(
+        fn_name.to_owned(),
+        pos,
+        "Character".to_owned(),
+        ast,
+    ))
+}
+
+fn get_atom(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<AtomCell>, RuntimeError> {
     match ast {
         Ast::Atom(ast) => Ok(ast),
-        _ => Err(ParserError::TypeMismatch(
+        _ => Err(RuntimeError::TypeMismatch(
             fn_name.to_owned(),
             pos,
             "Atom".to_owned(),
@@ -44,10 +118,64 @@ fn get_atom(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<RefCell<Ast>>, Parse
     }
 }
 
-fn get_fun(ast: Ast, pos: u32, fn_name: &str) -> Result<Box<UserFunction>, ParserError> {
+fn get_mutable_vector(
+    ast: Ast,
+    pos: u32,
+    fn_name: &str,
+) -> Result<Rc<RefCell<Vec<Ast>>>, RuntimeError> {
+    match ast {
+        Ast::MutableVector(v) => Ok(v),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "MutableVector".to_owned(),
+            ast,
+        )),
+    }
+}
+
+fn get_int_array(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<RefCell<Vec<i64>>>, RuntimeError> {
+    match ast {
+        Ast::IntArray(a) => Ok(a),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "IntArray".to_owned(),
+            ast,
+        )),
+    }
+}
+
+fn get_float_array(
+    ast: Ast,
+    pos: u32,
+    fn_name: &str,
+) -> Result<Rc<RefCell<Vec<f64>>>, RuntimeError> {
+    match ast {
+        Ast::FloatArray(a) => Ok(a),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "FloatArray".to_owned(),
+            ast,
+        )),
+    }
+}
+
+fn array_type_mismatch(ast: Ast, pos: u32, fn_name: &str) -> ReplError {
+    RuntimeError::TypeMismatch(
+        fn_name.to_owned(),
+        pos,
+        "IntArray or FloatArray".to_owned(),
+        ast,
+    )
+    .into()
+}
+
+fn get_callable(ast: Ast, pos: u32, fn_name: &str) -> Result<Callable, RuntimeError> {
     match ast {
-        Ast::Function(ast) => Ok(ast),
-        _ => Err(ParserError::TypeMismatch(
+        Ast::Function(_) | Ast::Builtin(..) | Ast::Closure(_) => Callable::from_ast(ast),
+        _ => Err(RuntimeError::TypeMismatch(
             fn_name.to_owned(),
             pos,
             "Function".to_owned(),
@@ -56,15 +184,104 @@ fn get_fun(ast: Ast, pos: u32, fn_name: &str) -> Result<Box<UserFunction>, Parse
     }
 }
 
+fn check_arity(args: &[Ast], expected: usize, fn_name: &str) -> Result<(), RuntimeError> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        Err(RuntimeError::WrongArity(
+            fn_name.to_owned(),
+            expected,
+            args.len(),
+        ))
+    }
+}
+
+/// Finds the value of the entry `(key value)` in an association list, comparing keys with
+/// `values_equal`
+pub fn assoc_lookup(entries: &[Ast], key: &Ast) -> Option<Ast> {
+    entries.iter().find_map(|entry| match entry {
+        Ast::List(pair) if pair.len() == 2 && values_equal(&pair[0], key) => Some(pair[1].clone()),
+        _ => None,
+    })
+}
+
+/// Structural equality: lists and vectors are equal when their elements are, hash-maps when they
+/// have the same keys with equal values, strings and symbols by content, and functions and atoms
+/// only to themselves
+pub fn values_equal(a: &Ast, b: &Ast) -> bool {
+    match (a, b) {
+        (Ast::Integer(a), Ast::Integer(b)) => a == b,
+        (Ast::Float(a), Ast::Float(b)) => a == b,
+        (Ast::Integer(a), Ast::Float(b)) | (Ast::Float(b), Ast::Integer(a)) => *a as f64 == *b,
+        (Ast::Boolean(a), Ast::Boolean(b)) => a == b,
+        (Ast::String(a), Ast::String(b))
+        | (Ast::Symbol(a), Ast::Symbol(b))
+        | (Ast::Keyword(a), Ast::Keyword(b)) => a == b,
+        (Ast::Nil, Ast::Nil) => true,
+        (Ast::List(a) | Ast::Vector(a), Ast::List(b) | Ast::Vector(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| values_equal(a, b))
+        }
+        (Ast::HashMap(a), Ast::HashMap(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, x)| b.get(key).is_some_and(|y| values_equal(x, y)))
+        }
+        (Ast::Function(a), Ast::Function(b)) => Rc::ptr_eq(a, b),
+        (Ast::Closure(a), Ast::Closure(b)) => Rc::ptr_eq(a, b),
+        (Ast::Atom(a), Ast::Atom(b)) => Rc::ptr_eq(a, b),
+        (Ast::MutableVector(a), Ast::MutableVector(b)) => Rc::ptr_eq(a, b),
+        (Ast::IntArray(a), Ast::IntArray(b)) => Rc::ptr_eq(a, b),
+        (Ast::FloatArray(a), Ast::FloatArray(b)) => Rc::ptr_eq(a, b),
+        (Ast::StringBuilder(a), Ast::StringBuilder(b)) => Rc::ptr_eq(a, b),
+        (Ast::Port(a), Ast::Port(b)) => Rc::ptr_eq(a, b),
+        (Ast::Seq(a), Ast::Seq(b)) => Rc::ptr_eq(a, b),
+        (Ast::Reader(a), Ast::Reader(b)) => Rc::ptr_eq(a, b),
+        (Ast::Env(a), Ast::Env(b)) => Rc::ptr_eq(a, b),
+        (Ast::Builtin(a, _), Ast::Builtin(b, _)) => a == b,
+        _ => false,
+    }
+}
+
 pub fn lookup(symbol: String, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
-    if let Some(v) = env.borrow().values.get(&symbol) {
-        Ok(v.clone())
+    find(&symbol, env).ok_or_else(|| {
+        let suggestion = complete::suggest(&symbol, &env.borrow());
+        ReplError::SymbolUndefined(symbol, suggestion)
+    })
+}
+
+pub(crate) fn find(symbol: &str, env: &Rc<RefCell<Environment>>) -> Option<Ast> {
+    if let Some(v) = env.borrow().get_local(symbol) {
+        Some(v.clone())
     } else {
         match &env.borrow().parent {
-            None => Err(ReplError::SymbolUndefined(symbol.to_owned())),
-            Some(penv) => lookup(symbol, penv),
+            None => None,
+            Some(penv) => find(symbol, penv),
+        }
+    }
+}
+
+/// Looks up a slot resolved by `resolve_function_body`. If the frame at that position turns out
+/// not to bind the name there, the symbol is looked up by name instead.
+pub fn lookup_local(
+    depth: usize,
+    slot: usize,
+    name: &str,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let mut current = Rc::clone(env);
+    for _ in 0..depth {
+        let parent = current.borrow().parent.clone();
+        match parent {
+            Some(parent) => current = parent,
+            None => return lookup(name.to_owned(), env),
         }
     }
+
+    let frame = current.borrow();
+    match frame.slots.get(slot) {
+        Some(v) if frame.slot_names[slot] == name => Ok(v.clone()),
+        _ => lookup(name.to_owned(), env),
+    }
 }
 
 pub fn get_root(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
@@ -86,221 +303,3225 @@ pub fn get_root(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
 
 /* Standard lib */
 
-fn add(name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let b = get_int(args.pop().unwrap(), 2, name)?;
-    let a = get_int(args.pop().unwrap(), 1, name)?;
+fn overflow(name: &str) -> ReplError {
+    ReplError::RuntimeError(RuntimeError::IntegerOverflow(name.to_owned()))
+}
+
+fn add(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    Ok(match get_operands(args, name)? {
+        Operands::Integers(a, b) => Ast::Integer(a.checked_add(b).ok_or_else(|| overflow(name))?),
+        Operands::Floats(a, b) => Ast::Float(a + b),
+    })
+}
+
+fn sub(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    Ok(match get_operands(args, name)? {
+        Operands::Integers(a, b) => Ast::Integer(a.checked_sub(b).ok_or_else(|| overflow(name))?),
+        Operands::Floats(a, b) => Ast::Float(a - b),
+    })
+}
+
+fn mult(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    Ok(match get_operands(args, name)? {
+        Operands::Integers(a, b) => Ast::Integer(a.checked_mul(b).ok_or_else(|| overflow(name))?),
+        Operands::Floats(a, b) => Ast::Float(a * b),
+    })
+}
+
+/// Dividing integers truncates, `(/ 7 2.0)` divides as floats
+fn div(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    Ok(match get_operands(args, name)? {
+        Operands::Integers(_, 0) => {
+            return Err(ReplError::RuntimeError(RuntimeError::DivisionByZero(
+                name.to_owned(),
+            )))
+        }
+        Operands::Integers(a, b) => Ast::Integer(a.checked_div(b).ok_or_else(|| overflow(name))?),
+        Operands::Floats(a, b) => Ast::Float(a / b),
+    })
+}
 
-    Ok(Ast::Integer(a + b))
+fn prn(_name: &str, args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    write_line(&pr_seq(&args, true, " ", PrintLimits::from_env(env)), env)?;
+    Ok(Ast::Nil)
 }
 
-fn sub(name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let b = get_int(args.pop().unwrap(), 2, name)?;
-    let a = get_int(args.pop().unwrap(), 1, name)?;
+fn println(_name: &str, args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    write_line(&pr_seq(&args, false, " ", PrintLimits::from_env(env)), env)?;
+    Ok(Ast::Nil)
+}
 
-    Ok(Ast::Integer(a - b))
+fn get_port(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<OutputPort>, RuntimeError> {
+    match ast {
+        Ast::Port(port) => Ok(port),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "Port".to_owned(),
+            ast,
+        )),
+    }
 }
 
-fn mult(name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let b = get_int(args.pop().unwrap(), 2, name)?;
-    let a = get_int(args.pop().unwrap(), 1, name)?;
+#[cfg(feature = "io")]
+/// `(open-output path)` returns a port writing to a new file at `path`
+fn open_output(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let path = get_str(args.pop().unwrap(), 1, name)?;
+    Ok(Ast::Port(Rc::new(OutputPort::open_file(&path)?)))
+}
 
-    Ok(Ast::Integer(a * b))
+/// `(string-output)` returns a port collecting what is written to it, see `output-string`
+fn string_output(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 0, name)?;
+    Ok(Ast::Port(Rc::new(OutputPort::buffer())))
 }
 
-fn div(name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let b = get_int(args.pop().unwrap(), 2, name)?;
-    let a = get_int(args.pop().unwrap(), 1, name)?;
+/// Returns what was written to a string port so far
+fn output_string(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let port = get_port(args.pop().unwrap(), 1, name)?;
+    match port.contents() {
+        Some(contents) => Ok(Ast::String(contents)),
+        None => Err(RuntimeError::TypeMismatch(
+            name.to_owned(),
+            1,
+            "string port".to_owned(),
+            Ast::Port(port),
+        )
+        .into()),
+    }
+}
 
-    Ok(Ast::Integer(a / b))
+fn close(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    get_port(args.pop().unwrap(), 1, name)?.close()?;
+    Ok(Ast::Nil)
 }
 
-fn prn(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let a = args.pop().unwrap();
-    println!("{:?}", a);
+/// `(write-port port args...)` prints the arguments like `print` to a port, e.g. to also send
+/// output to a log file
+fn write_port(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    if args.is_empty() {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 1, 0).into());
+    }
+    let port = get_port(args.remove(0), 1, name)?;
+    port.write(&pr_seq(&args, false, " ", PrintLimits::from_env(env)))?;
     Ok(Ast::Nil)
 }
 
-fn op_eq(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let b = args.pop().unwrap();
+/// `(string-reader s)` returns a reader over the forms in `s`, see `read-form`
+fn string_reader(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let text = get_str(args.pop().unwrap(), 1, name)?;
+    Ok(Ast::Reader(Rc::new(FormReader::new(
+        "string".to_owned(),
+        text,
+    ))))
+}
+
+#[cfg(feature = "io")]
+/// `(file-reader path)` returns a reader over the forms in a file, see `read-form`
+fn file_reader(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let path = get_str(args.pop().unwrap(), 1, name)?;
+    let text = fs::read_to_string(&path)?;
+    Ok(Ast::Reader(Rc::new(FormReader::new(
+        format!("file {}", path),
+        text,
+    ))))
+}
+
+/// `(read-form reader)` returns the next form of the reader, or nil once there are no more
+fn read_form(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    match args.pop().unwrap() {
+        Ast::Reader(reader) => Ok(reader.read_form()?.unwrap_or(Ast::Nil)),
+        other => {
+            Err(RuntimeError::TypeMismatch(name.to_owned(), 1, "Reader".to_owned(), other).into())
+        }
+    }
+}
+
+#[cfg(feature = "io")]
+/// `(line-seq path)` returns a lazy sequence of the lines of a file, which are read as the
+/// sequence is consumed
+fn line_seq(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let path = get_str(args.pop().unwrap(), 1, name)?;
+    Ok(seq::new_seq(LazySeq::file_lines(&path)?))
+}
+
+#[cfg(feature = "io")]
+/// `(read-stdin)` reads standard input until its end and returns it as a string
+fn read_stdin(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 0, name)?;
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    Ok(Ast::String(input))
+}
+
+#[cfg(feature = "io")]
+/// `(stdin-lines)` returns a lazy sequence of the lines of standard input
+fn stdin_lines(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 0, name)?;
+    Ok(seq::new_seq(LazySeq::stdin_lines()))
+}
+
+/// `(take n coll)` returns a list of the first `n` elements of a list or lazy sequence. Takes
+/// them out of a lazy sequence, so the next use of it continues after them.
+fn take(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let coll = args.pop().unwrap();
+    let n = get_int(args.pop().unwrap(), 1, name)?;
+    if !seq::is_iterable(&coll) {
+        return Err(
+            RuntimeError::TypeMismatch(name.to_owned(), 2, "List or Seq".to_owned(), coll).into(),
+        );
+    }
+
+    let mut taken = vec![];
+    if n > 0 {
+        seq::for_each(&coll, |x| {
+            taken.push(x);
+            Ok((taken.len() as i64) < n)
+        })?;
+    }
+    Ok(Ast::List(Rc::new(taken)))
+}
+
+/// Like `prn`, but writes to `*err*`, which is stderr unless rebound
+fn eprn(_name: &str, args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    write_to(
+        ERR,
+        &format!("{}\n", pr_seq(&args, true, " ", PrintLimits::from_env(env))),
+        env,
+    )?;
+    Ok(Ast::Nil)
+}
+
+/// Like `println`, but writes to `*err*`
+fn eprintln(_name: &str, args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    write_to(
+        ERR,
+        &format!(
+            "{}\n",
+            pr_seq(&args, false, " ", PrintLimits::from_env(env))
+        ),
+        env,
+    )?;
+    Ok(Ast::Nil)
+}
+
+fn print(_name: &str, args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    write_out(&pr_seq(&args, false, " ", PrintLimits::from_env(env)), env)?;
+    Ok(Ast::Nil)
+}
+
+fn pprint(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
     let a = args.pop().unwrap();
 
-    if mem::discriminant(&a) != mem::discriminant(&b) {
-        return Ok(Ast::Boolean(false));
+    let limits = PrintLimits::from_env(env);
+    write_line(&pretty_print(&a, true, limits, DEFAULT_WIDTH), env)?;
+    Ok(Ast::Nil)
+}
+
+/// Prints the definition of a user function
+fn source(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    match args.pop().unwrap() {
+        Ast::Function(f) => {
+            let limits = PrintLimits::from_env(env);
+            write_line(&pretty_print(&f.source, true, limits, DEFAULT_WIDTH), env)?;
+        }
+        Ast::Builtin(builtin, _) => write_line(&format!("<builtin {}>", builtin), env)?,
+        Ast::Closure(closure) => write_line(&format!("<function {}>", closure.name), env)?,
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                name.to_owned(),
+                1,
+                "Function".to_owned(),
+                other,
+            )
+            .into())
+        }
     }
+    Ok(Ast::Nil)
+}
 
-    match a {
-        Ast::Integer(a_n) => {
-            if let Ast::Integer(b_n) = b {
-                Ok(Ast::Boolean(a_n == b_n))
-            } else {
-                Ok(Ast::Boolean(false))
-            }
+/// Prints the body of a user function the way the evaluator sees it, after parameters and let
+/// bindings were resolved to slots and constants were folded
+fn disassemble(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    match args.pop().unwrap() {
+        Ast::Function(f) => {
+            write_line(&format!("Parameters {}", f.written_params().join(" ")), env)?;
+            write_line(&describe_ast(&f.body), env)?;
+            Ok(Ast::Nil)
         }
-        Ast::Boolean(a_b) => {
-            if let Ast::Boolean(b_b) = b {
-                Ok(Ast::Boolean(a_b == b_b))
-            } else {
-                Ok(Ast::Boolean(false))
-            }
+        other => {
+            Err(RuntimeError::TypeMismatch(name.to_owned(), 1, "Function".to_owned(), other).into())
         }
-        _ => Ok(Ast::Boolean(false)),
     }
 }
 
-fn op_lt(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let b = args.pop().unwrap();
-    let a = args.pop().unwrap();
+/// Returns a hash-map of counters describing the memory in use
+fn runtime_stats(
+    name: &str,
+    args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 0, name)?;
+    let stats = stats::runtime_stats(env);
 
-    if mem::discriminant(&a) != mem::discriminant(&b) {
-        return Ok(Ast::Boolean(false));
+    let count = |n: usize| Ast::Integer(n as i64);
+    Ok(keyword_map(vec![
+        ("live-environments", count(stats.live_environments)),
+        ("live-atoms", count(stats.live_atoms)),
+        ("symbols", count(stats.symbols)),
+        ("reachable-list-cells", count(stats.reachable_list_cells)),
+    ]))
+}
+
+/// `(get coll key default?)` returns the value of `key` in an association list or hash-map, or
+/// the default (nil if not given) when the key is missing or the collection is nil
+fn get(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 2, args.len()).into());
     }
 
-    match a {
-        Ast::Integer(a_n) => {
-            if let Ast::Integer(b_n) = b {
-                Ok(Ast::Boolean(a_n < b_n))
-            } else {
-                Ok(Ast::Boolean(false))
-            }
+    let mut args = args.into_iter();
+    let coll = args.next().unwrap();
+    let key = args.next().unwrap();
+    let default = args.next().unwrap_or(Ast::Nil);
+
+    match coll {
+        Ast::List(entries) => Ok(assoc_lookup(&entries, &key).unwrap_or(default)),
+        Ast::HashMap(map) => {
+            let value = MapKey::from_ast(&key).and_then(|key| map.get(&key).cloned());
+            Ok(value.unwrap_or(default))
         }
-        _ => Ok(Ast::Boolean(false)),
+        Ast::Nil => Ok(default),
+        other => Err(RuntimeError::TypeMismatch(
+            name.to_owned(),
+            1,
+            "association list or HashMap".to_owned(),
+            other,
+        )
+        .into()),
+    }
+}
+
+/// The entries of a hash-map, nil being the empty map
+fn get_map(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<BTreeMap<MapKey, Ast>>, RuntimeError> {
+    match ast {
+        Ast::HashMap(map) => Ok(map),
+        Ast::Nil => Ok(Rc::new(BTreeMap::new())),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "HashMap".to_owned(),
+            ast,
+        )),
     }
 }
 
-fn list(_name: &str, args: Vec<Ast>) -> Result<Ast, ReplError> {
-    Ok(Ast::List(args))
+fn get_map_key(ast: Ast, pos: u32, fn_name: &str) -> Result<MapKey, RuntimeError> {
+    MapKey::from_ast(&ast).ok_or_else(|| {
+        RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "String, Keyword or Integer".to_owned(),
+            ast,
+        )
+    })
 }
 
-fn list_q(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let a = args.pop().unwrap();
-    Ok(Ast::Boolean(matches!(a, Ast::List(_))))
+/// Adds the `key value ...` arguments, which start at argument position `pos`, to a map
+fn insert_entries(
+    map: &mut BTreeMap<MapKey, Ast>,
+    mut args: impl Iterator<Item = Ast>,
+    mut pos: u32,
+    fn_name: &str,
+) -> Result<(), RuntimeError> {
+    while let (Some(key), Some(value)) = (args.next(), args.next()) {
+        map.insert(get_map_key(key, pos, fn_name)?, value);
+        pos += 2;
+    }
+    Ok(())
 }
 
-fn empty_q(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let a = args.pop().unwrap();
-    Ok(Ast::Boolean(matches!(a, Ast::List(xs) if xs.is_empty())))
+/// `(hash-map key value ...)` creates a map of the given entries
+fn hash_map(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(RuntimeError::WrongArity(name.to_owned(), args.len() + 1, args.len()).into());
+    }
+
+    let mut map = BTreeMap::new();
+    insert_entries(&mut map, args.into_iter(), 1, name)?;
+    Ok(Ast::HashMap(Rc::new(map)))
 }
 
-fn count(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
+fn map_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
     let a = args.pop().unwrap();
+    Ok(Ast::Boolean(matches!(a, Ast::HashMap(_))))
+}
 
-    Ok(Ast::Integer(if let Ast::List(xs) = a {
-        xs.len() as i64
-    } else {
-        0
-    }))
+/// `(assoc m key value ...)` returns `m` with the entries added, replacing existing keys
+fn assoc(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.is_empty() || args.len().is_multiple_of(2) {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 3, args.len()).into());
+    }
+
+    let mut args = args.into_iter();
+    let mut map = Rc::unwrap_or_clone(get_map(args.next().unwrap(), 1, name)?);
+    insert_entries(&mut map, args, 2, name)?;
+    Ok(Ast::HashMap(Rc::new(map)))
 }
 
-fn concat_str(name: &str, args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let mut str = String::new();
-    for (i, arg) in args.into_iter().enumerate() {
-        str += &get_str(arg, (i as u32) + 1, name)?;
+/// `(dissoc m key ...)` returns `m` without the keys
+fn dissoc(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.is_empty() {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 1, 0).into());
     }
 
-    Ok(Ast::String(str))
+    let mut args = args.into_iter();
+    let mut map = Rc::unwrap_or_clone(get_map(args.next().unwrap(), 1, name)?);
+    for key in args {
+        // Values that can't be keys aren't in the map to begin with
+        if let Some(key) = MapKey::from_ast(&key) {
+            map.remove(&key);
+        }
+    }
+    Ok(Ast::HashMap(Rc::new(map)))
 }
 
-fn slurp(name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let file_name = get_str(args.pop().unwrap(), 1, name)?;
+fn contains_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let key = args.pop().unwrap();
+    let map = get_map(args.pop().unwrap(), 1, name)?;
 
-    let content = fs::read_to_string(file_name)?;
-    Ok(Ast::String(content))
+    let found = MapKey::from_ast(&key).is_some_and(|key| map.contains_key(&key));
+    Ok(Ast::Boolean(found))
 }
 
-fn read_str(name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let a = get_str(args.pop().unwrap(), 1, name)?;
+/// `(keys m)` returns the keys of a map as a list, in the order the map prints them
+fn keys(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let map = get_map(args.pop().unwrap(), 1, name)?;
+    Ok(Ast::List(Rc::new(map.keys().map(MapKey::to_ast).collect())))
+}
 
-    Ok(a.parse()?)
+/// `(vals m)` returns the values of a map as a list, in the same order as `keys`
+fn vals(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let map = get_map(args.pop().unwrap(), 1, name)?;
+    Ok(Ast::List(Rc::new(map.values().cloned().collect())))
 }
 
-/* Atom */
-fn atom(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let a = args.pop().unwrap();
+/// `(or-else x default)` returns `x` unless it is nil
+fn or_else(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let default = args.pop().unwrap();
 
-    Ok(Ast::Atom(Rc::new(RefCell::new(a))))
+    match args.pop().unwrap() {
+        Ast::Nil => Ok(default),
+        value => Ok(value),
+    }
 }
 
-fn atom_q(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let a = args.pop().unwrap();
+/// `(curry f n?)` returns a function that collects arguments until it has the `n` that `f`
+/// needs, returning a function awaiting the rest as long as there are fewer. For user functions
+/// `n` defaults to the number of parameters, not counting a rest parameter.
+fn curry(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 1, args.len()).into());
+    }
 
-    Ok(Ast::Boolean(matches!(a, Ast::Atom(_))))
+    let mut args = args.into_iter();
+    let f = args.next().unwrap();
+    let arity = match (args.next(), &f) {
+        (Some(n), _) => get_int(n, 2, name)?.max(0) as usize,
+        (None, Ast::Function(user_fun)) => user_fun.params.len() - user_fun.variadic as usize,
+        (None, other) => {
+            return Err(RuntimeError::TypeMismatch(
+                name.to_owned(),
+                1,
+                "Function with known arity".to_owned(),
+                other.clone(),
+            )
+            .into())
+        }
+    };
+
+    Ok(curried(get_callable(f, 1, name)?, arity, vec![]))
 }
 
-fn deref(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let a = args.pop().unwrap();
+fn curried(fun: Callable, arity: usize, collected: Vec<Ast>) -> Ast {
+    Ast::Closure(Rc::new(Closure {
+        name: "curried".to_owned(),
+        f: Box::new(move |args, env| {
+            let mut all = collected.clone();
+            all.extend(args);
+            if all.len() >= arity {
+                fun.call(all, env)
+            } else {
+                Ok(curried(fun.clone(), arity, all))
+            }
+        }),
+    }))
+}
 
-    if let Ast::Atom(ast) = a {
-        Ok(ast.borrow().to_owned())
-    } else {
-        todo!("Error: not an atom")
-    }
+/// `(comp f g h)` returns a function calling `h` with its arguments, then `g` and `f` with the
+/// result of the previous call
+fn comp(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    let mut fns = get_callables(args, name)?;
+    fns.reverse();
+    Ok(chain("comp", fns))
 }
 
-fn reset_m(_name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let val = args.pop().unwrap();
-    let atom = args.pop().unwrap();
+/// `(pipe f g h)` is `(comp h g f)`: the functions are applied from left to right
+fn pipe(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    Ok(chain("pipe", get_callables(args, name)?))
+}
 
-    if let Ast::Atom(ast) = atom {
-        *ast.borrow_mut() = val.clone();
-        Ok(val)
-    } else {
-        todo!("Error: not an atom")
-    }
+fn get_callables(args: Vec<Ast>, fn_name: &str) -> Result<Vec<Callable>, RuntimeError> {
+    args.into_iter()
+        .enumerate()
+        .map(|(i, f)| get_callable(f, i as u32 + 1, fn_name))
+        .collect()
 }
 
-fn swap_m(name: &str, mut args: Vec<Ast>) -> Result<Ast, ReplError> {
-    let fun = get_fun(args.pop().unwrap(), 2, name)?;
-    let atom = get_atom(args.pop().unwrap(), 1, name)?;
+/// Calls the first function with the arguments and each following one with the previous result.
+/// Without functions, the single argument is returned unchanged.
+fn chain(name: &str, fns: Vec<Callable>) -> Ast {
+    let name = name.to_owned();
+    Ast::Closure(Rc::new(Closure {
+        name: name.clone(),
+        f: Box::new(move |mut args, env| {
+            let (first, rest) = match fns.split_first() {
+                Some(split) => split,
+                None => {
+                    check_arity(&args, 1, &name)?;
+                    return Ok(args.pop().unwrap());
+                }
+            };
 
-    let atom_content = atom.borrow_mut().clone();
-    let env = bind_fn(&fun.params, vec![atom_content], &fun.env);
-    let new_val = eval(fun.body, &Rc::new(RefCell::new(env)))?;
-    *atom.borrow_mut() = new_val;
-    Ok(Ast::Atom(atom))
+            let mut result = first.call(args, env)?;
+            for f in rest {
+                result = f.call(vec![result], env)?;
+            }
+            Ok(result)
+        }),
+    }))
 }
 
-/* Public */
+fn identity(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    Ok(args.pop().unwrap())
+}
 
-#[derive(Clone)]
-pub struct Environment {
-    pub values: HashMap<String, Ast>,
-    pub parent: Option<Rc<RefCell<Environment>>>,
+/// `(constantly x)` returns a function that ignores its arguments and returns `x`
+fn constantly(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let value = args.pop().unwrap();
+
+    Ok(Ast::Closure(Rc::new(Closure {
+        name: "constantly".to_owned(),
+        f: Box::new(move |_, _| Ok(value.clone())),
+    })))
 }
 
-pub fn create_root_env() -> Environment {
-    let mut root_env_table = HashMap::new();
-    root_env_table.insert("+".to_owned(), Ast::Builtin("+".to_owned(), add));
-    root_env_table.insert("-".to_owned(), Ast::Builtin("-".to_owned(), sub));
-    root_env_table.insert("*".to_owned(), Ast::Builtin("*".to_owned(), mult));
-    root_env_table.insert("/".to_owned(), Ast::Builtin("/".to_owned(), div));
-    root_env_table.insert("prn".to_owned(), Ast::Builtin("prn".to_owned(), prn));
-    root_env_table.insert("=".to_owned(), Ast::Builtin("=".to_owned(), op_eq));
-    root_env_table.insert("<".to_owned(), Ast::Builtin("<".to_owned(), op_lt));
-    root_env_table.insert("list".to_owned(), Ast::Builtin("list".to_owned(), list));
-    root_env_table.insert("list?".to_owned(), Ast::Builtin("list?".to_owned(), list_q));
-    root_env_table.insert(
-        "empty?".to_owned(),
-        Ast::Builtin("empty?".to_owned(), empty_q),
-    );
-    root_env_table.insert("count".to_owned(), Ast::Builtin("count".to_owned(), count));
-    root_env_table.insert("str".to_owned(), Ast::Builtin("str".to_owned(), concat_str));
-    root_env_table.insert("slurp".to_owned(), Ast::Builtin("slurp".to_owned(), slurp));
-    root_env_table.insert(
-        "read-str".to_owned(),
-        Ast::Builtin("read-str".to_owned(), read_str),
-    );
+/// `(complement f)` returns a function returning true where `f` returns a false value and vice
+/// versa
+fn complement(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let f = get_callable(args.pop().unwrap(), 1, name)?;
 
-    root_env_table.insert("atom".to_owned(), Ast::Builtin("atom".to_owned(), atom));
-    root_env_table.insert("atom?".to_owned(), Ast::Builtin("atom?".to_owned(), atom_q));
-    root_env_table.insert("deref".to_owned(), Ast::Builtin("deref".to_owned(), deref));
-    root_env_table.insert(
-        "reset!".to_owned(),
-        Ast::Builtin("reset!".to_owned(), reset_m),
-    );
-    root_env_table.insert("swap!".to_owned(), Ast::Builtin("swap!".to_owned(), swap_m));
+    Ok(Ast::Closure(Rc::new(Closure {
+        name: "complement".to_owned(),
+        f: Box::new(move |args, env| Ok(Ast::Boolean(!is_truthy(&f.call(args, env)?)))),
+    })))
+}
 
-    Environment {
-        values: root_env_table,
-        parent: None,
+/// `(juxt f g)` returns a function whose result is the list of `f` and `g` applied to its
+/// arguments
+fn juxt(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    let fns = get_callables(args, name)?;
+
+    Ok(Ast::Closure(Rc::new(Closure {
+        name: "juxt".to_owned(),
+        f: Box::new(move |args, env| {
+            let results = fns
+                .iter()
+                .map(|f| f.call(args.clone(), env))
+                .collect::<Result<_, _>>()?;
+            Ok(Ast::List(Rc::new(results)))
+        }),
+    })))
+}
+
+fn get_list(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<Vec<Ast>>, RuntimeError> {
+    match ast {
+        Ast::List(xs) => Ok(xs),
+        Ast::Nil => Ok(Rc::new(vec![])),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "List".to_owned(),
+            ast,
+        )),
+    }
+}
+
+fn pair(key: Ast, value: Ast) -> Ast {
+    Ast::List(Rc::new(vec![key, value]))
+}
+
+/// Fails unless `coll` is a list, vector, lazy sequence or nil, which `seq::for_each` iterates
+fn check_iterable(coll: &Ast, pos: u32, fn_name: &str) -> Result<(), RuntimeError> {
+    if seq::is_iterable(coll) {
+        return Ok(());
     }
+    Err(RuntimeError::TypeMismatch(
+        fn_name.to_owned(),
+        pos,
+        "List, Vector or Seq".to_owned(),
+        coll.clone(),
+    ))
+}
+
+/// `(map f coll)` returns a list of `(f x)` for each element. A lazy sequence is consumed
+/// entirely.
+fn map(name: &str, mut args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let coll = args.pop().unwrap();
+    let f = get_callable(args.pop().unwrap(), 1, name)?;
+    check_iterable(&coll, 2, name)?;
+
+    let mut result = vec![];
+    seq::for_each(&coll, |x| {
+        result.push(f.call(vec![x], env)?);
+        Ok(true)
+    })?;
+    Ok(Ast::List(Rc::new(result)))
+}
+
+/// `(filter pred coll)` returns a list of the elements for which `pred` is truthy
+fn filter(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let coll = args.pop().unwrap();
+    let pred = get_callable(args.pop().unwrap(), 1, name)?;
+    check_iterable(&coll, 2, name)?;
+
+    let mut result = vec![];
+    seq::for_each(&coll, |x| {
+        if is_truthy(&pred.call(vec![x.clone()], env)?) {
+            result.push(x);
+        }
+        Ok(true)
+    })?;
+    Ok(Ast::List(Rc::new(result)))
+}
+
+/// `(reduce f init coll)` calls `(f acc x)` for each element, starting with `init`.
+/// `(reduce f coll)` starts with the first element instead, and returns `(f)` if there is none.
+fn reduce(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 3, args.len()).into());
+    }
+    let pos = args.len() as u32;
+    let coll = args.pop().unwrap();
+    let mut acc = if args.len() == 2 { args.pop() } else { None };
+    let f = get_callable(args.pop().unwrap(), 1, name)?;
+    check_iterable(&coll, pos, name)?;
+
+    seq::for_each(&coll, |x| {
+        acc = Some(match acc.take() {
+            Some(acc) => f.call(vec![acc, x], env)?,
+            None => x,
+        });
+        Ok(true)
+    })?;
+    match acc {
+        Some(acc) => Ok(acc),
+        None => f.call(vec![], env),
+    }
+}
+
+/// Elements of a list or vector, or the entries of a hash-map as `(key value)` lists
+fn get_elements(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<Vec<Ast>>, RuntimeError> {
+    match ast {
+        Ast::HashMap(map) => Ok(Rc::new(
+            map.iter()
+                .map(|(key, value)| pair(key.to_ast(), value.clone()))
+                .collect(),
+        )),
+        Ast::List(xs) | Ast::Vector(xs) => Ok(xs),
+        Ast::Nil => Ok(Rc::new(vec![])),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "List, Vector or HashMap".to_owned(),
+            ast,
+        )),
+    }
+}
+
+/// `(group-by f coll)` returns a hash-map from each result of `f`, which must be a valid map key,
+/// to a list of the elements it was returned for
+fn group_by(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let coll = get_elements(args.pop().unwrap(), 2, name)?;
+    let f = get_callable(args.pop().unwrap(), 1, name)?;
+
+    let mut groups: BTreeMap<MapKey, Vec<Ast>> = BTreeMap::new();
+    for x in coll.iter() {
+        let key = get_map_key(f.call(vec![x.clone()], env)?, 1, name)?;
+        groups.entry(key).or_default().push(x.clone());
+    }
+
+    let groups = groups
+        .into_iter()
+        .map(|(key, group)| (key, Ast::List(Rc::new(group))))
+        .collect();
+    Ok(Ast::HashMap(Rc::new(groups)))
+}
+
+/// Returns a hash-map from each distinct element, which must be a valid map key, to the number of
+/// times it occurs
+fn frequencies(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let coll = get_elements(args.pop().unwrap(), 1, name)?;
+
+    let mut counts: BTreeMap<MapKey, i64> = BTreeMap::new();
+    for x in coll.iter() {
+        *counts.entry(get_map_key(x.clone(), 1, name)?).or_default() += 1;
+    }
+
+    let counts = counts
+        .into_iter()
+        .map(|(x, n)| (x, Ast::Integer(n)))
+        .collect();
+    Ok(Ast::HashMap(Rc::new(counts)))
+}
+
+/// Removes repeated elements, keeping the first occurrence of each
+fn distinct(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let coll = get_list(args.pop().unwrap(), 1, name)?;
+
+    let mut result: Vec<Ast> = vec![];
+    for x in coll.iter() {
+        if !result.iter().any(|y| values_equal(x, y)) {
+            result.push(x.clone());
+        }
+    }
+    Ok(Ast::List(Rc::new(result)))
+}
+
+/// `(interleave xs ys ...)` takes the first element of each list, then the second and so on,
+/// stopping when the shortest list is used up
+fn interleave(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let lists = args
+        .into_iter()
+        .enumerate()
+        .map(|(i, xs)| get_list(xs, i as u32 + 1, name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let len = lists.iter().map(|xs| xs.len()).min().unwrap_or(0);
+    let result = (0..len)
+        .flat_map(|i| lists.iter().map(move |xs| xs[i].clone()))
+        .collect();
+    Ok(Ast::List(Rc::new(result)))
+}
+
+/// `(interpose sep xs)` puts `sep` between the elements of `xs`
+fn interpose(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let xs = get_list(args.pop().unwrap(), 2, name)?;
+    let separator = args.pop().unwrap();
+
+    let mut result = Vec::with_capacity((xs.len() * 2).saturating_sub(1));
+    for (i, x) in xs.iter().enumerate() {
+        if i > 0 {
+            result.push(separator.clone());
+        }
+        result.push(x.clone());
+    }
+    Ok(Ast::List(Rc::new(result)))
+}
+
+/// `(zipmap keys values)` pairs up keys and values into a hash-map, as far as both collections
+/// go. A later duplicate key replaces the earlier one.
+fn zipmap(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let values = get_elements(args.pop().unwrap(), 2, name)?;
+    let keys = get_elements(args.pop().unwrap(), 1, name)?;
+
+    let mut map = BTreeMap::new();
+    for (k, v) in keys.iter().zip(values.iter()) {
+        map.insert(get_map_key(k.clone(), 1, name)?, v.clone());
+    }
+    Ok(Ast::HashMap(Rc::new(map)))
+}
+
+/// Reads the entries of a hash-map or the `(key value)` entries of an association list
+fn get_entries(ast: Ast, pos: u32, fn_name: &str) -> Result<Vec<(Ast, Ast)>, RuntimeError> {
+    if let Ast::HashMap(map) = ast {
+        return Ok(map
+            .iter()
+            .map(|(key, value)| (key.to_ast(), value.clone()))
+            .collect());
+    }
+    let entries = get_list(ast, pos, fn_name)?;
+    entries
+        .iter()
+        .map(|entry| match entry {
+            Ast::List(kv) if kv.len() == 2 => Ok((kv[0].clone(), kv[1].clone())),
+            _ => Err(RuntimeError::TypeMismatch(
+                fn_name.to_owned(),
+                pos,
+                "association list".to_owned(),
+                Ast::List(Rc::clone(&entries)),
+            )),
+        })
+        .collect()
+}
+
+/// Collects entries into a hash-map if `as_map`, or else into an association list. The keys of a
+/// hash-map's entries are always valid map keys again.
+fn from_entries(entries: Vec<(Ast, Ast)>, as_map: bool) -> Ast {
+    if as_map {
+        let map = entries
+            .into_iter()
+            .filter_map(|(key, value)| Some((MapKey::from_ast(&key)?, value)))
+            .collect();
+        Ast::HashMap(Rc::new(map))
+    } else {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| pair(key, value))
+            .collect();
+        Ast::List(Rc::new(entries))
+    }
+}
+
+/// `(reduce-kv f init m)` calls `(f acc key value)` for each entry, starting with `init`
+fn reduce_kv(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 3, name)?;
+    let entries = get_entries(args.pop().unwrap(), 3, name)?;
+    let mut acc = args.pop().unwrap();
+    let f = get_callable(args.pop().unwrap(), 1, name)?;
+
+    for (key, value) in entries {
+        acc = f.call(vec![acc, key, value], env)?;
+    }
+    Ok(acc)
+}
+
+/// `(map-vals f m)` replaces each value of a hash-map or association list with `(f value)`,
+/// returning the same kind of collection
+fn map_vals(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let m = args.pop().unwrap();
+    let as_map = matches!(m, Ast::HashMap(_));
+    let entries = get_entries(m, 2, name)?;
+    let f = get_callable(args.pop().unwrap(), 1, name)?;
+
+    let mut result = Vec::with_capacity(entries.len());
+    for (key, value) in entries {
+        result.push((key, f.call(vec![value], env)?));
+    }
+    Ok(from_entries(result, as_map))
+}
+
+/// `(filter-keys pred m)` keeps the entries of a hash-map or association list whose key
+/// satisfies `pred`, returning the same kind of collection
+fn filter_keys(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let m = args.pop().unwrap();
+    let as_map = matches!(m, Ast::HashMap(_));
+    let entries = get_entries(m, 2, name)?;
+    let pred = get_callable(args.pop().unwrap(), 1, name)?;
+
+    let mut result = vec![];
+    for (key, value) in entries {
+        if is_truthy(&pred.call(vec![key.clone()], env)?) {
+            result.push((key, value));
+        }
+    }
+    Ok(from_entries(result, as_map))
+}
+
+#[cfg(feature = "math")]
+/// `(format-number n options...)` formats an integer or float. Options are given as name and
+/// value: `"width" 8` pads to 8 characters, `"pad" "0"` pads with zeros instead of spaces,
+/// `"decimals" 2` rounds to 2 decimal places (integers get zeros), `"separator" ","` groups
+/// digits in thousands and `"radix" 16` writes an integer in another base from 2 to 36.
+fn format_number(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    if args.len().is_multiple_of(2) {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 1, args.len()).into());
+    }
+
+    let mut args = args.into_iter();
+    let n = args.next().unwrap();
+    let (mut width, mut pad, mut decimals, mut separator, mut radix) = (0, ' ', 0, None, 10);
+
+    let mut pos = 2;
+    while let (Some(option), Some(value)) = (args.next(), args.next()) {
+        let option = get_str(option, pos, name)?;
+        let invalid = |value| {
+            RuntimeError::TypeMismatch(
+                name.to_owned(),
+                pos + 1,
+                format!("{} option", option),
+                value,
+            )
+        };
+
+        match (option.as_str(), value) {
+            ("width", Ast::Integer(w)) if w >= 0 => width = w as usize,
+            ("decimals", Ast::Integer(d)) if d >= 0 => decimals = d as usize,
+            ("radix", Ast::Integer(r)) if (2..=36).contains(&r) => radix = r as u32,
+            ("pad", Ast::String(p)) if p.chars().count() == 1 => pad = p.chars().next().unwrap(),
+            ("separator", Ast::String(sep)) => separator = Some(sep),
+            (_, value) => return Err(invalid(value).into()),
+        }
+        pos += 2;
+    }
+
+    let (negative, mut digits, fraction) = match n {
+        Ast::Float(x) if x.is_finite() => {
+            if radix != 10 {
+                return Err(RuntimeError::TypeMismatch(
+                    name.to_owned(),
+                    1,
+                    "Integer with a radix".to_owned(),
+                    Ast::Float(x),
+                )
+                .into());
+            }
+            let rounded = format!("{:.*}", decimals, x.abs());
+            // Leaves out the sign of numbers that round to zero, like -0.001 to 2 decimals
+            let negative = x < 0.0 && rounded.chars().any(|c| c.is_ascii_digit() && c != '0');
+            let (whole, fraction) = rounded.split_once('.').unwrap_or((&rounded, ""));
+            (negative, whole.to_owned(), fraction.to_owned())
+        }
+        Ast::Integer(n) => (
+            n < 0,
+            to_radix(n.unsigned_abs(), radix),
+            "0".repeat(decimals),
+        ),
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                name.to_owned(),
+                1,
+                "finite Number".to_owned(),
+                other,
+            )
+            .into())
+        }
+    };
+    if let Some(separator) = separator {
+        digits = group_digits(&digits, &separator);
+    }
+    if decimals > 0 {
+        digits = format!("{}.{}", digits, fraction);
+    }
+
+    let sign = if negative { "-" } else { "" };
+    let padding = width.saturating_sub(sign.len() + digits.chars().count());
+    let padding = pad.to_string().repeat(padding);
+    Ok(Ast::String(if pad == '0' {
+        format!("{}{}{}", sign, padding, digits)
+    } else {
+        format!("{}{}{}", padding, sign, digits)
+    }))
+}
+
+#[cfg(feature = "math")]
+fn to_radix(mut n: u64, radix: u32) -> String {
+    let mut digits = vec![];
+    loop {
+        digits.push(std::char::from_digit((n % radix as u64) as u32, radix).unwrap());
+        n /= radix as u64;
+        if n == 0 {
+            break;
+        }
+    }
+    digits.iter().rev().collect()
+}
+
+#[cfg(feature = "math")]
+/// Inserts the separator between groups of three digits, counted from the right
+fn group_digits(digits: &str, separator: &str) -> String {
+    let chars: Vec<char> = digits.chars().collect();
+    let groups: Vec<String> = chars
+        .rchunks(3)
+        .rev()
+        .map(|group| group.iter().collect())
+        .collect();
+    groups.join(separator)
+}
+
+/// Returns the calls in progress, innermost first, as hash-maps with the `:name` of the called
+/// function and the `:form` of the call. The call to `backtrace` itself is left out.
+fn backtrace(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 0, name)?;
+
+    let frames = callstack::backtrace().into_iter().skip(1).collect();
+    Ok(callstack::frames_to_ast(frames))
+}
+
+/// Number of function calls in progress, not counting the call to `stack-depth`
+fn stack_depth(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 0, name)?;
+    Ok(Ast::Integer(callstack::stack_depth() as i64 - 1))
+}
+
+/// Name of the type of a value, as returned by `type-of` without the colon
+pub fn type_name(ast: &Ast) -> &'static str {
+    match ast {
+        Ast::Symbol(_) | Ast::Local(..) => "symbol",
+        Ast::Integer(_) => "integer",
+        Ast::Float(_) => "float",
+        Ast::Keyword(_) => "keyword",
+        Ast::Boolean(_) => "boolean",
+        Ast::String(_) => "string",
+        Ast::List(_) => "list",
+        Ast::Vector(_) => "vector",
+        Ast::HashMap(_) => "hash-map",
+        Ast::Function(_) | Ast::Builtin(..) | Ast::Closure(_) => "function",
+        Ast::Nil => "nil",
+        Ast::Atom(_) => "atom",
+        Ast::MutableVector(_) => "mutable-vector",
+        Ast::IntArray(_) => "int-array",
+        Ast::FloatArray(_) => "float-array",
+        Ast::StringBuilder(_) => "string-builder",
+        Ast::Port(_) => "port",
+        Ast::Seq(_) => "seq",
+        Ast::Reader(_) => "reader",
+        Ast::Env(_) => "environment",
+    }
+}
+
+fn type_of(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+
+    Ok(Ast::Keyword(type_name(&a).to_owned()))
+}
+
+/// `(keyword "name")` is `:name`, a keyword is returned as it is
+fn keyword(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+
+    match args.pop().unwrap() {
+        Ast::String(s) | Ast::Keyword(s) => Ok(Ast::Keyword(s)),
+        other => Err(RuntimeError::TypeMismatch(
+            name.to_owned(),
+            1,
+            "String or Keyword".to_owned(),
+            other,
+        )
+        .into()),
+    }
+}
+
+fn keyword_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+
+    Ok(Ast::Boolean(matches!(a, Ast::Keyword(_))))
+}
+
+/// `(= a b ...)` is true if all arguments are equal according to `values_equal`: lists by their
+/// elements, atoms by identity
+fn op_eq(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.is_empty() {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 1, 0).into());
+    }
+
+    Ok(Ast::Boolean(
+        args.windows(2).all(|w| values_equal(&w[0], &w[1])),
+    ))
+}
+
+/// `(not= a b ...)` is true unless all arguments are equal
+fn op_not_eq(name: &str, args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    match op_eq(name, args, env)? {
+        Ast::Boolean(equal) => Ok(Ast::Boolean(!equal)),
+        _ => unreachable!("= returns a boolean"),
+    }
+}
+
+/// `(identical? a b)` is true if both are the same list, vector, hash-map, function or atom. Values without an
+/// identity, like numbers and strings, are compared by value.
+fn identical_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let b = args.pop().unwrap();
+    let a = args.pop().unwrap();
+
+    let identical = match (&a, &b) {
+        (Ast::List(a), Ast::List(b)) | (Ast::Vector(a), Ast::Vector(b)) => Rc::ptr_eq(a, b),
+        (Ast::HashMap(a), Ast::HashMap(b)) => Rc::ptr_eq(a, b),
+        _ => values_equal(&a, &b),
+    };
+    Ok(Ast::Boolean(identical))
+}
+
+/// Orders two numbers or two strings, strings lexicographically. Integers and floats compare
+/// by value; NaN is unordered relative to every number, giving `None`. Other values, and a
+/// number and a string, can't be compared. `pos` is the argument position of `a`, `b` follows it.
+fn compare(a: &Ast, b: &Ast, pos: u32, name: &str) -> Result<Option<Ordering>, RuntimeError> {
+    match (a, b) {
+        (Ast::Integer(a), Ast::Integer(b)) => Ok(Some(a.cmp(b))),
+        (Ast::Integer(_) | Ast::Float(_), Ast::Integer(_) | Ast::Float(_)) => {
+            let a = get_float(a.clone(), pos, name)?;
+            let b = get_float(b.clone(), pos + 1, name)?;
+            Ok(a.partial_cmp(&b))
+        }
+        (Ast::String(a), Ast::String(b)) => Ok(Some(a.cmp(b))),
+        (Ast::Integer(_) | Ast::Float(_), _) => Err(RuntimeError::TypeMismatch(
+            name.to_owned(),
+            pos + 1,
+            "Number".to_owned(),
+            b.clone(),
+        )),
+        (Ast::String(_), _) => Err(RuntimeError::TypeMismatch(
+            name.to_owned(),
+            pos + 1,
+            "String".to_owned(),
+            b.clone(),
+        )),
+        _ => Err(RuntimeError::TypeMismatch(
+            name.to_owned(),
+            pos,
+            "Number or String".to_owned(),
+            a.clone(),
+        )),
+    }
+}
+
+/// Tests that each argument is ordered relative to the next one as `test` expects, so
+/// `(< 1 2 3)` checks that the arguments are increasing. All arguments are checked to be
+/// comparable, even if the result is already known to be false. NaN is unordered, so any
+/// comparison with it is false.
+fn compare_args(name: &str, args: Vec<Ast>, test: fn(Ordering) -> bool) -> Result<Ast, ReplError> {
+    if args.is_empty() {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 1, 0).into());
+    }
+
+    let mut result = true;
+    for (i, w) in args.windows(2).enumerate() {
+        result &= compare(&w[0], &w[1], i as u32 + 1, name)?.is_some_and(test);
+    }
+    Ok(Ast::Boolean(result))
+}
+
+fn op_lt(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    compare_args(name, args, Ordering::is_lt)
+}
+
+fn op_gt(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    compare_args(name, args, Ordering::is_gt)
+}
+
+fn op_le(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    compare_args(name, args, Ordering::is_le)
+}
+
+fn op_ge(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    compare_args(name, args, Ordering::is_ge)
+}
+
+fn list(_name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    Ok(Ast::List(Rc::new(args)))
+}
+
+fn list_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+    Ok(Ast::Boolean(matches!(a, Ast::List(_))))
+}
+
+fn vector(_name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    Ok(Ast::Vector(Rc::new(args)))
+}
+
+fn vector_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+    Ok(Ast::Boolean(matches!(a, Ast::Vector(_))))
+}
+
+/// Elements of a list or vector, nil being the empty list
+fn get_sequential(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<Vec<Ast>>, RuntimeError> {
+    match ast {
+        Ast::List(xs) | Ast::Vector(xs) => Ok(xs),
+        Ast::Nil => Ok(Rc::new(vec![])),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "List or Vector".to_owned(),
+            ast,
+        )),
+    }
+}
+
+/// `(nth coll i)` returns the element at index `i` of a list or vector
+fn nth(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let index = get_int(args.pop().unwrap(), 2, name)?;
+    let xs = get_sequential(args.pop().unwrap(), 1, name)?;
+
+    let i = vector_index(index, xs.len(), name)?;
+    Ok(xs[i].clone())
+}
+
+/// `(cons x coll)` returns a list of `x` followed by the elements of a list or vector
+fn cons(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let xs = get_sequential(args.pop().unwrap(), 2, name)?;
+    let x = args.pop().unwrap();
+
+    let mut result = Vec::with_capacity(xs.len() + 1);
+    result.push(x);
+    result.extend(xs.iter().cloned());
+    Ok(Ast::List(Rc::new(result)))
+}
+
+/// `(first coll)` returns the first element of a list or vector, or nil if it is empty
+fn first(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let xs = get_sequential(args.pop().unwrap(), 1, name)?;
+    Ok(xs.first().cloned().unwrap_or(Ast::Nil))
+}
+
+/// `(rest coll)` returns a list of all elements but the first, which is empty for an empty
+/// collection
+fn rest(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let xs = get_sequential(args.pop().unwrap(), 1, name)?;
+    Ok(Ast::List(Rc::new(xs.iter().skip(1).cloned().collect())))
+}
+
+/// `(last coll)` returns the last element of a list or vector, or nil if it is empty
+fn last(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let xs = get_sequential(args.pop().unwrap(), 1, name)?;
+    Ok(xs.last().cloned().unwrap_or(Ast::Nil))
+}
+
+/// `(conj coll x...)` adds the values where it is cheapest: at the end of a vector and at the
+/// front of a list, so `(conj '(1 2) 3 4)` is `(4 3 1 2)`. nil is treated as the empty list.
+fn conj(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.is_empty() {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 1, 0).into());
+    }
+    let values = args.split_off(1);
+
+    match args.pop().unwrap() {
+        Ast::Vector(xs) => {
+            let mut xs = Rc::unwrap_or_clone(xs);
+            xs.extend(values);
+            Ok(Ast::Vector(Rc::new(xs)))
+        }
+        Ast::List(xs) => {
+            let mut result: Vec<Ast> = values.into_iter().rev().collect();
+            result.extend(xs.iter().cloned());
+            Ok(Ast::List(Rc::new(result)))
+        }
+        Ast::Nil => Ok(Ast::List(Rc::new(values.into_iter().rev().collect()))),
+        other => {
+            Err(
+                RuntimeError::TypeMismatch(name.to_owned(), 1, "List or Vector".to_owned(), other)
+                    .into(),
+            )
+        }
+    }
+}
+
+/// Number of elements of a collection or characters of a string; nil counts as an empty list
+fn length_of(ast: Ast, name: &str) -> Result<usize, RuntimeError> {
+    match ast {
+        Ast::List(xs) | Ast::Vector(xs) => Ok(xs.len()),
+        Ast::HashMap(map) => Ok(map.len()),
+        Ast::MutableVector(v) => Ok(v.borrow().len()),
+        Ast::IntArray(a) => Ok(a.borrow().len()),
+        Ast::FloatArray(a) => Ok(a.borrow().len()),
+        Ast::StringBuilder(sb) => Ok(sb.borrow().chars().count()),
+        Ast::String(s) => Ok(s.chars().count()),
+        Ast::Nil => Ok(0),
+        other => Err(RuntimeError::TypeMismatch(
+            name.to_owned(),
+            1,
+            "List, Vector, HashMap, MutableVector, IntArray, FloatArray, String, StringBuilder \
+             or nil"
+                .to_owned(),
+            other,
+        )),
+    }
+}
+
+fn empty_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+    Ok(Ast::Boolean(length_of(a, name)? == 0))
+}
+
+fn count(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+    Ok(Ast::Integer(length_of(a, name)? as i64))
+}
+
+/// Appends a value the way `str` shows it: strings as they are, other values printed
+fn append_str(buffer: &mut String, value: &Ast) {
+    match value {
+        Ast::String(s) => buffer.push_str(s),
+        other => buffer.push_str(&format!("{:?}", other)),
+    }
+}
+
+fn concat_str(
+    _name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let capacity = args
+        .iter()
+        .map(|arg| match arg {
+            Ast::String(s) => s.len(),
+            _ => 0,
+        })
+        .sum();
+
+    let mut str = String::with_capacity(capacity);
+    for arg in &args {
+        append_str(&mut str, arg);
+    }
+
+    Ok(Ast::String(str))
+}
+
+#[cfg(feature = "string")]
+/// `(string-builder parts...)` returns a string builder starting with the parts joined like `str`
+fn string_builder(
+    _name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let mut str = String::new();
+    for arg in &args {
+        append_str(&mut str, arg);
+    }
+    Ok(Ast::StringBuilder(Rc::new(RefCell::new(str))))
+}
+
+#[cfg(feature = "string")]
+/// `(sb-append! sb parts...)` appends the parts like `str` would join them and returns `sb`
+fn sb_append_m(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    if args.is_empty() {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 1, 0).into());
+    }
+    let sb = match args.remove(0) {
+        Ast::StringBuilder(sb) => sb,
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                name.to_owned(),
+                1,
+                "StringBuilder".to_owned(),
+                other,
+            )
+            .into())
+        }
+    };
+
+    {
+        let mut str = sb.borrow_mut();
+        for arg in &args {
+            append_str(&mut str, arg);
+        }
+    }
+    Ok(Ast::StringBuilder(sb))
+}
+
+#[cfg(feature = "string")]
+/// Returns the string built so far
+fn sb_str(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    match args.pop().unwrap() {
+        Ast::StringBuilder(sb) => Ok(Ast::String(sb.borrow().clone())),
+        other => {
+            Err(
+                RuntimeError::TypeMismatch(name.to_owned(), 1, "StringBuilder".to_owned(), other)
+                    .into(),
+            )
+        }
+    }
+}
+
+#[cfg(feature = "string")]
+fn string_length(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let s = get_str(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::Integer(s.chars().count() as i64))
+}
+
+#[cfg(feature = "string")]
+/// `(substring s start end?)` takes the characters from `start` up to, but excluding, `end`,
+/// which defaults to the end of the string
+fn substring(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 3, args.len()).into());
+    }
+
+    let mut args = args.into_iter();
+    let chars: Vec<char> = get_str(args.next().unwrap(), 1, name)?.chars().collect();
+    let start = get_int(args.next().unwrap(), 2, name)?;
+    let end = match args.next() {
+        Some(end) => get_int(end, 3, name)?,
+        None => chars.len() as i64,
+    };
+
+    let in_bounds = |i: i64| (0..=chars.len() as i64).contains(&i);
+    if !in_bounds(start) {
+        return Err(RuntimeError::IndexOutOfBounds(name.to_owned(), start, chars.len()).into());
+    }
+    if !in_bounds(end) || end < start {
+        return Err(RuntimeError::IndexOutOfBounds(name.to_owned(), end, chars.len()).into());
+    }
+
+    Ok(Ast::String(
+        chars[start as usize..end as usize].iter().collect(),
+    ))
+}
+
+#[cfg(feature = "string")]
+fn string_reverse(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let s = get_str(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::String(s.chars().rev().collect()))
+}
+
+#[cfg(feature = "string")]
+fn upper_case(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let s = get_str(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::String(s.to_uppercase()))
+}
+
+#[cfg(feature = "string")]
+fn lower_case(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let s = get_str(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::String(s.to_lowercase()))
+}
+
+#[cfg(feature = "string")]
+fn char_to_int(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let c = get_char(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::Integer(c as i64))
+}
+
+#[cfg(feature = "string")]
+fn int_to_char(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let n = args.pop().unwrap();
+
+    match &n {
+        Ast::Integer(code) => match u32::try_from(*code).ok().and_then(char::from_u32) {
+            Some(c) => Ok(Ast::String(c.to_string())),
+            None => Err(RuntimeError::TypeMismatch(
+                name.to_owned(),
+                1,
+                "Unicode code point".to_owned(),
+                n,
+            )
+            .into()),
+        },
+        _ => Err(RuntimeError::TypeMismatch(name.to_owned(), 1, "Integer".to_owned(), n).into()),
+    }
+}
+
+#[cfg(feature = "string")]
+fn char_predicate(
+    name: &str,
+    mut args: Vec<Ast>,
+    predicate: fn(&char) -> bool,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let c = get_char(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::Boolean(predicate(&c)))
+}
+
+#[cfg(feature = "string")]
+fn digit_q(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    char_predicate(name, args, char::is_ascii_digit)
+}
+
+#[cfg(feature = "string")]
+fn alpha_q(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    char_predicate(name, args, |c| c.is_alphabetic())
+}
+
+#[cfg(feature = "string")]
+fn whitespace_q(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    char_predicate(name, args, |c| c.is_whitespace())
+}
+
+#[cfg(feature = "string")]
+/// Converts a character to upper case, leaving it unchanged where the upper case form consists of
+/// several characters (like for 'ß')
+fn upper_char(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let c = get_char(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::String(
+        single_char_mapping(c, c.to_uppercase()).to_string(),
+    ))
+}
+
+#[cfg(feature = "string")]
+fn lower_char(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let c = get_char(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::String(
+        single_char_mapping(c, c.to_lowercase()).to_string(),
+    ))
+}
+
+#[cfg(feature = "string")]
+fn single_char_mapping(c: char, mut mapped: impl Iterator<Item = char>) -> char {
+    match (mapped.next(), mapped.next()) {
+        (Some(m), None) => m,
+        _ => c,
+    }
+}
+
+#[cfg(feature = "string")]
+/// Splits a string into a list of one-character strings
+fn chars(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let s = get_str(args.pop().unwrap(), 1, name)?;
+
+    let chars = s.chars().map(|c| Ast::String(c.to_string())).collect();
+    Ok(Ast::List(Rc::new(chars)))
+}
+
+#[cfg(feature = "io")]
+fn slurp(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let encoding = if args.len() == 2 {
+        get_str(args.pop().unwrap(), 2, name)?
+    } else {
+        check_arity(&args, 1, name)?;
+        "utf-8".to_owned()
+    };
+    let file_name = get_str(args.pop().unwrap(), 1, name)?;
+
+    let content = match encoding.as_str() {
+        "utf-8" => fs::read_to_string(file_name)?,
+        "latin-1" => fs::read(file_name)?.into_iter().map(char::from).collect(),
+        "lossy" => String::from_utf8_lossy(&fs::read(file_name)?).into_owned(),
+        _ => {
+            return Err(RuntimeError::TypeMismatch(
+                name.to_owned(),
+                2,
+                "\"utf-8\", \"latin-1\" or \"lossy\"".to_owned(),
+                Ast::String(encoding),
+            )
+            .into())
+        }
+    };
+    Ok(Ast::String(content))
+}
+
+#[cfg(feature = "io")]
+fn slurp_bytes(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let file_name = get_str(args.pop().unwrap(), 1, name)?;
+
+    let bytes = fs::read(file_name)?
+        .into_iter()
+        .map(|b| Ast::Integer(b as i64))
+        .collect();
+    Ok(Ast::List(Rc::new(bytes)))
+}
+
+#[cfg(feature = "io")]
+/// `(write-ast file form)` stores a form in binary, so it can be loaded with `read-ast` without
+/// parsing it again
+fn write_ast(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let form = args.pop().unwrap();
+    let file_name = get_str(args.pop().unwrap(), 1, name)?;
+
+    let bytes = serialize::encode(&form).map_err(|value| {
+        RuntimeError::TypeMismatch(name.to_owned(), 2, "serializable value".to_owned(), value)
+    })?;
+    fs::write(file_name, bytes)?;
+    Ok(Ast::Nil)
+}
+
+#[cfg(feature = "io")]
+fn read_ast(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let file_name = get_str(args.pop().unwrap(), 1, name)?;
+
+    Ok(serialize::decode(&fs::read(file_name)?)?)
+}
+
+#[cfg(feature = "io")]
+/// `(spit file content options)` writes a string to a file and returns the number of bytes
+/// written. The optional options map takes `:append true`, `:create-new true` (fail if the file
+/// exists) and `:mode n`, a permission mode that is applied on unix, e.g.
+/// `(spit "log.txt" line {:append true :mode 384})`.
+fn spit(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 3, args.len()).into());
+    }
+
+    let mut args = args.into_iter();
+    let file_name = get_str(args.next().unwrap(), 1, name)?;
+    let content = get_str(args.next().unwrap(), 2, name)?;
+    let options = get_map(args.next().unwrap_or(Ast::Nil), 3, name)?;
+
+    let mut open_options = fs::OpenOptions::new();
+    open_options.write(true).create(true).truncate(true);
+    let mut mode = None;
+
+    for (key, value) in options.iter() {
+        match key {
+            MapKey::Keyword(k) if k == "append" => {
+                if is_truthy(value) {
+                    open_options.append(true).truncate(false);
+                }
+            }
+            MapKey::Keyword(k) if k == "create-new" => {
+                open_options.create_new(is_truthy(value));
+            }
+            MapKey::Keyword(k) if k == "mode" => {
+                let n = get_int(value.clone(), 3, name)?;
+                mode = Some(u32::try_from(n).map_err(|_| {
+                    RuntimeError::TypeMismatch(
+                        name.to_owned(),
+                        3,
+                        "permission mode from 0 to 4294967295".to_owned(),
+                        Ast::Integer(n),
+                    )
+                })?);
+            }
+            other => {
+                return Err(RuntimeError::TypeMismatch(
+                    name.to_owned(),
+                    3,
+                    ":append, :create-new or :mode".to_owned(),
+                    other.to_ast(),
+                )
+                .into())
+            }
+        }
+    }
+
+    let mut file = open_options.open(&file_name)?;
+    file.write_all(content.as_bytes())?;
+
+    if let Some(mode) = mode {
+        set_mode(&file_name, mode)?;
+    }
+
+    Ok(Ast::Integer(content.len() as i64))
+}
+
+#[cfg(all(feature = "io", unix))]
+fn set_mode(file_name: &str, mode: u32) -> Result<(), io::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(file_name, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(all(feature = "io", not(unix)))]
+fn set_mode(_file_name: &str, _mode: u32) -> Result<(), io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Permission modes are only supported on unix",
+    ))
+}
+
+#[cfg(feature = "math")]
+/// Seed of `rand-int` in deterministic mode
+const FIXED_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+thread_local! {
+    static RNG_STATE: Cell<Option<u64>> = const { Cell::new(None) };
+    static CLOCK: Cell<i64> = const { Cell::new(0) };
+}
+
+/// Restarts the random number generator and the deterministic clock
+pub fn reset_random_and_clock() {
+    RNG_STATE.with(|state| state.set(None));
+    CLOCK.with(|clock| clock.set(0));
+}
+
+#[cfg(feature = "math")]
+fn next_random() -> u64 {
+    RNG_STATE.with(|state| {
+        let mut x = state.get().unwrap_or_else(|| {
+            if with_options(|options| options.deterministic) {
+                FIXED_SEED
+            } else {
+                let nanos = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64);
+                nanos | 1
+            }
+        });
+
+        // xorshift64*
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(Some(x));
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+#[cfg(feature = "math")]
+/// `(rand-int n)` returns a random integer from 0 up to, but excluding, n
+fn rand_int(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let n = match args.pop().unwrap() {
+        Ast::Integer(n) if n > 0 => n,
+        other => {
+            return Err(RuntimeError::TypeMismatch(
+                name.to_owned(),
+                1,
+                "positive Integer".to_owned(),
+                other,
+            )
+            .into())
+        }
+    };
+
+    Ok(Ast::Integer((next_random() % n as u64) as i64))
+}
+
+#[cfg(feature = "os")]
+/// Milliseconds since the unix epoch, or a counter going up by one per call in deterministic mode
+fn time_ms(name: &str, args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 0, name)?;
+
+    if with_options(|options| options.deterministic) {
+        return Ok(Ast::Integer(CLOCK.with(|clock| {
+            clock.set(clock.get() + 1);
+            clock.get()
+        })));
+    }
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    Ok(Ast::Integer(millis))
+}
+
+thread_local! {
+    static LOADED_FILES: RefCell<Vec<PathBuf>> = const { RefCell::new(vec![]) };
+    /// Files whose forms are being evaluated, innermost last
+    static LOADING: RefCell<Vec<PathBuf>> = const { RefCell::new(vec![]) };
+    /// File each root definition was made by, for definitions made while loading a file
+    static DEFINED_IN: RefCell<HashMap<String, PathBuf>> = RefCell::new(HashMap::new());
+}
+
+/// Remembers which file, if any, made the root definition of `name`
+pub fn record_root_definition(name: &str) {
+    let file = LOADING.with(|loading| loading.borrow().last().cloned());
+    DEFINED_IN.with(|defined| match file {
+        Some(file) => defined.borrow_mut().insert(name.to_owned(), file),
+        None => defined.borrow_mut().remove(name),
+    });
+}
+
+/// Whether `name` is being defined again by the file that defined it, i.e. by reloading it
+pub fn is_redefined_by_its_file(name: &str) -> bool {
+    let file = LOADING.with(|loading| loading.borrow().last().cloned());
+    file.is_some() && DEFINED_IN.with(|defined| defined.borrow().get(name) == file.as_ref())
+}
+
+/// Files evaluated with `load-file`, in the order they were first loaded
+pub fn loaded_files() -> Vec<PathBuf> {
+    LOADED_FILES.with(|files| files.borrow().clone())
+}
+
+pub fn forget_loaded_files() {
+    LOADED_FILES.with(|files| files.borrow_mut().clear());
+    DEFINED_IN.with(|defined| defined.borrow_mut().clear());
+}
+
+/// Evaluates all forms of a file in the root environment and remembers it as loaded
+pub fn load(file_name: &str, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    let forms = serialize::read_program(file_name)?;
+    let path = fs::canonicalize(file_name)?;
+    LOADED_FILES.with(|files| {
+        let mut files = files.borrow_mut();
+        if !files.contains(&path) {
+            files.push(path.clone());
+        }
+    });
+
+    let root = get_root(env);
+    LOADING.with(|loading| loading.borrow_mut().push(path));
+    let result = forms
+        .into_iter()
+        .try_for_each(|form| eval(form, &root).map(drop));
+    LOADING.with(|loading| loading.borrow_mut().pop());
+
+    result.map(|_| Ast::Nil)
+}
+
+#[cfg(feature = "io")]
+fn load_file(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let file_name = get_str(args.pop().unwrap(), 1, name)?;
+
+    load(&file_name, env)
+}
+
+#[cfg(feature = "io")]
+/// Evaluates a previously loaded file again. Definitions are replaced, while `defonce` keeps
+/// existing state such as atoms.
+fn reload(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let file_name = get_str(args.pop().unwrap(), 1, name)?;
+
+    let path = fs::canonicalize(&file_name)?;
+    if !loaded_files().contains(&path) {
+        return Err(RuntimeError::NotLoaded(file_name).into());
+    }
+
+    load(&file_name, env)
+}
+
+fn read_str(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = get_str(args.pop().unwrap(), 1, name)?;
+
+    a.parse()
+        .map_err(|err| ReplError::from(err).with_source(&a))
+}
+
+fn get_env(ast: Ast, pos: u32, fn_name: &str) -> Result<Rc<RefCell<Environment>>, RuntimeError> {
+    match ast {
+        Ast::Env(env) => Ok(env),
+        _ => Err(RuntimeError::TypeMismatch(
+            fn_name.to_owned(),
+            pos,
+            "Environment".to_owned(),
+            ast,
+        )),
+    }
+}
+
+/// `(fork-env)` creates an environment whose parent is the calling one, `(fork-env e)` one whose
+/// parent is `e`. Definitions made in it don't affect the parent.
+fn fork_env(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let parent = match args.len() {
+        0 => Rc::clone(env),
+        1 => get_env(args.pop().unwrap(), 1, name)?,
+        n => return Err(RuntimeError::WrongArity(name.to_owned(), 1, n).into()),
+    };
+
+    let child = Environment::frame(Rc::new(vec![]), vec![], &parent);
+    Ok(Ast::Env(Rc::new(RefCell::new(child))))
+}
+
+/// `(eval-in e form)` evaluates a form in an environment made by `fork-env`
+fn eval_in(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let form = args.pop().unwrap();
+    let target = get_env(args.pop().unwrap(), 1, name)?;
+
+    eval(form, &target)
+}
+
+/// `(throw value)` raises the value as an error, which `try*` can catch
+fn throw(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    Err(ReplError::Thrown(args.pop().unwrap()))
+}
+
+/// `(macroexpand form)` expands a macro call until its head isn't a macro anymore and returns
+/// the result without evaluating it
+fn macroexpand(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let mut form = args.pop().unwrap();
+
+    while let Ast::List(xs) = &form {
+        let Some(f) = macro_function(xs, env) else {
+            break;
+        };
+        form = Callable::User(f).call(xs[1..].to_vec(), env)?;
+    }
+    Ok(form)
+}
+
+/// Lists the names of all bindings and special forms whose name contains the given string
+fn apropos(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let pattern = get_str(args.pop().unwrap(), 1, name)?;
+
+    let matches = complete::apropos(&pattern, &env.borrow())
+        .into_iter()
+        .map(|completion| Ast::Symbol(completion.name))
+        .collect();
+    Ok(Ast::List(Rc::new(matches)))
+}
+
+/// Prints the tokens and the parsed structure of a string without evaluating it
+fn read_str_debug(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = get_str(args.pop().unwrap(), 1, name)?;
+
+    write_line(&describe_tokens(&a), env)?;
+    for form in parser::read_all(&a)? {
+        write_line(&describe_ast(&form), env)?;
+    }
+    Ok(Ast::Nil)
+}
+
+fn read_all(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = get_str(args.pop().unwrap(), 1, name)?;
+
+    Ok(Ast::List(Rc::new(parser::read_all(&a)?)))
+}
+
+/// Evaluates every form of a string and returns the last result. By default the forms are
+/// evaluated in the root environment like with `eval`, an optional second argument of `"local"`
+/// evaluates them in the calling environment instead.
+fn eval_string(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let target = if args.len() == 2 {
+        match args.pop().unwrap() {
+            Ast::String(s) if s == "root" => get_root(env),
+            Ast::String(s) if s == "local" => Rc::clone(env),
+            other => {
+                return Err(RuntimeError::TypeMismatch(
+                    name.to_owned(),
+                    2,
+                    "\"root\" or \"local\"".to_owned(),
+                    other,
+                )
+                .into())
+            }
+        }
+    } else {
+        check_arity(&args, 1, name)?;
+        get_root(env)
+    };
+    let code = get_str(args.pop().unwrap(), 1, name)?;
+
+    let mut result = Ast::Nil;
+    for form in parser::read_all(&code)? {
+        result = eval(form, &target)?;
+    }
+
+    Ok(result)
+}
+
+/* Mutable vector */
+/// `(mutable-vector xs...)` returns a new mutable vector of the arguments
+fn mutable_vector(
+    _name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    Ok(Ast::MutableVector(Rc::new(RefCell::new(args))))
+}
+
+fn mutable_vector_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+    Ok(Ast::Boolean(matches!(a, Ast::MutableVector(_))))
+}
+
+/// Position `index` in a vector of length `len`, if it is in bounds
+fn vector_index(index: i64, len: usize, name: &str) -> Result<usize, RuntimeError> {
+    usize::try_from(index)
+        .ok()
+        .filter(|&i| i < len)
+        .ok_or_else(|| RuntimeError::IndexOutOfBounds(name.to_owned(), index, len))
+}
+
+/// `(vec-push! v x)` appends `x` to `v` and returns `v`
+fn vec_push_m(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let x = args.pop().unwrap();
+    let v = get_mutable_vector(args.pop().unwrap(), 1, name)?;
+
+    v.borrow_mut().push(x);
+    Ok(Ast::MutableVector(v))
+}
+
+/// `(vec-pop! v)` removes and returns the last element of `v`, or nil if it is empty
+fn vec_pop_m(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let v = get_mutable_vector(args.pop().unwrap(), 1, name)?;
+
+    let last = v.borrow_mut().pop();
+    Ok(last.unwrap_or(Ast::Nil))
+}
+
+/// `(vec-set! v i x)` replaces the element at index `i` and returns `v`
+fn vec_set_m(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 3, name)?;
+    let x = args.pop().unwrap();
+    let index = get_int(args.pop().unwrap(), 2, name)?;
+    let v = get_mutable_vector(args.pop().unwrap(), 1, name)?;
+
+    {
+        let mut xs = v.borrow_mut();
+        let i = vector_index(index, xs.len(), name)?;
+        xs[i] = x;
+    }
+    Ok(Ast::MutableVector(v))
+}
+
+/// `(vec-get v i)` returns the element at index `i`
+fn vec_get(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let index = get_int(args.pop().unwrap(), 2, name)?;
+    let v = get_mutable_vector(args.pop().unwrap(), 1, name)?;
+
+    let xs = v.borrow();
+    let i = vector_index(index, xs.len(), name)?;
+    Ok(xs[i].clone())
+}
+
+/// `(vec->list v)` returns the current elements of `v` as a list
+fn vec_to_list(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let v = get_mutable_vector(args.pop().unwrap(), 1, name)?;
+
+    let xs = v.borrow().clone();
+    Ok(Ast::List(Rc::new(xs)))
+}
+
+/* Numeric array */
+fn new_int_array(xs: Vec<i64>) -> Ast {
+    Ast::IntArray(Rc::new(RefCell::new(xs)))
+}
+
+/// `(int-array xs...)` returns an int array of the arguments
+fn int_array(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let xs = args
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| get_int(x, i as u32 + 1, name))
+        .collect::<Result<_, _>>()?;
+    Ok(new_int_array(xs))
+}
+
+/// `(make-int-array n fill)` returns an int array of `n` elements, all `fill` or 0 if omitted
+fn make_int_array(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 2, args.len()).into());
+    }
+    let fill = match args.len() {
+        2 => get_int(args.pop().unwrap(), 2, name)?,
+        _ => 0,
+    };
+    let n = get_int(args.pop().unwrap(), 1, name)?;
+    let len = usize::try_from(n).map_err(|_| {
+        RuntimeError::TypeMismatch(
+            name.to_owned(),
+            1,
+            "non-negative Integer".to_owned(),
+            Ast::Integer(n),
+        )
+    })?;
+
+    Ok(new_int_array(vec![fill; len]))
+}
+
+/// `(list->int-array xs)` converts a list of integers
+fn list_to_int_array(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let xs = get_list(args.pop().unwrap(), 1, name)?;
+
+    let xs = xs
+        .iter()
+        .map(|x| get_int(x.clone(), 1, name))
+        .collect::<Result<_, _>>()?;
+    Ok(new_int_array(xs))
+}
+
+fn int_array_to_list(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = get_int_array(args.pop().unwrap(), 1, name)?;
+
+    let xs = a.borrow().iter().map(|&n| Ast::Integer(n)).collect();
+    Ok(Ast::List(Rc::new(xs)))
+}
+
+fn new_float_array(xs: Vec<f64>) -> Ast {
+    Ast::FloatArray(Rc::new(RefCell::new(xs)))
+}
+
+/// `(float-array xs...)` returns a float array of the arguments, which may also be integers
+fn float_array(
+    name: &str,
+    args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    let xs = args
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| get_float(x, i as u32 + 1, name))
+        .collect::<Result<_, _>>()?;
+    Ok(new_float_array(xs))
+}
+
+/// `(make-float-array n fill)` returns a float array of `n` elements, all `fill` or 0.0 if
+/// omitted
+fn make_float_array(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(RuntimeError::WrongArity(name.to_owned(), 2, args.len()).into());
+    }
+    let fill = match args.len() {
+        2 => get_float(args.pop().unwrap(), 2, name)?,
+        _ => 0.0,
+    };
+    let n = get_int(args.pop().unwrap(), 1, name)?;
+    let len = usize::try_from(n).map_err(|_| {
+        RuntimeError::TypeMismatch(
+            name.to_owned(),
+            1,
+            "non-negative Integer".to_owned(),
+            Ast::Integer(n),
+        )
+    })?;
+
+    Ok(new_float_array(vec![fill; len]))
+}
+
+/// `(list->float-array xs)` converts a list of numbers
+fn list_to_float_array(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let xs = get_list(args.pop().unwrap(), 1, name)?;
+
+    let xs = xs
+        .iter()
+        .map(|x| get_float(x.clone(), 1, name))
+        .collect::<Result<_, _>>()?;
+    Ok(new_float_array(xs))
+}
+
+fn float_array_to_list(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = get_float_array(args.pop().unwrap(), 1, name)?;
+
+    let xs = a.borrow().iter().map(|&x| Ast::Float(x)).collect();
+    Ok(Ast::List(Rc::new(xs)))
+}
+
+/// `(aget a i)` returns the element at index `i` of an int or float array
+fn aget(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let index = get_int(args.pop().unwrap(), 2, name)?;
+
+    match args.pop().unwrap() {
+        Ast::IntArray(a) => {
+            let xs = a.borrow();
+            let i = vector_index(index, xs.len(), name)?;
+            Ok(Ast::Integer(xs[i]))
+        }
+        Ast::FloatArray(a) => {
+            let xs = a.borrow();
+            let i = vector_index(index, xs.len(), name)?;
+            Ok(Ast::Float(xs[i]))
+        }
+        other => Err(array_type_mismatch(other, 1, name)),
+    }
+}
+
+/// `(aset! a i x)` replaces the element at index `i` and returns `a`. Float arrays also take
+/// integers.
+fn aset_m(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 3, name)?;
+    let x = args.pop().unwrap();
+    let index = get_int(args.pop().unwrap(), 2, name)?;
+
+    match args.pop().unwrap() {
+        Ast::IntArray(a) => {
+            let n = get_int(x, 3, name)?;
+            {
+                let mut xs = a.borrow_mut();
+                let i = vector_index(index, xs.len(), name)?;
+                xs[i] = n;
+            }
+            Ok(Ast::IntArray(a))
+        }
+        Ast::FloatArray(a) => {
+            let x = get_float(x, 3, name)?;
+            {
+                let mut xs = a.borrow_mut();
+                let i = vector_index(index, xs.len(), name)?;
+                xs[i] = x;
+            }
+            Ok(Ast::FloatArray(a))
+        }
+        other => Err(array_type_mismatch(other, 1, name)),
+    }
+}
+
+/// `(asum a)` returns the sum of the elements, failing like `+` when an int array's sum overflows
+fn asum(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+
+    match args.pop().unwrap() {
+        Ast::IntArray(a) => {
+            let sum = a
+                .borrow()
+                .iter()
+                .try_fold(0i64, |sum, &n| sum.checked_add(n))
+                .ok_or_else(|| overflow(name))?;
+            Ok(Ast::Integer(sum))
+        }
+        Ast::FloatArray(a) => Ok(Ast::Float(a.borrow().iter().sum())),
+        other => Err(array_type_mismatch(other, 1, name)),
+    }
+}
+
+/// `(amap f a)` returns a new array of the same kind as `a`, of `f` applied to each element
+fn amap(name: &str, mut args: Vec<Ast>, env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let a = args.pop().unwrap();
+    let f = get_callable(args.pop().unwrap(), 1, name)?;
+
+    match a {
+        Ast::IntArray(a) => {
+            let xs = a.borrow().clone();
+            let mapped = xs
+                .into_iter()
+                .map(|n| {
+                    get_int(f.call(vec![Ast::Integer(n)], env)?, 1, name).map_err(ReplError::from)
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(new_int_array(mapped))
+        }
+        Ast::FloatArray(a) => {
+            let xs = a.borrow().clone();
+            let mapped = xs
+                .into_iter()
+                .map(|x| {
+                    get_float(f.call(vec![Ast::Float(x)], env)?, 1, name).map_err(ReplError::from)
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(new_float_array(mapped))
+        }
+        other => Err(array_type_mismatch(other, 2, name)),
+    }
+}
+
+/* Atom */
+fn atom(name: &str, mut args: Vec<Ast>, _env: &Rc<RefCell<Environment>>) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+
+    Ok(Ast::Atom(Rc::new(AtomCell::new(a))))
+}
+
+fn atom_q(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let a = args.pop().unwrap();
+
+    Ok(Ast::Boolean(matches!(a, Ast::Atom(_))))
+}
+
+fn deref(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 1, name)?;
+    let atom = get_atom(args.pop().unwrap(), 1, name)?;
+
+    let value = atom.borrow().to_owned();
+    Ok(value)
+}
+
+fn reset_m(
+    name: &str,
+    mut args: Vec<Ast>,
+    _env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let val = args.pop().unwrap();
+    let atom = get_atom(args.pop().unwrap(), 1, name)?;
+
+    *atom.borrow_mut() = val.clone();
+    Ok(val)
+}
+
+fn swap_m(
+    name: &str,
+    mut args: Vec<Ast>,
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Ast, ReplError> {
+    check_arity(&args, 2, name)?;
+    let fun = get_callable(args.pop().unwrap(), 2, name)?;
+    let atom = get_atom(args.pop().unwrap(), 1, name)?;
+
+    let atom_content = atom.borrow().clone();
+    let new_val = fun.call(vec![atom_content], env)?;
+    *atom.borrow_mut() = new_val;
+    Ok(Ast::Atom(atom))
+}
+
+/* Public */
+
+pub struct Environment {
+    pub values: HashMap<String, Ast>,
+    /// Names bound with `defconst`, which may not be redefined in this environment
+    pub constants: HashSet<String>,
+    /// Parameters or let bindings of this frame, in the order of `slot_names`
+    pub slots: Vec<Ast>,
+    pub slot_names: Rc<Vec<String>>,
+    pub parent: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Clone for Environment {
+    fn clone(&self) -> Self {
+        stats::environment_created();
+        Environment {
+            values: self.values.clone(),
+            constants: self.constants.clone(),
+            slots: self.slots.clone(),
+            slot_names: Rc::clone(&self.slot_names),
+            parent: self.parent.clone(),
+        }
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        stats::environment_dropped();
+    }
+}
+
+impl Environment {
+    /// Creates a frame whose bindings are addressed by slot. `slots` may be shorter than
+    /// `slot_names` while the bindings are still being evaluated.
+    pub fn frame(
+        slot_names: Rc<Vec<String>>,
+        slots: Vec<Ast>,
+        parent: &Rc<RefCell<Environment>>,
+    ) -> Environment {
+        stats::environment_created();
+        Environment {
+            values: HashMap::new(),
+            constants: HashSet::new(),
+            slots,
+            slot_names,
+            parent: Some(Rc::clone(parent)),
+        }
+    }
+
+    fn slot_of(&self, name: &str) -> Option<usize> {
+        self.slot_names[..self.slots.len()]
+            .iter()
+            .rposition(|n| n == name)
+    }
+
+    /// Looks up a name in this frame only
+    pub fn get_local(&self, name: &str) -> Option<&Ast> {
+        match self.slot_of(name) {
+            Some(slot) => Some(&self.slots[slot]),
+            None => self.values.get(name),
+        }
+    }
+
+    /// Binds a name in this frame, overwriting an existing slot of the same name
+    pub fn set_local(&mut self, name: String, value: Ast) {
+        match self.slot_of(&name) {
+            Some(slot) => self.slots[slot] = value,
+            None => {
+                self.values.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Optional groups of builtins that are added to the root environment. A group whose cargo
+/// feature is disabled isn't compiled in and is never added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinGroups {
+    /// `rand-int` and `format-number`
+    pub math: bool,
+    /// String and character functions beyond `str`
+    pub string: bool,
+    /// File and stdin access, `load-file` and file output ports
+    pub io: bool,
+    /// `time-ms`
+    pub os: bool,
+}
+
+impl BuiltinGroups {
+    /// Only the core builtins, which can't touch anything outside the interpreter except for
+    /// printing to `*out*` and `*err*`
+    pub fn core_only() -> BuiltinGroups {
+        BuiltinGroups {
+            math: false,
+            string: false,
+            io: false,
+            os: false,
+        }
+    }
+}
+
+impl Default for BuiltinGroups {
+    /// Every group that is compiled in
+    fn default() -> Self {
+        BuiltinGroups {
+            math: cfg!(feature = "math"),
+            string: cfg!(feature = "string"),
+            io: cfg!(feature = "io"),
+            os: cfg!(feature = "os"),
+        }
+    }
+}
+
+pub fn create_root_env() -> Environment {
+    create_root_env_with(BuiltinGroups::default())
+}
+
+/// Root environment with the core builtins and the given groups of further builtins
+#[cfg_attr(
+    not(any(feature = "math", feature = "string", feature = "io", feature = "os")),
+    allow(unused_variables)
+)]
+pub fn create_root_env_with(groups: BuiltinGroups) -> Environment {
+    let mut root_env_table = HashMap::new();
+    add_core_builtins(&mut root_env_table);
+    #[cfg(feature = "math")]
+    if groups.math {
+        add_math_builtins(&mut root_env_table);
+    }
+    #[cfg(feature = "string")]
+    if groups.string {
+        add_string_builtins(&mut root_env_table);
+    }
+    #[cfg(feature = "io")]
+    if groups.io {
+        add_io_builtins(&mut root_env_table);
+    }
+    #[cfg(feature = "os")]
+    if groups.os {
+        add_os_builtins(&mut root_env_table);
+    }
+
+    stats::environment_created();
+    Environment {
+        values: root_env_table,
+        constants: HashSet::new(),
+        slots: vec![],
+        slot_names: Rc::new(vec![]),
+        parent: None,
+    }
+}
+
+/// Evaluation, lists, comparisons, arithmetic, printing and data structures, which are always available
+fn add_core_builtins(root_env_table: &mut HashMap<String, Ast>) {
+    root_env_table.insert("+".to_owned(), Ast::Builtin("+".to_owned(), add));
+    root_env_table.insert("-".to_owned(), Ast::Builtin("-".to_owned(), sub));
+    root_env_table.insert("*".to_owned(), Ast::Builtin("*".to_owned(), mult));
+    root_env_table.insert("/".to_owned(), Ast::Builtin("/".to_owned(), div));
+    root_env_table.insert("prn".to_owned(), Ast::Builtin("prn".to_owned(), prn));
+    root_env_table.insert(
+        "apropos".to_owned(),
+        Ast::Builtin("apropos".to_owned(), apropos),
+    );
+    root_env_table.insert(
+        "source".to_owned(),
+        Ast::Builtin("source".to_owned(), source),
+    );
+    root_env_table.insert(
+        "disassemble".to_owned(),
+        Ast::Builtin("disassemble".to_owned(), disassemble),
+    );
+    root_env_table.insert(
+        "runtime-stats".to_owned(),
+        Ast::Builtin("runtime-stats".to_owned(), runtime_stats),
+    );
+    root_env_table.insert(
+        "pprint".to_owned(),
+        Ast::Builtin("pprint".to_owned(), pprint),
+    );
+    root_env_table.insert("*print-length*".to_owned(), Ast::Nil);
+    root_env_table.insert("*print-depth*".to_owned(), Ast::Nil);
+    root_env_table.insert(
+        "println".to_owned(),
+        Ast::Builtin("println".to_owned(), println),
+    );
+    root_env_table.insert("print".to_owned(), Ast::Builtin("print".to_owned(), print));
+    root_env_table.insert("eprn".to_owned(), Ast::Builtin("eprn".to_owned(), eprn));
+    root_env_table.insert(OUT.to_owned(), Ast::Port(Rc::new(OutputPort::Stdout)));
+    root_env_table.insert(ERR.to_owned(), Ast::Port(Rc::new(OutputPort::Stderr)));
+    root_env_table.insert(
+        "string-output".to_owned(),
+        Ast::Builtin("string-output".to_owned(), string_output),
+    );
+    root_env_table.insert(
+        "output-string".to_owned(),
+        Ast::Builtin("output-string".to_owned(), output_string),
+    );
+    root_env_table.insert(
+        "string-reader".to_owned(),
+        Ast::Builtin("string-reader".to_owned(), string_reader),
+    );
+    root_env_table.insert("throw".to_owned(), Ast::Builtin("throw".to_owned(), throw));
+    root_env_table.insert(
+        "macroexpand".to_owned(),
+        Ast::Builtin("macroexpand".to_owned(), macroexpand),
+    );
+    root_env_table.insert(
+        "fork-env".to_owned(),
+        Ast::Builtin("fork-env".to_owned(), fork_env),
+    );
+    root_env_table.insert(
+        "eval-in".to_owned(),
+        Ast::Builtin("eval-in".to_owned(), eval_in),
+    );
+    root_env_table.insert(
+        "read-form".to_owned(),
+        Ast::Builtin("read-form".to_owned(), read_form),
+    );
+    root_env_table.insert("take".to_owned(), Ast::Builtin("take".to_owned(), take));
+    root_env_table.insert("close".to_owned(), Ast::Builtin("close".to_owned(), close));
+    root_env_table.insert(
+        "write-port".to_owned(),
+        Ast::Builtin("write-port".to_owned(), write_port),
+    );
+    root_env_table.insert(
+        "eprintln".to_owned(),
+        Ast::Builtin("eprintln".to_owned(), eprintln),
+    );
+    root_env_table.insert("=".to_owned(), Ast::Builtin("=".to_owned(), op_eq));
+    root_env_table.insert(
+        "not=".to_owned(),
+        Ast::Builtin("not=".to_owned(), op_not_eq),
+    );
+    root_env_table.insert("<".to_owned(), Ast::Builtin("<".to_owned(), op_lt));
+    root_env_table.insert(">".to_owned(), Ast::Builtin(">".to_owned(), op_gt));
+    root_env_table.insert("<=".to_owned(), Ast::Builtin("<=".to_owned(), op_le));
+    root_env_table.insert(">=".to_owned(), Ast::Builtin(">=".to_owned(), op_ge));
+    root_env_table.insert("list".to_owned(), Ast::Builtin("list".to_owned(), list));
+    root_env_table.insert("list?".to_owned(), Ast::Builtin("list?".to_owned(), list_q));
+    root_env_table.insert(
+        "vector".to_owned(),
+        Ast::Builtin("vector".to_owned(), vector),
+    );
+    root_env_table.insert(
+        "vector?".to_owned(),
+        Ast::Builtin("vector?".to_owned(), vector_q),
+    );
+    root_env_table.insert("nth".to_owned(), Ast::Builtin("nth".to_owned(), nth));
+    root_env_table.insert("cons".to_owned(), Ast::Builtin("cons".to_owned(), cons));
+    root_env_table.insert("first".to_owned(), Ast::Builtin("first".to_owned(), first));
+    root_env_table.insert("rest".to_owned(), Ast::Builtin("rest".to_owned(), rest));
+    root_env_table.insert("last".to_owned(), Ast::Builtin("last".to_owned(), last));
+    root_env_table.insert("conj".to_owned(), Ast::Builtin("conj".to_owned(), conj));
+    root_env_table.insert(
+        "empty?".to_owned(),
+        Ast::Builtin("empty?".to_owned(), empty_q),
+    );
+    root_env_table.insert("count".to_owned(), Ast::Builtin("count".to_owned(), count));
+    root_env_table.insert("str".to_owned(), Ast::Builtin("str".to_owned(), concat_str));
+    root_env_table.insert("get".to_owned(), Ast::Builtin("get".to_owned(), get));
+    root_env_table.insert(
+        "hash-map".to_owned(),
+        Ast::Builtin("hash-map".to_owned(), hash_map),
+    );
+    root_env_table.insert("map?".to_owned(), Ast::Builtin("map?".to_owned(), map_q));
+    root_env_table.insert("assoc".to_owned(), Ast::Builtin("assoc".to_owned(), assoc));
+    root_env_table.insert(
+        "dissoc".to_owned(),
+        Ast::Builtin("dissoc".to_owned(), dissoc),
+    );
+    root_env_table.insert(
+        "contains?".to_owned(),
+        Ast::Builtin("contains?".to_owned(), contains_q),
+    );
+    root_env_table.insert("keys".to_owned(), Ast::Builtin("keys".to_owned(), keys));
+    root_env_table.insert("vals".to_owned(), Ast::Builtin("vals".to_owned(), vals));
+    root_env_table.insert(
+        "backtrace".to_owned(),
+        Ast::Builtin("backtrace".to_owned(), backtrace),
+    );
+    root_env_table.insert(
+        "stack-depth".to_owned(),
+        Ast::Builtin("stack-depth".to_owned(), stack_depth),
+    );
+    root_env_table.insert(
+        "identical?".to_owned(),
+        Ast::Builtin("identical?".to_owned(), identical_q),
+    );
+    root_env_table.insert(
+        "type-of".to_owned(),
+        Ast::Builtin("type-of".to_owned(), type_of),
+    );
+    root_env_table.insert(
+        "keyword".to_owned(),
+        Ast::Builtin("keyword".to_owned(), keyword),
+    );
+    root_env_table.insert(
+        "keyword?".to_owned(),
+        Ast::Builtin("keyword?".to_owned(), keyword_q),
+    );
+    root_env_table.insert("curry".to_owned(), Ast::Builtin("curry".to_owned(), curry));
+    root_env_table.insert("comp".to_owned(), Ast::Builtin("comp".to_owned(), comp));
+    root_env_table.insert("pipe".to_owned(), Ast::Builtin("pipe".to_owned(), pipe));
+    root_env_table.insert(
+        "identity".to_owned(),
+        Ast::Builtin("identity".to_owned(), identity),
+    );
+    root_env_table.insert(
+        "constantly".to_owned(),
+        Ast::Builtin("constantly".to_owned(), constantly),
+    );
+    root_env_table.insert(
+        "complement".to_owned(),
+        Ast::Builtin("complement".to_owned(), complement),
+    );
+    root_env_table.insert("juxt".to_owned(), Ast::Builtin("juxt".to_owned(), juxt));
+    root_env_table.insert("map".to_owned(), Ast::Builtin("map".to_owned(), map));
+    root_env_table.insert(
+        "filter".to_owned(),
+        Ast::Builtin("filter".to_owned(), filter),
+    );
+    root_env_table.insert(
+        "reduce".to_owned(),
+        Ast::Builtin("reduce".to_owned(), reduce),
+    );
+    root_env_table.insert(
+        "group-by".to_owned(),
+        Ast::Builtin("group-by".to_owned(), group_by),
+    );
+    root_env_table.insert(
+        "frequencies".to_owned(),
+        Ast::Builtin("frequencies".to_owned(), frequencies),
+    );
+    root_env_table.insert(
+        "distinct".to_owned(),
+        Ast::Builtin("distinct".to_owned(), distinct),
+    );
+    root_env_table.insert(
+        "interleave".to_owned(),
+        Ast::Builtin("interleave".to_owned(), interleave),
+    );
+    root_env_table.insert(
+        "interpose".to_owned(),
+        Ast::Builtin("interpose".to_owned(), interpose),
+    );
+    root_env_table.insert(
+        "zipmap".to_owned(),
+        Ast::Builtin("zipmap".to_owned(), zipmap),
+    );
+    root_env_table.insert(
+        "reduce-kv".to_owned(),
+        Ast::Builtin("reduce-kv".to_owned(), reduce_kv),
+    );
+    root_env_table.insert(
+        "map-vals".to_owned(),
+        Ast::Builtin("map-vals".to_owned(), map_vals),
+    );
+    root_env_table.insert(
+        "filter-keys".to_owned(),
+        Ast::Builtin("filter-keys".to_owned(), filter_keys),
+    );
+    root_env_table.insert(
+        "or-else".to_owned(),
+        Ast::Builtin("or-else".to_owned(), or_else),
+    );
+    root_env_table.insert(
+        "read-str".to_owned(),
+        Ast::Builtin("read-str".to_owned(), read_str),
+    );
+    root_env_table.insert(
+        "read-str-debug".to_owned(),
+        Ast::Builtin("read-str-debug".to_owned(), read_str_debug),
+    );
+    root_env_table.insert(
+        "read-all".to_owned(),
+        Ast::Builtin("read-all".to_owned(), read_all),
+    );
+    root_env_table.insert(
+        "eval-string".to_owned(),
+        Ast::Builtin("eval-string".to_owned(), eval_string),
+    );
+    root_env_table.insert(
+        "mutable-vector".to_owned(),
+        Ast::Builtin("mutable-vector".to_owned(), mutable_vector),
+    );
+    root_env_table.insert(
+        "mutable-vector?".to_owned(),
+        Ast::Builtin("mutable-vector?".to_owned(), mutable_vector_q),
+    );
+    root_env_table.insert(
+        "vec-push!".to_owned(),
+        Ast::Builtin("vec-push!".to_owned(), vec_push_m),
+    );
+    root_env_table.insert(
+        "vec-pop!".to_owned(),
+        Ast::Builtin("vec-pop!".to_owned(), vec_pop_m),
+    );
+    root_env_table.insert(
+        "vec-set!".to_owned(),
+        Ast::Builtin("vec-set!".to_owned(), vec_set_m),
+    );
+    root_env_table.insert(
+        "vec-get".to_owned(),
+        Ast::Builtin("vec-get".to_owned(), vec_get),
+    );
+    root_env_table.insert(
+        "vec->list".to_owned(),
+        Ast::Builtin("vec->list".to_owned(), vec_to_list),
+    );
+    root_env_table.insert(
+        "int-array".to_owned(),
+        Ast::Builtin("int-array".to_owned(), int_array),
+    );
+    root_env_table.insert(
+        "make-int-array".to_owned(),
+        Ast::Builtin("make-int-array".to_owned(), make_int_array),
+    );
+    root_env_table.insert(
+        "list->int-array".to_owned(),
+        Ast::Builtin("list->int-array".to_owned(), list_to_int_array),
+    );
+    root_env_table.insert(
+        "int-array->list".to_owned(),
+        Ast::Builtin("int-array->list".to_owned(), int_array_to_list),
+    );
+    root_env_table.insert(
+        "float-array".to_owned(),
+        Ast::Builtin("float-array".to_owned(), float_array),
+    );
+    root_env_table.insert(
+        "make-float-array".to_owned(),
+        Ast::Builtin("make-float-array".to_owned(), make_float_array),
+    );
+    root_env_table.insert(
+        "list->float-array".to_owned(),
+        Ast::Builtin("list->float-array".to_owned(), list_to_float_array),
+    );
+    root_env_table.insert(
+        "float-array->list".to_owned(),
+        Ast::Builtin("float-array->list".to_owned(), float_array_to_list),
+    );
+    root_env_table.insert("aget".to_owned(), Ast::Builtin("aget".to_owned(), aget));
+    root_env_table.insert("aset!".to_owned(), Ast::Builtin("aset!".to_owned(), aset_m));
+    root_env_table.insert("asum".to_owned(), Ast::Builtin("asum".to_owned(), asum));
+    root_env_table.insert("amap".to_owned(), Ast::Builtin("amap".to_owned(), amap));
+    root_env_table.insert("atom".to_owned(), Ast::Builtin("atom".to_owned(), atom));
+    root_env_table.insert("atom?".to_owned(), Ast::Builtin("atom?".to_owned(), atom_q));
+    root_env_table.insert("deref".to_owned(), Ast::Builtin("deref".to_owned(), deref));
+    root_env_table.insert(
+        "reset!".to_owned(),
+        Ast::Builtin("reset!".to_owned(), reset_m),
+    );
+    root_env_table.insert("swap!".to_owned(), Ast::Builtin("swap!".to_owned(), swap_m));
+}
+
+#[cfg(feature = "math")]
+/// Random numbers and number formatting
+fn add_math_builtins(root_env_table: &mut HashMap<String, Ast>) {
+    root_env_table.insert(
+        "rand-int".to_owned(),
+        Ast::Builtin("rand-int".to_owned(), rand_int),
+    );
+    root_env_table.insert(
+        "format-number".to_owned(),
+        Ast::Builtin("format-number".to_owned(), format_number),
+    );
+}
+
+#[cfg(feature = "string")]
+/// String and character functions beyond `str`
+fn add_string_builtins(root_env_table: &mut HashMap<String, Ast>) {
+    root_env_table.insert(
+        "string-builder".to_owned(),
+        Ast::Builtin("string-builder".to_owned(), string_builder),
+    );
+    root_env_table.insert(
+        "sb-append!".to_owned(),
+        Ast::Builtin("sb-append!".to_owned(), sb_append_m),
+    );
+    root_env_table.insert(
+        "sb-str".to_owned(),
+        Ast::Builtin("sb-str".to_owned(), sb_str),
+    );
+    root_env_table.insert(
+        "string-length".to_owned(),
+        Ast::Builtin("string-length".to_owned(), string_length),
+    );
+    root_env_table.insert(
+        "substring".to_owned(),
+        Ast::Builtin("substring".to_owned(), substring),
+    );
+    root_env_table.insert(
+        "string-reverse".to_owned(),
+        Ast::Builtin("string-reverse".to_owned(), string_reverse),
+    );
+    root_env_table.insert(
+        "upper-case".to_owned(),
+        Ast::Builtin("upper-case".to_owned(), upper_case),
+    );
+    root_env_table.insert(
+        "lower-case".to_owned(),
+        Ast::Builtin("lower-case".to_owned(), lower_case),
+    );
+    root_env_table.insert("chars".to_owned(), Ast::Builtin("chars".to_owned(), chars));
+    root_env_table.insert(
+        "char->int".to_owned(),
+        Ast::Builtin("char->int".to_owned(), char_to_int),
+    );
+    root_env_table.insert(
+        "int->char".to_owned(),
+        Ast::Builtin("int->char".to_owned(), int_to_char),
+    );
+    root_env_table.insert(
+        "digit?".to_owned(),
+        Ast::Builtin("digit?".to_owned(), digit_q),
+    );
+    root_env_table.insert(
+        "alpha?".to_owned(),
+        Ast::Builtin("alpha?".to_owned(), alpha_q),
+    );
+    root_env_table.insert(
+        "whitespace?".to_owned(),
+        Ast::Builtin("whitespace?".to_owned(), whitespace_q),
+    );
+    root_env_table.insert(
+        "upper-char".to_owned(),
+        Ast::Builtin("upper-char".to_owned(), upper_char),
+    );
+    root_env_table.insert(
+        "lower-char".to_owned(),
+        Ast::Builtin("lower-char".to_owned(), lower_char),
+    );
+}
+
+#[cfg(feature = "io")]
+/// Files, standard input, loading code and file output ports
+fn add_io_builtins(root_env_table: &mut HashMap<String, Ast>) {
+    root_env_table.insert(
+        "write-ast".to_owned(),
+        Ast::Builtin("write-ast".to_owned(), write_ast),
+    );
+    root_env_table.insert(
+        "read-ast".to_owned(),
+        Ast::Builtin("read-ast".to_owned(), read_ast),
+    );
+    root_env_table.insert(
+        "open-output".to_owned(),
+        Ast::Builtin("open-output".to_owned(), open_output),
+    );
+    root_env_table.insert(
+        "file-reader".to_owned(),
+        Ast::Builtin("file-reader".to_owned(), file_reader),
+    );
+    root_env_table.insert(
+        "line-seq".to_owned(),
+        Ast::Builtin("line-seq".to_owned(), line_seq),
+    );
+    root_env_table.insert(
+        "read-stdin".to_owned(),
+        Ast::Builtin("read-stdin".to_owned(), read_stdin),
+    );
+    root_env_table.insert(
+        "stdin-lines".to_owned(),
+        Ast::Builtin("stdin-lines".to_owned(), stdin_lines),
+    );
+    root_env_table.insert("slurp".to_owned(), Ast::Builtin("slurp".to_owned(), slurp));
+    root_env_table.insert("spit".to_owned(), Ast::Builtin("spit".to_owned(), spit));
+    root_env_table.insert(
+        "slurp-bytes".to_owned(),
+        Ast::Builtin("slurp-bytes".to_owned(), slurp_bytes),
+    );
+    root_env_table.insert(
+        "load-file".to_owned(),
+        Ast::Builtin("load-file".to_owned(), load_file),
+    );
+    root_env_table.insert(
+        "reload".to_owned(),
+        Ast::Builtin("reload".to_owned(), reload),
+    );
+}
+
+#[cfg(feature = "os")]
+/// The system clock
+fn add_os_builtins(root_env_table: &mut HashMap<String, Ast>) {
+    root_env_table.insert(
+        "time-ms".to_owned(),
+        Ast::Builtin("time-ms".to_owned(), time_ms),
+    );
 }
@@ -0,0 +1,102 @@
+use crate::errors::ReplError;
+use crate::parser::Ast;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::rc::Rc;
+
+type Items = dyn Iterator<Item = Result<Ast, ReplError>>;
+
+/// Sequence whose elements are produced one at a time as they are consumed, e.g. the lines of
+/// a file. Each element can only be consumed once.
+pub struct LazySeq {
+    /// Where the elements come from, e.g. `lines of a.log`
+    pub origin: String,
+    items: RefCell<Box<Items>>,
+}
+
+impl LazySeq {
+    pub fn new(
+        origin: String,
+        items: impl Iterator<Item = Result<Ast, ReplError>> + 'static,
+    ) -> LazySeq {
+        LazySeq {
+            origin,
+            items: RefCell::new(Box::new(items)),
+        }
+    }
+
+    /// Lines of a reader without their line endings, read as they are consumed
+    pub fn lines(origin: String, reader: impl BufRead + 'static) -> LazySeq {
+        let lines = reader
+            .lines()
+            .map(|line| line.map(Ast::String).map_err(ReplError::from));
+        LazySeq::new(origin, lines)
+    }
+
+    pub fn file_lines(path: &str) -> io::Result<LazySeq> {
+        let file = File::open(path)?;
+        Ok(LazySeq::lines(
+            format!("lines of {}", path),
+            BufReader::new(file),
+        ))
+    }
+
+    /// Lines of standard input, read as they are consumed. Stdin isn't locked in between, so
+    /// the REPL can keep reading from it as well.
+    pub fn stdin_lines() -> LazySeq {
+        let lines = std::iter::from_fn(|| {
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => {
+                    let len = line.trim_end_matches(['\r', '\n']).len();
+                    line.truncate(len);
+                    Some(Ok(Ast::String(line)))
+                }
+                Err(err) => Some(Err(ReplError::from(err))),
+            }
+        });
+        LazySeq::new("lines of stdin".to_owned(), lines)
+    }
+
+    /// Consumes the next element, or returns `None` if there are no more
+    pub fn next(&self) -> Result<Option<Ast>, ReplError> {
+        self.items.borrow_mut().next().transpose()
+    }
+}
+
+/// Calls `f` with each element of a list or vector, or with each remaining element of a lazy sequence
+/// until `f` returns false
+pub fn for_each(
+    coll: &Ast,
+    mut f: impl FnMut(Ast) -> Result<bool, ReplError>,
+) -> Result<(), ReplError> {
+    match coll {
+        Ast::List(xs) | Ast::Vector(xs) => {
+            for x in xs.iter() {
+                if !f(x.clone())? {
+                    break;
+                }
+            }
+        }
+        Ast::Seq(seq) => {
+            while let Some(x) = seq.next()? {
+                if !f(x)? {
+                    break;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn is_iterable(ast: &Ast) -> bool {
+    matches!(ast, Ast::List(_) | Ast::Vector(_) | Ast::Seq(_) | Ast::Nil)
+}
+
+pub fn new_seq(seq: LazySeq) -> Ast {
+    Ast::Seq(Rc::new(seq))
+}
@@ -0,0 +1,264 @@
+use crate::coverage;
+use crate::errors::ReplError;
+use crate::hmac::sha256;
+use crate::parser::{max_nesting_depth, read_all, Ast, MapKey};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{fs, io};
+
+/// Start of every serialized form, the last byte is the format version
+const MAGIC: &[u8; 4] = b"BEE\x01";
+/// Start of compiled `.bstc` files, the last byte is the format version. It is followed by the
+/// length and SHA-256 of the source the file was compiled from.
+const PROGRAM_MAGIC: &[u8; 4] = b"BSC\x02";
+const SOURCE_ID_LEN: usize = 8 + 32;
+pub const COMPILED_EXTENSION: &str = "bstc";
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_SYMBOL: u8 = 5;
+const TAG_LIST: u8 = 6;
+const TAG_FLOAT: u8 = 7;
+const TAG_KEYWORD: u8 = 8;
+const TAG_VECTOR: u8 = 9;
+const TAG_MAP: u8 = 10;
+
+/// Serializes a form into a compact binary representation. Only data can be serialized, for
+/// functions, builtins and atoms the offending value is returned as error.
+pub fn encode(ast: &Ast) -> Result<Vec<u8>, Ast> {
+    let mut out = MAGIC.to_vec();
+    write(ast, &mut out)?;
+    Ok(out)
+}
+
+/// Serializes the forms of a program, parsed from `source`, into the contents of a `.bstc` file
+pub fn encode_program(source: &str, forms: &[Ast]) -> Result<Vec<u8>, Ast> {
+    let mut out = program_header(source);
+    write(&Ast::List(Rc::new(forms.to_vec())), &mut out)?;
+    Ok(out)
+}
+
+/// Magic and source identification that start a `.bstc` file compiled from `source`
+fn program_header(source: &str) -> Vec<u8> {
+    let mut header = PROGRAM_MAGIC.to_vec();
+    header.extend_from_slice(&(source.len() as u64).to_le_bytes());
+    header.extend_from_slice(&sha256(source.as_bytes()));
+    header
+}
+
+fn write(ast: &Ast, out: &mut Vec<u8>) -> Result<(), Ast> {
+    match ast {
+        Ast::Nil => out.push(TAG_NIL),
+        Ast::Boolean(false) => out.push(TAG_FALSE),
+        Ast::Boolean(true) => out.push(TAG_TRUE),
+        Ast::Integer(n) => {
+            out.push(TAG_INTEGER);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Ast::Float(x) => {
+            out.push(TAG_FLOAT);
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        Ast::String(s) => write_str(TAG_STRING, s, out),
+        Ast::Symbol(s) | Ast::Local(_, _, s) => write_str(TAG_SYMBOL, s, out),
+        Ast::Keyword(k) => write_str(TAG_KEYWORD, k, out),
+        Ast::List(xs) | Ast::Vector(xs) => {
+            out.push(if let Ast::List(_) = ast {
+                TAG_LIST
+            } else {
+                TAG_VECTOR
+            });
+            out.extend_from_slice(&(xs.len() as u32).to_le_bytes());
+            for x in xs.iter() {
+                write(x, out)?;
+            }
+        }
+        Ast::HashMap(map) => {
+            out.push(TAG_MAP);
+            out.extend_from_slice(&(map.len() as u32).to_le_bytes());
+            for (key, value) in map.iter() {
+                write(&key.to_ast(), out)?;
+                write(value, out)?;
+            }
+        }
+        Ast::Function(_)
+        | Ast::Builtin(..)
+        | Ast::Closure(_)
+        | Ast::Atom(_)
+        | Ast::MutableVector(_)
+        | Ast::IntArray(_)
+        | Ast::FloatArray(_)
+        | Ast::StringBuilder(_)
+        | Ast::Port(_)
+        | Ast::Seq(_)
+        | Ast::Reader(_)
+        | Ast::Env(_) => return Err(ast.clone()),
+    }
+
+    Ok(())
+}
+
+fn write_str(tag: u8, s: &str, out: &mut Vec<u8>) {
+    out.push(tag);
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Reads a form written by `encode`
+pub fn decode(bytes: &[u8]) -> io::Result<Ast> {
+    decode_with_magic(bytes, MAGIC, 0, "not a serialized beesting form")
+}
+
+/// Reads the forms of a `.bstc` file
+pub fn decode_program(bytes: &[u8]) -> io::Result<Vec<Ast>> {
+    let error = "not a compiled beesting file";
+    match decode_with_magic(bytes, PROGRAM_MAGIC, SOURCE_ID_LEN, error)? {
+        Ast::List(forms) => Ok(Rc::unwrap_or_clone(forms)),
+        _ => Err(invalid("compiled file does not contain a program")),
+    }
+}
+
+/// Decodes the form following `magic` and a header of `header_len` bytes
+fn decode_with_magic(
+    bytes: &[u8],
+    magic: &[u8],
+    header_len: usize,
+    error: &str,
+) -> io::Result<Ast> {
+    let mut reader = Reader { bytes, offset: 0 };
+    if reader.take(magic.len())? != magic {
+        return Err(invalid(error));
+    }
+    reader.take(header_len)?;
+
+    let ast = reader.read(0)?;
+    if reader.offset != bytes.len() {
+        return Err(invalid("trailing bytes after form"));
+    }
+    Ok(ast)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let end = self.offset + n;
+        if end > self.bytes.len() {
+            return Err(invalid("unexpected end of data"));
+        }
+
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> io::Result<usize> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn read_string(&mut self) -> io::Result<String> {
+        let len = self.read_u32()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("string is not UTF-8"))
+    }
+
+    /// Reads a form nested `depth` levels deep, failing past the parser's nesting limit so
+    /// malformed data can't overflow the stack
+    fn read(&mut self, depth: usize) -> io::Result<Ast> {
+        if depth >= max_nesting_depth() {
+            return Err(invalid("form is nested too deeply"));
+        }
+
+        let tag = self.take(1)?[0];
+        let ast = match tag {
+            TAG_NIL => Ast::Nil,
+            TAG_FALSE => Ast::Boolean(false),
+            TAG_TRUE => Ast::Boolean(true),
+            TAG_INTEGER => Ast::Integer(i64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            TAG_FLOAT => Ast::Float(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            TAG_STRING => Ast::String(self.read_string()?),
+            TAG_SYMBOL => Ast::Symbol(self.read_string()?),
+            TAG_KEYWORD => Ast::Keyword(self.read_string()?),
+            TAG_LIST | TAG_VECTOR => {
+                let len = self.read_u32()?;
+                let xs = (0..len)
+                    .map(|_| self.read(depth + 1))
+                    .collect::<io::Result<_>>()?;
+                if tag == TAG_LIST {
+                    Ast::List(Rc::new(xs))
+                } else {
+                    Ast::Vector(Rc::new(xs))
+                }
+            }
+            TAG_MAP => {
+                let len = self.read_u32()?;
+                let mut map = BTreeMap::new();
+                for _ in 0..len {
+                    let key = MapKey::from_ast(&self.read(depth + 1)?)
+                        .ok_or_else(|| invalid("map key is not a string, keyword or integer"))?;
+                    map.insert(key, self.read(depth + 1)?);
+                }
+                Ast::HashMap(Rc::new(map))
+            }
+            _ => return Err(invalid("unknown tag")),
+        };
+        Ok(ast)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn compiled_path(file_name: &str) -> PathBuf {
+    Path::new(file_name).with_extension(COMPILED_EXTENSION)
+}
+
+/// Parses a source file and writes its forms next to it as `.bstc` file, returning its path
+pub fn compile_file(file_name: &str) -> Result<PathBuf, ReplError> {
+    let code = fs::read_to_string(file_name)?;
+    let forms = read_all(&code).map_err(|err| ReplError::from(err).with_source(&code))?;
+    let bytes = encode_program(&code, &forms).expect("parsed forms only contain data");
+
+    let path = compiled_path(file_name);
+    fs::write(&path, bytes)?;
+    Ok(path)
+}
+
+/// Reads the forms of a program. `.bstc` files are decoded; for source files a compiled version
+/// is used instead of parsing when it was compiled from exactly the current source, unless
+/// coverage is being recorded.
+pub fn read_program(file_name: &str) -> Result<Vec<Ast>, ReplError> {
+    let path = Path::new(file_name);
+    if path.extension().is_some_and(|e| e == COMPILED_EXTENSION) {
+        return Ok(decode_program(&fs::read(path)?)?);
+    }
+
+    let code = if coverage::is_enabled() {
+        fs::read_to_string(file_name)?
+    } else {
+        return read_source_or_compiled(file_name);
+    };
+    coverage::read_file(file_name, &code).map_err(|err| ReplError::from(err).with_source(&code))
+}
+
+/// Reads a source file, or its compiled version if it was compiled from the same source
+fn read_source_or_compiled(file_name: &str) -> Result<Vec<Ast>, ReplError> {
+    let code = fs::read_to_string(file_name)?;
+    if let Ok(bytes) = fs::read(compiled_path(file_name)) {
+        if bytes.starts_with(&program_header(&code)) {
+            if let Ok(forms) = decode_program(&bytes) {
+                return Ok(forms);
+            }
+        }
+    }
+
+    read_all(&code).map_err(|err| ReplError::from(err).with_source(&code))
+}
@@ -0,0 +1,115 @@
+use crate::parser::Ast;
+use crate::root_env::Environment;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+thread_local! {
+    static LIVE_ENVIRONMENTS: Cell<usize> = const { Cell::new(0) };
+    static LIVE_ATOMS: Cell<usize> = const { Cell::new(0) };
+    static SYMBOLS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+pub fn environment_created() {
+    LIVE_ENVIRONMENTS.with(|n| n.set(n.get() + 1));
+}
+
+pub fn environment_dropped() {
+    LIVE_ENVIRONMENTS.with(|n| n.set(n.get() - 1));
+}
+
+pub fn atom_created() {
+    LIVE_ATOMS.with(|n| n.set(n.get() + 1));
+}
+
+pub fn atom_dropped() {
+    LIVE_ATOMS.with(|n| n.set(n.get() - 1));
+}
+
+/// Records a symbol name read by the parser
+pub fn symbol_read(name: &str) {
+    SYMBOLS.with(|symbols| {
+        if !symbols.borrow().contains(name) {
+            symbols.borrow_mut().insert(name.to_owned());
+        }
+    });
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeStats {
+    /// Environments that have been created and not dropped yet, including leaked ones
+    pub live_environments: usize,
+    /// Atoms that have been created and not dropped yet
+    pub live_atoms: usize,
+    /// Distinct symbol names the parser has seen
+    pub symbols: usize,
+    /// Number of list, vector and map elements reachable from the environment the stats were
+    /// taken in. Unlike the other counts this walks the values, so unreachable leaked ones are
+    /// missing.
+    pub reachable_list_cells: usize,
+}
+
+pub fn runtime_stats(env: &Rc<RefCell<Environment>>) -> RuntimeStats {
+    let mut counter = CellCounter {
+        seen: HashSet::new(),
+        cells: 0,
+    };
+    counter.count_env(env);
+
+    RuntimeStats {
+        live_environments: LIVE_ENVIRONMENTS.with(Cell::get),
+        live_atoms: LIVE_ATOMS.with(Cell::get),
+        symbols: SYMBOLS.with(|symbols| symbols.borrow().len()),
+        reachable_list_cells: counter.cells,
+    }
+}
+
+/// Walks values and closure environments, visiting every shared allocation once
+struct CellCounter {
+    seen: HashSet<*const ()>,
+    cells: usize,
+}
+
+impl CellCounter {
+    fn first_visit<T>(&mut self, rc: &Rc<T>) -> bool {
+        self.seen.insert(Rc::as_ptr(rc) as *const ())
+    }
+
+    fn count_env(&mut self, env: &Rc<RefCell<Environment>>) {
+        if !self.first_visit(env) {
+            return;
+        }
+
+        let env = env.borrow();
+        for value in env.values.values().chain(env.slots.iter()) {
+            self.count(value);
+        }
+        if let Some(parent) = &env.parent {
+            self.count_env(parent);
+        }
+    }
+
+    fn count(&mut self, ast: &Ast) {
+        match ast {
+            Ast::List(xs) | Ast::Vector(xs) if self.first_visit(xs) => {
+                self.cells += xs.len();
+                xs.iter().for_each(|x| self.count(x));
+            }
+            Ast::Function(f) if self.first_visit(f) => {
+                self.count(&f.body);
+                self.count_env(&f.env);
+            }
+            Ast::HashMap(map) if self.first_visit(map) => {
+                self.cells += map.len();
+                map.values().for_each(|x| self.count(x));
+            }
+            Ast::Atom(a) if self.first_visit(a) => self.count(&a.borrow()),
+            Ast::MutableVector(v) if self.first_visit(v) => {
+                let xs = v.borrow();
+                self.cells += xs.len();
+                xs.iter().for_each(|x| self.count(x));
+            }
+            _ => {}
+        }
+    }
+}
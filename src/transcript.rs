@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// Shown before REPL input, transcripts mark input lines with it as well
+pub const PROMPT: &str = "🐝> ";
+/// Shown before the lines continuing an open string or list
+pub const CONTINUATION_PROMPT: &str = " .. ";
+
+/// Input and what the REPL printed for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub input: String,
+    pub output: String,
+}
+
+/// Log of a REPL session that reads like the session itself:
+///
+/// ```text
+/// 🐝> (def! xs (list 1
+///  ..   2))
+/// (1 2)
+/// 🐝> (println (count xs))
+/// 2
+/// nil
+/// ```
+pub struct Transcript {
+    file: File,
+}
+
+impl Transcript {
+    pub fn create(path: &Path) -> io::Result<Transcript> {
+        Ok(Transcript {
+            file: File::create(path)?,
+        })
+    }
+
+    /// Appends an entry, written out right away so the transcript survives a crash
+    pub fn record(&mut self, input: &str, output: &str) -> io::Result<()> {
+        let mut text = String::new();
+        for (i, line) in input.trim_end_matches(['\r', '\n']).lines().enumerate() {
+            let prompt = if i == 0 { PROMPT } else { CONTINUATION_PROMPT };
+            text.push_str(prompt);
+            text.push_str(line);
+            text.push('\n');
+        }
+        text.push_str(output.trim_end_matches('\n'));
+        text.push('\n');
+        self.file.write_all(text.as_bytes())
+    }
+}
+
+/// Splits a transcript into its entries. Text before the first prompt is ignored.
+pub fn parse_transcript(text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = vec![];
+    let mut reading_input = false;
+
+    for line in text.lines() {
+        if let Some(input) = line.strip_prefix(PROMPT) {
+            entries.push(Entry {
+                input: input.to_owned(),
+                output: String::new(),
+            });
+            reading_input = true;
+            continue;
+        }
+
+        let Some(entry) = entries.last_mut() else {
+            continue;
+        };
+        match line.strip_prefix(CONTINUATION_PROMPT) {
+            Some(input) if reading_input => {
+                entry.input.push('\n');
+                entry.input.push_str(input);
+            }
+            _ => {
+                reading_input = false;
+                if !entry.output.is_empty() {
+                    entry.output.push('\n');
+                }
+                entry.output.push_str(line);
+            }
+        }
+    }
+
+    entries
+}
@@ -0,0 +1,197 @@
+//! The parts of ZeroMQ's wire protocol (ZMTP 3.0 with the NULL mechanism) that a Jupyter
+//! kernel needs: bound ROUTER, PUB and REP sockets over TCP. Every accepted peer gets its own
+//! thread; replies go straight back over the peer's connection, which is what a ROUTER does
+//! once it has looked up the peer by its routing id.
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Frames of a multipart message
+pub type Message = Vec<Vec<u8>>;
+
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+/// Largest frame accepted from a peer, anything bigger closes the connection before the
+/// frame is buffered
+const MAX_FRAME_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SocketType {
+    Router,
+    Pub,
+    Rep,
+}
+
+impl SocketType {
+    fn name(self) -> &'static str {
+        match self {
+            SocketType::Router => "ROUTER",
+            SocketType::Pub => "PUB",
+            SocketType::Rep => "REP",
+        }
+    }
+}
+
+/// Sending half of a connection to a peer, shareable between threads
+pub struct Connection {
+    stream: Mutex<TcpStream>,
+}
+
+impl Connection {
+    pub fn send(&self, message: &[Vec<u8>]) -> io::Result<()> {
+        let mut stream = self.stream.lock().unwrap_or_else(|err| err.into_inner());
+        let mut buffer = vec![];
+        for (i, frame) in message.iter().enumerate() {
+            let more = if i + 1 < message.len() { MORE } else { 0 };
+            write_frame(&mut buffer, more, frame);
+        }
+        stream.write_all(&buffer)?;
+        stream.flush()
+    }
+}
+
+/// Reading half of a connection to a peer
+pub struct Receiver {
+    stream: TcpStream,
+}
+
+impl Receiver {
+    /// Reads the next message, skipping commands
+    pub fn recv(&mut self) -> io::Result<Message> {
+        let mut message = vec![];
+        loop {
+            let (flags, body) = read_frame(&mut self.stream)?;
+            if flags & COMMAND != 0 {
+                continue;
+            }
+            message.push(body);
+            if flags & MORE == 0 {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+/// Listens on `ip:port` and, for every peer that completes the handshake, calls `on_connect`
+/// on a thread of its own
+pub fn bind(
+    ip: &str,
+    port: u16,
+    socket_type: SocketType,
+    on_connect: impl Fn(Arc<Connection>, Receiver) + Send + Sync + 'static,
+) -> io::Result<()> {
+    let listener = TcpListener::bind((ip, port))?;
+    let on_connect = Arc::new(on_connect);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let on_connect = on_connect.clone();
+            thread::spawn(move || {
+                let mut stream = stream;
+                if handshake(&mut stream, socket_type).is_err() {
+                    return;
+                }
+                let Ok(reader) = stream.try_clone() else {
+                    return;
+                };
+                let connection = Arc::new(Connection {
+                    stream: Mutex::new(stream),
+                });
+                on_connect(connection, Receiver { stream: reader });
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Exchanges greetings and READY commands
+fn handshake(stream: &mut TcpStream, socket_type: SocketType) -> io::Result<()> {
+    let mut greeting = vec![0xFF, 0, 0, 0, 0, 0, 0, 0, 0, 0x7F, 3, 0];
+    let mut mechanism = b"NULL".to_vec();
+    mechanism.resize(20, 0);
+    greeting.extend(mechanism);
+    // as-server and filler
+    greeting.resize(64, 0);
+    stream.write_all(&greeting)?;
+
+    let mut peer_greeting = [0u8; 64];
+    stream.read_exact(&mut peer_greeting)?;
+    if peer_greeting[0] != 0xFF || peer_greeting[9] != 0x7F || peer_greeting[10] < 3 {
+        return Err(io::Error::other("Peer doesn't speak ZMTP 3"));
+    }
+    if !peer_greeting[12..32].starts_with(b"NULL\0") {
+        return Err(io::Error::other(
+            "Peer wants a security mechanism other than NULL",
+        ));
+    }
+
+    let mut ready = command_name("READY");
+    ready.extend(property("Socket-Type", socket_type.name().as_bytes()));
+    let mut buffer = vec![];
+    write_frame(&mut buffer, COMMAND, &ready);
+    stream.write_all(&buffer)?;
+
+    loop {
+        let (flags, body) = read_frame(stream)?;
+        if flags & COMMAND == 0 {
+            return Err(io::Error::other("Expected READY from peer"));
+        }
+        if body.starts_with(&command_name("READY")) {
+            return Ok(());
+        }
+        if body.starts_with(&command_name("ERROR")) {
+            return Err(io::Error::other("Peer rejected the handshake"));
+        }
+    }
+}
+
+fn command_name(name: &str) -> Vec<u8> {
+    let mut body = vec![name.len() as u8];
+    body.extend(name.as_bytes());
+    body
+}
+
+fn property(name: &str, value: &[u8]) -> Vec<u8> {
+    let mut bytes = command_name(name);
+    bytes.extend((value.len() as u32).to_be_bytes());
+    bytes.extend(value);
+    bytes
+}
+
+fn write_frame(buffer: &mut Vec<u8>, flags: u8, body: &[u8]) {
+    if body.len() > u8::MAX as usize {
+        buffer.push(flags | LONG);
+        buffer.extend((body.len() as u64).to_be_bytes());
+    } else {
+        buffer.push(flags);
+        buffer.push(body.len() as u8);
+    }
+    buffer.extend(body);
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    stream.read_exact(&mut flags)?;
+    let size = if flags[0] & LONG != 0 {
+        let mut size = [0u8; 8];
+        stream.read_exact(&mut size)?;
+        let size = u64::from_be_bytes(size);
+        if size > MAX_FRAME_SIZE {
+            return Err(io::Error::other(format!(
+                "Peer sent a frame of {} bytes, the limit is {}",
+                size, MAX_FRAME_SIZE
+            )));
+        }
+        size as usize
+    } else {
+        let mut size = [0u8; 1];
+        stream.read_exact(&mut size)?;
+        size[0] as usize
+    };
+    let mut body = vec![0u8; size];
+    stream.read_exact(&mut body)?;
+    Ok((flags[0], body))
+}